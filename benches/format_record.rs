@@ -0,0 +1,146 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Measure how long formatting records takes.
+//!
+//! Run with `cargo bench --bench format_record`.  This benchmark does not need
+//! journald.
+//!
+//! Also compare both encodings of field values of the native protocol at
+//! different sizes, to check the size from which the logger always
+//! length-encodes values instead of scanning them for newlines.
+
+#![deny(warnings, clippy::all)]
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use log::kv::Value;
+use log::{Level, Record};
+use systemd_journal_logger::JournalLog;
+
+const ITERATIONS: u32 = 100_000;
+
+/// Format the record `with_record` passes to its callback `ITERATIONS` times.
+fn bench<F: Fn(&mut dyn FnMut(&Record))>(name: &str, logger: &JournalLog, with_record: F) {
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        with_record(&mut |record| {
+            black_box(logger.format_record(black_box(record)));
+        });
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "{:<24} {:>10?} per record",
+        name,
+        Duration::from_nanos((elapsed.as_nanos() / u128::from(ITERATIONS)) as u64)
+    );
+}
+
+/// Write `value` as `NAME=value` if it has no newline, like the logger does
+/// for small values.
+fn put_field_scanned(buffer: &mut Vec<u8>, value: &[u8]) {
+    if value.contains(&b'\n') {
+        put_field_length_encoded(buffer, value);
+    } else {
+        buffer.extend_from_slice(b"NAME=");
+        buffer.extend_from_slice(value);
+        buffer.push(b'\n');
+    }
+}
+
+/// Write `value` length-encoded, like the logger does for large values.
+fn put_field_length_encoded(buffer: &mut Vec<u8>, value: &[u8]) {
+    buffer.extend_from_slice(b"NAME\n");
+    buffer.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    buffer.extend_from_slice(value);
+    buffer.push(b'\n');
+}
+
+/// Encode a field with `value` with `encode` `ITERATIONS` times.
+fn bench_encoding(name: &str, value: &[u8], encode: fn(&mut Vec<u8>, &[u8])) {
+    let mut buffer = Vec::with_capacity(value.len() + 32);
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        buffer.clear();
+        encode(&mut buffer, black_box(value));
+        black_box(&buffer);
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "{:<24} {:>10?} per field",
+        format!("{} {} B", name, value.len()),
+        Duration::from_nanos((elapsed.as_nanos() / u128::from(ITERATIONS)) as u64)
+    );
+}
+
+fn main() {
+    let logger = JournalLog::empty()
+        .unwrap()
+        .with_syslog_identifier("bench".to_string())
+        .with_extra_fields(vec![("VERSION", env!("CARGO_PKG_VERSION"))]);
+
+    bench("small static message", &logger, |f| {
+        f(&Record::builder()
+            .level(Level::Info)
+            .target("bench")
+            .args(format_args!("Hello world"))
+            .build())
+    });
+
+    bench("small with location", &logger, |f| {
+        f(&Record::builder()
+            .level(Level::Info)
+            .target("bench")
+            .file(Some(file!()))
+            .line(Some(line!()))
+            .args(format_args!("Hello world"))
+            .build())
+    });
+
+    bench("small formatted message", &logger, |f| {
+        f(&Record::builder()
+            .level(Level::Info)
+            .target("bench")
+            .args(format_args!("Hello {}", "world"))
+            .build())
+    });
+
+    let large = "x".repeat(64 * 1024);
+    bench("large message", &logger, |f| {
+        f(&Record::builder()
+            .level(Level::Info)
+            .target("bench")
+            .args(format_args!("{}", large))
+            .build())
+    });
+
+    let kvs: &[(&str, Value)] = &[
+        ("request_id", Value::from(42)),
+        ("user", Value::from("jdoe")),
+        ("path", Value::from("/api/v1/items")),
+        ("elapsed_ms", Value::from(12.5)),
+    ];
+    bench("key-values", &logger, |f| {
+        f(&Record::builder()
+            .level(Level::Info)
+            .target("bench")
+            .file(Some(file!()))
+            .line(Some(line!()))
+            .module_path(Some(module_path!()))
+            .args(format_args!("Handled request"))
+            .key_values(&kvs)
+            .build())
+    });
+
+    for size in [64, 256, 4 * 1024, 64 * 1024] {
+        let value = "x".repeat(size);
+        bench_encoding("newline scan", value.as_bytes(), put_field_scanned);
+        bench_encoding("length-encoded", value.as_bytes(), put_field_length_encoded);
+    }
+}
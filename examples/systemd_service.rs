@@ -25,7 +25,7 @@
 
 use log::{info, LevelFilter, Log};
 use std::io::prelude::*;
-use systemd_journal_logger::{connected_to_journal, JournalLog};
+use systemd_journal_logger::{connected_to_journal, JournalLog, StreamBackend};
 
 struct SimpleLogger;
 
@@ -48,8 +48,15 @@ fn main() {
         // If the output streams of this process are directly connected to the
         // systemd journal log directly to the journal to preserve structured
         // log entries (e.g. proper multiline messages, metadata fields, etc.)
-        JournalLog::new()
-            .unwrap()
+        let logger = JournalLog::new().unwrap_or_else(|_| {
+            // If journald's socket isn't available, e.g. in a container, write
+            // to standard error with a priority prefix, so that the journal
+            // still gets the severity of each message right.
+            JournalLog::new_lenient()
+                .unwrap()
+                .with_stream_backend(StreamBackend::stderr().unwrap())
+        });
+        logger
             .with_extra_fields(vec![("VERSION", env!("CARGO_PKG_VERSION"))])
             .install()
             .unwrap();
@@ -0,0 +1,163 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Send journal payloads from a dedicated background thread.
+
+use std::io::ErrorKind;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{
+    channel, sync_channel, Receiver, RecvTimeoutError, SyncSender, TrySendError,
+};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::transport::JournalTransport;
+
+enum Message {
+    /// A pre-formatted payload to send to journald.
+    Payload(Vec<u8>),
+    /// Signal the sender once all preceding payloads were sent.
+    Flush(SyncSender<()>),
+    /// Terminate the thread once all preceding payloads were sent.
+    Shutdown,
+}
+
+/// The background thread.
+struct Worker {
+    thread: JoinHandle<()>,
+    /// Disconnects when the thread terminates.
+    terminated: Receiver<()>,
+}
+
+/// A bounded queue of payloads, drained by a background thread.
+pub struct BackgroundSender {
+    sender: SyncSender<Message>,
+    /// Shared with the thread, which drops payloads if sending would block.
+    dropped: Arc<AtomicU64>,
+    worker: Mutex<Option<Worker>>,
+}
+
+impl BackgroundSender {
+    /// Spawn a thread which sends all queued payloads through `transport`.
+    ///
+    /// The queue holds at most `queue_capacity` payloads, but at least one,
+    /// because we never block to hand a payload over to the thread.  The
+    /// thread terminates once the returned sender is dropped and all queued
+    /// payloads were sent.
+    pub fn spawn(
+        transport: Arc<dyn JournalTransport>,
+        queue_capacity: usize,
+    ) -> std::io::Result<Self> {
+        let (sender, receiver) = sync_channel(queue_capacity.max(1));
+        let (terminate, terminated) = channel();
+        let dropped = Arc::new(AtomicU64::new(0));
+        let thread_dropped = dropped.clone();
+        let thread = std::thread::Builder::new()
+            .name("systemd-journal-logger".to_string())
+            .spawn(move || {
+                // Dropped when the thread terminates
+                let _terminate: std::sync::mpsc::Sender<()> = terminate;
+                for message in receiver {
+                    match message {
+                        // Like the logger itself we have no way to handle
+                        // errors here, so we discard them.
+                        Message::Payload(payload) => {
+                            if let Err(error) = transport.send_payload(&payload) {
+                                if error.kind() == ErrorKind::WouldBlock {
+                                    thread_dropped.fetch_add(1, Ordering::Relaxed);
+                                }
+                            }
+                        }
+                        Message::Flush(done) => {
+                            let _ = done.send(());
+                        }
+                        Message::Shutdown => break,
+                    }
+                }
+            })?;
+        Ok(Self {
+            sender,
+            dropped,
+            worker: Mutex::new(Some(Worker { thread, terminated })),
+        })
+    }
+
+    /// Queue `payload` for sending, without blocking.
+    ///
+    /// If the queue is full drop `payload`, count it as dropped, and return a
+    /// [`ErrorKind::WouldBlock`] error.
+    pub fn try_send(&self, payload: Vec<u8>) -> std::io::Result<()> {
+        match self.sender.try_send(Message::Payload(payload)) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                Err(std::io::Error::new(
+                    ErrorKind::WouldBlock,
+                    "background queue full, dropped payload",
+                ))
+            }
+            Err(TrySendError::Disconnected(_)) => Err(std::io::Error::new(
+                ErrorKind::BrokenPipe,
+                "background sender thread terminated",
+            )),
+        }
+    }
+
+    /// The number of payloads dropped because the queue was full, or because
+    /// sending them would block (see [`crate::client::JournalClient::set_nonblocking`]).
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Block until all payloads queued so far were sent.
+    pub fn flush(&self) {
+        let (done, wait) = sync_channel(1);
+        if self.sender.send(Message::Flush(done)).is_ok() {
+            let _ = wait.recv();
+        }
+    }
+
+    /// Send all queued payloads and terminate the background thread.
+    ///
+    /// Wait at most `timeout` for the thread to send all queued payloads and
+    /// terminate, and return whether it terminated in time.  If it did not,
+    /// leave it running detached.
+    ///
+    /// After shutdown the sender drops all further payloads.
+    pub fn shutdown(&self, timeout: Duration) -> bool {
+        let worker = match self.worker.lock().unwrap().take() {
+            Some(worker) => worker,
+            // Already shut down
+            None => return true,
+        };
+        let deadline = Instant::now() + timeout;
+        let mut message = Message::Shutdown;
+        // Wait for space in the queue, but don't block beyond the deadline.
+        loop {
+            match self.sender.try_send(message) {
+                Ok(()) | Err(TrySendError::Disconnected(_)) => break,
+                Err(TrySendError::Full(returned)) if Instant::now() < deadline => {
+                    message = returned;
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+                Err(TrySendError::Full(_)) => return false,
+            }
+        }
+        match worker
+            .terminated
+            .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+        {
+            Err(RecvTimeoutError::Disconnected) => {
+                let _ = worker.thread.join();
+                true
+            }
+            Ok(()) | Err(RecvTimeoutError::Timeout) => false,
+        }
+    }
+}
@@ -0,0 +1,132 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Look up message IDs of events by name.
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+use crate::MessageId;
+
+/// A mapping of event names to message IDs for the journal catalog.
+///
+/// Define the message IDs of your catalog in a single table, and refer to
+/// events by name instead of by message ID throughout your code (see
+/// [`crate::JournalLog::log_catalog`]):
+///
+/// ```rust
+/// use systemd_journal_logger::{Catalog, MessageId};
+///
+/// const EVENTS: &[(&str, MessageId)] = &[
+///     ("started", MessageId::from_u128(0x39f53479d3a045ac8e11786248231fbf)),
+///     ("stopped", MessageId::from_u128(0x7b6b1c6ef2f44d5c9b1e8a6c9d5f3e21)),
+/// ];
+///
+/// let catalog = Catalog::new(EVENTS.iter().copied());
+/// // Fail at startup if the table lacks an event we log
+/// catalog.require(&["started", "stopped"]).unwrap();
+/// assert_eq!(catalog.id("started"), Some(EVENTS[0].1));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Catalog {
+    events: HashMap<String, MessageId>,
+}
+
+impl Catalog {
+    /// Create a catalog from pairs of event names and message IDs.
+    ///
+    /// If an event occurs multiple times, the last message ID wins.
+    pub fn new<I, K>(events: I) -> Self
+    where
+        I: IntoIterator<Item = (K, MessageId)>,
+        K: Into<String>,
+    {
+        Self {
+            events: events
+                .into_iter()
+                .map(|(event, id)| (event.into(), id))
+                .collect(),
+        }
+    }
+
+    /// The message ID of `event`, or `None` if this catalog does not know
+    /// `event`.
+    pub fn id(&self, event: &str) -> Option<MessageId> {
+        self.events.get(event).copied()
+    }
+
+    /// The message ID of `event`.
+    ///
+    /// # Errors
+    ///
+    /// Return an error if this catalog does not know `event`.
+    pub fn lookup(&self, event: &str) -> Result<MessageId, UnknownCatalogEvent> {
+        self.id(event).ok_or_else(|| UnknownCatalogEvent {
+            event: event.to_string(),
+        })
+    }
+
+    /// Check that this catalog knows all `events`.
+    ///
+    /// Call this method at startup with all events your code logs, to catch
+    /// missing events before logging them.
+    ///
+    /// # Errors
+    ///
+    /// Return an error for the first event this catalog does not know.
+    pub fn require(&self, events: &[&str]) -> Result<(), UnknownCatalogEvent> {
+        for event in events {
+            self.lookup(event)?;
+        }
+        Ok(())
+    }
+}
+
+/// An event which is not in a [`Catalog`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownCatalogEvent {
+    event: String,
+}
+
+impl UnknownCatalogEvent {
+    /// The name of the unknown event.
+    pub fn event(&self) -> &str {
+        &self.event
+    }
+}
+
+impl Display for UnknownCatalogEvent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Unknown catalog event {:?}", self.event)
+    }
+}
+
+impl std::error::Error for UnknownCatalogEvent {}
+
+#[cfg(test)]
+mod tests {
+    use similar_asserts::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn lookup() {
+        let started = MessageId::from_u128(1);
+        let catalog = Catalog::new([("started", MessageId::from_u128(0)), ("started", started)]);
+        assert_eq!(catalog.id("started"), Some(started));
+        assert_eq!(catalog.id("stopped"), None);
+        assert_eq!(catalog.require(&["started"]), Ok(()));
+        assert_eq!(
+            catalog
+                .require(&["started", "stopped", "crashed"])
+                .unwrap_err()
+                .event(),
+            "stopped"
+        );
+    }
+}
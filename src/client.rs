@@ -8,96 +8,690 @@
 
 //! A journald client.
 
+use std::ffi::CString;
 use std::fs::File;
 use std::io::prelude::*;
-use std::os::fd::AsFd;
+use std::io::{ErrorKind, IoSlice};
+use std::os::fd::{AsFd, OwnedFd};
 use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 
-use rustix::fs::fcntl_add_seals;
-use rustix::fs::memfd_create;
-use rustix::fs::MemfdFlags;
-use rustix::fs::SealFlags;
-use rustix::io::Errno;
-use rustix::net::sendmsg_unix;
-use rustix::net::SendAncillaryBuffer;
-use rustix::net::SendFlags;
-use rustix::net::SocketAddrUnix;
+use crate::sys;
 
-const JOURNALD_PATH: &str = "/run/systemd/journal/socket";
+/// The socket of the system journal.
+pub const SYSTEM_JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
 
+/// The name of memfds for large payloads, if the client has no other name.
+const DEFAULT_MEMFD_NAME: &str = "systemd-journal-logger";
+
+/// The maximum length of a memfd name, without the trailing NUL byte.
+///
+/// See `memfd_create(2)`.
+const MAX_MEMFD_NAME_LEN: usize = 249;
+
+/// The name of memfds for large payloads of a logger with `identifier`.
+///
+/// Replace NUL bytes, which memfd names can't contain, truncate to the
+/// maximum length of memfd names, and use [`DEFAULT_MEMFD_NAME`] if
+/// `identifier` is empty.
+fn memfd_name(identifier: &str) -> CString {
+    let mut name = String::with_capacity(identifier.len().min(MAX_MEMFD_NAME_LEN));
+    for c in identifier.chars() {
+        if MAX_MEMFD_NAME_LEN < name.len() + c.len_utf8() {
+            break;
+        }
+        name.push(if c == '\0' { ' ' } else { c });
+    }
+    if name.is_empty() {
+        name.push_str(DEFAULT_MEMFD_NAME);
+    }
+    // We replaced all NUL bytes above
+    CString::new(name).unwrap()
+}
+
+/// The maximum length of a journal namespace.
+///
+/// journald stores the journal of a namespace in a directory named after the
+/// machine ID and the namespace, separated by a dot, so this is `NAME_MAX`
+/// minus 33 bytes.
+const MAX_NAMESPACE_LEN: usize = 222;
+
+/// Whether `namespace` is a valid journal namespace.
+///
+/// Like `log_namespace_name_valid` in systemd, accept non-empty names with
+/// ASCII letters, digits, and `:-_.@`, except for `.` and `..`, because the
+/// name ends up in file names and unit instance names.
+fn is_valid_namespace(namespace: &str) -> bool {
+    !namespace.is_empty()
+        && namespace.len() <= MAX_NAMESPACE_LEN
+        && namespace != "."
+        && namespace != ".."
+        && namespace
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b":-_.@".contains(&b))
+}
+
+/// The socket of the journal namespace `namespace`.
+///
+/// Return an [`ErrorKind::InvalidInput`] error if `namespace` is not a valid
+/// namespace name.
+///
+/// See `systemd-journald.service(8)`.
+pub fn namespace_socket(namespace: &str) -> std::io::Result<PathBuf> {
+    if is_valid_namespace(namespace) {
+        Ok(PathBuf::from(format!(
+            "/run/systemd/journal.{}/socket",
+            namespace
+        )))
+    } else {
+        Err(std::io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("Invalid journal namespace: {:?}", namespace),
+        ))
+    }
+}
+
+/// How to pass payloads too large for a single datagram to journald.
+///
+/// journald accepts large payloads as file descriptor, either of a sealed
+/// memfd, or of a file in a temporary file system such as `/dev/shm`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum LargePayloadStrategy {
+    /// Use a sealed memfd, and fall back to a temporary file if the kernel
+    /// does not support or does not permit memfds or sealing.
+    #[default]
+    Auto,
+    /// Always use a sealed memfd.
+    Memfd,
+    /// Always use an anonymous temporary file in `/dev/shm`.
+    TempFile,
+}
+
+/// A client for journald.
+///
+/// Clones of a client share the same socket.  The socket is an unbound datagram
+/// socket, and every send is a single `sendmsg` call with the address of
+/// journald's socket, so concurrent sends from multiple threads and clones are
+/// safe, and never interleave.  Alternatively the socket is a datagram socket
+/// already connected to journald, e.g. inherited from the parent process, and
+/// every send is a single `sendmsg` call without address.
+#[derive(Clone)]
 pub struct JournalClient {
-    socket: UnixDatagram,
+    socket: Arc<UnixDatagram>,
+    /// The address of journald's socket, or `None` if the socket is connected.
+    address: Option<sys::Address>,
+    large_payload_strategy: LargePayloadStrategy,
+    /// Send payloads larger than this size directly as large payload.
+    large_payload_threshold: Option<usize>,
+    /// The name of memfds for large payloads.
+    memfd_name: CString,
+    /// Whether to send the credentials of this process with every payload.
+    send_credentials: bool,
+    /// The number of large payloads sent, shared with all clones.
+    large_payloads: Arc<AtomicU64>,
 }
 
 impl JournalClient {
-    pub fn new() -> std::io::Result<Self> {
-        let client = Self {
-            socket: UnixDatagram::unbound()?,
-        };
-        // Check that we can talk to journald, by sending empty payload which journald discards.
-        // However if the socket didn't exist or if none listened we'd get an error here.
-        client.send_payload(&[])?;
+    /// Create a client for the system journal, and check that journald listens.
+    pub fn system() -> std::io::Result<Self> {
+        Self::connect(Path::new(SYSTEM_JOURNAL_SOCKET))
+    }
+
+    /// Create a client for the journald socket at `path`, and check that
+    /// journald listens.
+    pub fn connect(path: &Path) -> std::io::Result<Self> {
+        let client = Self::connect_unchecked(path)?;
+        client.probe()?;
         Ok(client)
     }
 
+    /// Create a client for the journald socket at `path` without checking
+    /// whether journald listens.
+    ///
+    /// If journald isn't reachable all sends fail until journald's socket
+    /// appears; since we send every payload to the socket path anew, the client
+    /// picks up the socket as soon as it exists.
+    pub fn connect_unchecked(path: &Path) -> std::io::Result<Self> {
+        let mut client = Self::from_socket(UnixDatagram::unbound()?);
+        client.address = Some(sys::Address::new(path)?);
+        Ok(client)
+    }
+
+    /// Create a client for the datagram `socket`, which is connected to
+    /// journald, and check that journald listens.
+    pub fn from_connected_socket(socket: OwnedFd) -> std::io::Result<Self> {
+        let client = Self::from_socket(UnixDatagram::from(socket));
+        client.probe()?;
+        Ok(client)
+    }
+
+    /// Create a client which sends to the peer of `socket`.
+    fn from_socket(socket: UnixDatagram) -> Self {
+        Self {
+            socket: Arc::new(socket),
+            address: None,
+            large_payload_strategy: LargePayloadStrategy::default(),
+            large_payload_threshold: None,
+            memfd_name: memfd_name(""),
+            send_credentials: false,
+            large_payloads: Arc::default(),
+        }
+    }
+
+    /// Check that we can talk to journald.
+    ///
+    /// Send an empty payload which journald discards. However if the socket
+    /// didn't exist or if none listened we'd get an error here.
+    pub fn probe(&self) -> std::io::Result<()> {
+        self.send_payload(&[])?;
+        Ok(())
+    }
+
+    /// Set how to send payloads too large for a single datagram.
+    pub fn set_large_payload_strategy(&mut self, strategy: LargePayloadStrategy) {
+        self.large_payload_strategy = strategy;
+    }
+
+    /// Send payloads larger than `threshold` bytes directly as large payloads.
+    ///
+    /// If `None` only send payloads as large payload if sending them as
+    /// datagram fails.
+    pub fn set_large_payload_threshold(&mut self, threshold: Option<usize>) {
+        self.large_payload_threshold = threshold;
+    }
+
+    /// Name memfds for large payloads after the syslog `identifier`.
+    ///
+    /// The name shows up in `/proc/<pid>/fd`, to attribute memfds to loggers.
+    /// If `identifier` is empty use a default name.
+    pub fn set_memfd_name(&mut self, identifier: &str) {
+        self.memfd_name = memfd_name(identifier);
+    }
+
+    /// Whether to send the credentials of this process with every payload.
+    ///
+    /// If enabled send the PID and the real user and group IDs of this
+    /// process as `SCM_CREDENTIALS` control message with every payload.
+    pub fn set_send_credentials(&mut self, enabled: bool) {
+        self.send_credentials = enabled;
+    }
+
+    /// The credentials to send with payloads, if any.
+    fn credentials(&self) -> Option<sys::Credentials> {
+        // Look up credentials for every payload, to send the right PID after
+        // a fork.
+        self.send_credentials.then(sys::Credentials::current)
+    }
+
+    /// The number of payloads this client and its clones sent as large payload.
+    ///
+    /// See [`Self::send_large_payload`].
+    pub fn large_payloads(&self) -> u64 {
+        self.large_payloads.load(Ordering::Relaxed)
+    }
+
+    /// Request a send buffer of `size` bytes for the socket.
+    ///
+    /// This sets `SO_SNDBUF` on the socket, and thus affects all clones of
+    /// this client.  The kernel doubles the requested size for bookkeeping
+    /// overhead, and clamps it to `net.core.wmem_max`.
+    pub fn set_send_buffer_size(&self, size: usize) -> std::io::Result<()> {
+        sys::set_send_buffer_size(&self.socket, size)
+    }
+
+    /// The actual size of the send buffer of the socket, in bytes.
+    pub fn send_buffer_size(&self) -> std::io::Result<usize> {
+        sys::send_buffer_size(&self.socket)
+    }
+
+    /// Whether to fail sends which would block instead of blocking.
+    ///
+    /// This sets `O_NONBLOCK` on the socket, and thus affects all clones of
+    /// this client.  If the send buffer of the socket is full, sends fail with
+    /// [`std::io::ErrorKind::WouldBlock`] right away.
+    pub fn set_nonblocking(&self, nonblocking: bool) -> std::io::Result<()> {
+        self.socket.set_nonblocking(nonblocking)
+    }
+
+    /// Fail sends which block for longer than `timeout`.
+    ///
+    /// This sets `SO_SNDTIMEO` on the socket, and thus affects all clones of
+    /// this client.  If `None` sends block until the socket has room.
+    ///
+    /// Return an [`ErrorKind::InvalidInput`] error for a zero `timeout`, which
+    /// `SO_SNDTIMEO` can't express, because zero disables the timeout.
+    pub fn set_send_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        if timeout == Some(Duration::ZERO) {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                "send timeout must not be zero",
+            ));
+        }
+        self.socket.set_write_timeout(timeout)
+    }
+
+    /// The send timeout of the socket, if any.
+    pub fn send_timeout(&self) -> std::io::Result<Option<Duration>> {
+        self.socket.write_timeout()
+    }
+
+    /// Wait until the socket is writable, for at most `timeout`.
+    ///
+    /// The socket becomes writable once the kernel has room for another
+    /// datagram, i.e. once it handed previously sent datagrams to journald.
+    /// Return whether the socket became writable in time.
+    pub fn wait_writable(&self, timeout: Duration) -> std::io::Result<bool> {
+        sys::wait_writable(self.socket.as_fd(), timeout)
+    }
+
     /// Send `payload` to journald.
     ///
-    /// Directly send it as datagram, and fall back to [`Self::send_large_payload`]
-    /// if that fails with `EMSGSIZE`.
+    /// See [`Self::send_payload_vectored`].
     pub fn send_payload(&self, payload: &[u8]) -> std::io::Result<usize> {
-        self.socket
-            .send_to(payload, JOURNALD_PATH)
-            .or_else(|error| {
-                if Some(Errno::MSGSIZE) == Errno::from_io_error(&error) {
-                    self.send_large_payload(payload)
-                } else {
-                    Err(error)
-                }
-            })
+        self.send_payload_vectored(&[IoSlice::new(payload)])
+    }
+
+    /// Send the concatenation of all `slices` as a single payload to journald.
+    ///
+    /// Directly send all slices as a single datagram, and fall back to
+    /// [`Self::send_large_payload`] if that fails with `EMSGSIZE`, or if the
+    /// payload exceeds the large payload threshold of this client.
+    pub fn send_payload_vectored(&self, slices: &[IoSlice<'_>]) -> std::io::Result<usize> {
+        if let Some(threshold) = self.large_payload_threshold {
+            if threshold < slices.iter().map(|slice| slice.len()).sum() {
+                return self.send_large_payload(slices);
+            }
+        }
+        match sys::send(
+            &self.socket,
+            self.address.as_ref(),
+            slices,
+            None,
+            self.credentials(),
+        ) {
+            Ok(size) => Ok(size),
+            Err(error) => match error.raw_os_error() {
+                Some(sys::EMSGSIZE) => self.send_large_payload(slices),
+                Some(sys::EAGAIN) => Err(std::io::Error::new(
+                    std::io::ErrorKind::WouldBlock,
+                    "socket send buffer full, dropped payload",
+                )),
+                _ => Err(error),
+            },
+        }
     }
 
     /// Send a large payload to journald.
     ///
-    /// Write payload to a memfd, seal it, and then send the FD to the socket in
-    /// an ancilliary message.
+    /// Write all slices of the payload to a file according to the large payload
+    /// strategy of this client, and then send the FD to the socket in an
+    /// ancilliary message.
     ///
     /// See <https://systemd.io/JOURNAL_NATIVE_PROTOCOL/>.
-    fn send_large_payload(&self, payload: &[u8]) -> std::io::Result<usize> {
-        let mut mem: File = memfd_create(
-            "systemd-journal-logger",
-            MemfdFlags::ALLOW_SEALING | MemfdFlags::CLOEXEC,
-        )?
-        .into();
-        mem.write_all(payload)?;
-        // Fully seal the memfd to signal journald that it is safe to mmap now.
-        fcntl_add_seals(
-            &mem,
-            SealFlags::SEAL | SealFlags::SHRINK | SealFlags::WRITE | SealFlags::GROW,
-        )?;
-        let fds = &[mem.as_fd()];
-        let scm_rights = rustix::net::SendAncillaryMessage::ScmRights(fds);
-        // We use a static buffer size here, because we don't need to account
-        // for arbitrary messages; we just need enough space for a single FD.
-        // With a static buffer we get away without any additional heap
-        // allocations here.
-        let mut buffer = [0; 64];
-        // Just a sanity check should we ever get the static buffer size wrong.
-        assert!(
-            scm_rights.size() <= buffer.len(),
-            "static buffer size not sufficient for ScmRights message of size {}",
-            scm_rights.size()
-        );
-        let mut buffer = SendAncillaryBuffer::new(&mut buffer);
-        // push returns false if the buffer is too small to add the new message;
-        // let's guard against this.
-        assert!(buffer.push(scm_rights), "Failed to push ScmRights message");
-        let size = sendmsg_unix(
+    fn send_large_payload(&self, slices: &[IoSlice<'_>]) -> std::io::Result<usize> {
+        let file = large_payload_file(self.large_payload_strategy, slices, || {
+            sys::memfd_sealable(&self.memfd_name)
+        })?;
+        let size = sys::send(
             &self.socket,
-            &SocketAddrUnix::new(JOURNALD_PATH)?,
+            self.address.as_ref(),
             &[],
-            &mut buffer,
-            SendFlags::NOSIGNAL,
+            Some(file.as_fd()),
+            self.credentials(),
         )?;
+        self.large_payloads.fetch_add(1, Ordering::Relaxed);
         Ok(size)
     }
 }
+
+/// Write all `slices` to a file for a large payload, according to `strategy`.
+///
+/// Create memfds with `memfd`.
+fn large_payload_file<F: FnOnce() -> std::io::Result<File>>(
+    strategy: LargePayloadStrategy,
+    slices: &[IoSlice<'_>],
+    memfd: F,
+) -> std::io::Result<File> {
+    match strategy {
+        LargePayloadStrategy::Memfd => sealed_memfd(memfd()?, slices),
+        LargePayloadStrategy::TempFile => temp_file(slices),
+        LargePayloadStrategy::Auto => match memfd().and_then(|mem| sealed_memfd(mem, slices)) {
+            Ok(file) => Ok(file),
+            // Old kernels lack memfds or sealing, and seccomp filters may
+            // deny either.
+            Err(error)
+                if matches!(
+                    error.raw_os_error(),
+                    Some(sys::ENOSYS | sys::EPERM | sys::EINVAL)
+                ) =>
+            {
+                temp_file(slices)
+            }
+            Err(error) => Err(error),
+        },
+    }
+}
+
+/// Write all `slices` to the new memfd `mem` and seal it.
+///
+/// If the memfd runs out of memory or space return an error of kind
+/// [`ErrorKind::OutOfMemory`], see [`memfd_write_error`].
+fn sealed_memfd(mut mem: File, slices: &[IoSlice<'_>]) -> std::io::Result<File> {
+    for slice in slices {
+        if let Err(error) = mem.write_all(slice) {
+            // Close the memfd right away to release its memory, before we
+            // hand the error to the caller.
+            drop(mem);
+            return Err(memfd_write_error(error));
+        }
+    }
+    // Fully seal the memfd to signal journald that it is safe to mmap now.
+    sys::seal_fully(&mem)?;
+    Ok(mem)
+}
+
+/// Convert an `error` from writing to a memfd.
+///
+/// A memfd lives in memory, so writes fail with `ENOMEM` or `ENOSPC` under
+/// memory pressure, e.g. if the process hits its memory limit.  Turn these into
+/// errors of kind [`ErrorKind::OutOfMemory`], to let callers distinguish a
+/// lack of memory from other errors, e.g. to back off.  The returned error
+/// wraps the original error.
+fn memfd_write_error(error: std::io::Error) -> std::io::Error {
+    match error.raw_os_error() {
+        Some(sys::ENOMEM | sys::ENOSPC) => std::io::Error::new(ErrorKind::OutOfMemory, error),
+        _ => error,
+    }
+}
+
+/// Write all `slices` to a new anonymous file in `/dev/shm`.
+///
+/// journald only accepts unsealed files from temporary file systems.
+fn temp_file(slices: &[IoSlice<'_>]) -> std::io::Result<File> {
+    let mut file = sys::shm_tmpfile()?;
+    for slice in slices {
+        file.write_all(slice)?;
+    }
+    file.sync_all()?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::IoSliceMut;
+    use std::os::fd::AsRawFd;
+
+    use rustix::fs::{OFlags, SealFlags};
+    use rustix::io::Errno;
+    use rustix::net::sockopt::set_socket_passcred;
+    use rustix::net::{recvmsg, RecvAncillaryBuffer, RecvAncillaryMessage, RecvFlags};
+
+    use super::*;
+
+    #[test]
+    fn memfd_name() {
+        assert_eq!(super::memfd_name("my-service").as_bytes(), b"my-service");
+        assert_eq!(super::memfd_name("").as_bytes(), b"systemd-journal-logger");
+        assert_eq!(super::memfd_name("a\0b").as_bytes(), b"a b");
+        let name = super::memfd_name(&"ä".repeat(200));
+        assert_eq!(name.as_bytes().len(), 248);
+        assert!(std::str::from_utf8(name.as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn namespace_socket() {
+        assert_eq!(
+            super::namespace_socket("my-app_1.0@host:a").unwrap(),
+            Path::new("/run/systemd/journal.my-app_1.0@host:a/socket")
+        );
+        assert!(super::namespace_socket(&"a".repeat(222)).is_ok());
+        for namespace in [
+            "",
+            ".",
+            "..",
+            "../system",
+            "a/b",
+            "a b",
+            "a*",
+            "ä",
+            &"a".repeat(223),
+        ] {
+            let error = super::namespace_socket(namespace).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidInput, "{:?}", namespace);
+        }
+    }
+
+    #[test]
+    fn sealed_memfd_name() {
+        let file = super::sealed_memfd(
+            sys::memfd_sealable(&super::memfd_name("my-service")).unwrap(),
+            &[IoSlice::new(b"FOO=bar\n")],
+        )
+        .unwrap();
+        let link = std::fs::read_link(format!("/proc/self/fd/{}", file.as_raw_fd())).unwrap();
+        assert!(
+            link.to_string_lossy().starts_with("/memfd:my-service"),
+            "{:?}",
+            link
+        );
+    }
+
+    #[test]
+    fn sealed_memfd() {
+        let mut file = super::sealed_memfd(
+            sys::memfd_sealable(&super::memfd_name("")).unwrap(),
+            &[IoSlice::new(b"FOO=bar\n"), IoSlice::new(b"SPAM=eggs\n")],
+        )
+        .unwrap();
+        // journald only accepts fully sealed memfds
+        let seals = rustix::fs::fcntl_get_seals(&file).unwrap();
+        assert!(seals
+            .contains(SealFlags::SEAL | SealFlags::SHRINK | SealFlags::WRITE | SealFlags::GROW));
+        let mut contents = Vec::new();
+        file.rewind().unwrap();
+        file.read_to_end(&mut contents).unwrap();
+        assert_eq!(contents, b"FOO=bar\nSPAM=eggs\n");
+    }
+
+    #[test]
+    fn wait_writable() {
+        let client = JournalClient::connect_unchecked(Path::new(SYSTEM_JOURNAL_SOCKET)).unwrap();
+        assert!(client.wait_writable(Duration::from_secs(1)).unwrap());
+    }
+
+    #[test]
+    fn send_credentials() {
+        let path = std::env::temp_dir().join(format!(
+            "systemd-journal-logger-credentials-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let receiver = UnixDatagram::bind(&path).unwrap();
+        set_socket_passcred(&receiver, true).unwrap();
+        let mut client = JournalClient::connect_unchecked(&path).unwrap();
+        client.set_send_credentials(true);
+        client.send_payload(b"FOO=bar\n").unwrap();
+        // Also send credentials along with the FD of large payloads
+        client.set_large_payload_threshold(Some(0));
+        client.send_payload(b"FOO=baz\n").unwrap();
+
+        for (expected, expected_fds) in [(&b"FOO=bar\n"[..], 0), (b"", 1)] {
+            let mut data = [0; 64];
+            let mut space = [0; rustix::cmsg_space!(ScmRights(1), ScmCredentials(1))];
+            let mut ancillary = RecvAncillaryBuffer::new(&mut space);
+            let received = recvmsg(
+                &receiver,
+                &mut [IoSliceMut::new(&mut data)],
+                &mut ancillary,
+                RecvFlags::empty(),
+            )
+            .unwrap();
+            assert_eq!(&data[..received.bytes], expected);
+            let mut fds = 0;
+            let mut credentials = None;
+            // Drain all messages, to close received FDs
+            for message in ancillary.drain() {
+                match message {
+                    RecvAncillaryMessage::ScmRights(received) => fds += received.count(),
+                    RecvAncillaryMessage::ScmCredentials(received) => credentials = Some(received),
+                    _ => {}
+                }
+            }
+            assert_eq!(fds, expected_fds);
+            let credentials = credentials.unwrap();
+            assert_eq!(credentials.pid, rustix::process::getpid());
+            assert_eq!(credentials.uid, rustix::process::getuid());
+            assert_eq!(credentials.gid, rustix::process::getgid());
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn from_connected_socket() {
+        let path = std::env::temp_dir().join(format!(
+            "systemd-journal-logger-connected-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let receiver = UnixDatagram::bind(&path).unwrap();
+        let socket = UnixDatagram::unbound().unwrap();
+        socket.connect(&path).unwrap();
+        let mut client = JournalClient::from_connected_socket(socket.into()).unwrap();
+        client.send_payload(b"FOO=bar\n").unwrap();
+        client.set_large_payload_threshold(Some(0));
+        client.send_payload(b"FOO=baz\n").unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut buffer = [0; 64];
+        // The probe
+        assert_eq!(receiver.recv(&mut buffer).unwrap(), 0);
+        let size = receiver.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..size], b"FOO=bar\n");
+        // The large payload, with the FD in an ancillary message
+        assert_eq!(receiver.recv(&mut buffer).unwrap(), 0);
+        assert_eq!(client.large_payloads(), 1);
+
+        let unconnected = UnixDatagram::unbound().unwrap();
+        assert!(JournalClient::from_connected_socket(unconnected.into()).is_err());
+    }
+
+    #[test]
+    fn large_payloads() {
+        let mut client =
+            JournalClient::connect_unchecked(Path::new(SYSTEM_JOURNAL_SOCKET)).unwrap();
+        client.send_payload(b"FOO=bar\n").unwrap();
+        assert_eq!(client.large_payloads(), 0);
+        client.set_large_payload_threshold(Some(4));
+        client.send_payload(b"FOO=bar\n").unwrap();
+        // Clones share the count
+        assert_eq!(client.clone().large_payloads(), 1);
+    }
+
+    #[test]
+    fn send_buffer_size() {
+        let client = JournalClient::connect_unchecked(Path::new(SYSTEM_JOURNAL_SOCKET)).unwrap();
+        client.set_send_buffer_size(16384).unwrap();
+        // The kernel doubles the requested size
+        assert_eq!(client.send_buffer_size().unwrap(), 32768);
+        // Clones share the socket, and thus the send buffer
+        assert_eq!(client.clone().send_buffer_size().unwrap(), 32768);
+    }
+
+    #[test]
+    fn set_nonblocking() {
+        let client = JournalClient::connect_unchecked(Path::new(SYSTEM_JOURNAL_SOCKET)).unwrap();
+        let nonblocking = |client: &JournalClient| {
+            rustix::fs::fcntl_getfl(&client.socket)
+                .unwrap()
+                .contains(OFlags::NONBLOCK)
+        };
+        assert!(!nonblocking(&client));
+        client.set_nonblocking(true).unwrap();
+        assert!(nonblocking(&client));
+        client.set_nonblocking(false).unwrap();
+        assert!(!nonblocking(&client));
+    }
+
+    #[test]
+    fn send_timeout() {
+        let client = JournalClient::connect_unchecked(Path::new(SYSTEM_JOURNAL_SOCKET)).unwrap();
+        assert_eq!(client.send_timeout().unwrap(), None);
+        // The kernel rounds timeouts to jiffies, so use full seconds
+        client
+            .set_send_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        assert_eq!(client.send_timeout().unwrap(), Some(Duration::from_secs(2)));
+        let error = client.set_send_timeout(Some(Duration::ZERO)).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert_eq!(client.send_timeout().unwrap(), Some(Duration::from_secs(2)));
+        client.set_send_timeout(None).unwrap();
+        assert_eq!(client.send_timeout().unwrap(), None);
+    }
+
+    /// Assert that `file` is a temporary file in `/dev/shm` with `contents`.
+    fn assert_temp_file(mut file: File, contents: &[u8]) {
+        let link = std::fs::read_link(format!("/proc/self/fd/{}", file.as_raw_fd())).unwrap();
+        assert!(link.starts_with("/dev/shm"), "{:?}", link);
+        let mut actual = Vec::new();
+        file.rewind().unwrap();
+        file.read_to_end(&mut actual).unwrap();
+        assert_eq!(actual, contents);
+    }
+
+    #[test]
+    fn large_payload_file_without_memfd() {
+        let slices = [IoSlice::new(b"FOO=bar\n")];
+        let unsupported = || Err(std::io::Error::from_raw_os_error(sys::ENOSYS));
+        let error =
+            large_payload_file(LargePayloadStrategy::Memfd, &slices, unsupported).unwrap_err();
+        assert_eq!(error.raw_os_error(), Some(sys::ENOSYS));
+        let file = large_payload_file(LargePayloadStrategy::Auto, &slices, unsupported).unwrap();
+        assert_temp_file(file, b"FOO=bar\n");
+    }
+
+    #[test]
+    fn large_payload_file_memfd_denies_writes() {
+        let slices = [IoSlice::new(b"FOO=bar\n")];
+        // Writes to a memfd sealed against writes fail with EPERM
+        let sealed = || {
+            let mem = sys::memfd_sealable(&super::memfd_name(""))?;
+            rustix::fs::fcntl_add_seals(&mem, SealFlags::WRITE)?;
+            Ok(mem)
+        };
+        let error = large_payload_file(LargePayloadStrategy::Memfd, &slices, sealed).unwrap_err();
+        assert_eq!(error.raw_os_error(), Some(sys::EPERM));
+        let file = large_payload_file(LargePayloadStrategy::Auto, &slices, sealed).unwrap();
+        assert_temp_file(file, b"FOO=bar\n");
+    }
+
+    #[test]
+    fn large_payload_file_memfd_out_of_memory() {
+        let slices = [IoSlice::new(b"FOO=bar\n")];
+        // Writes to /dev/full fail with ENOSPC, like writes to a memfd
+        // beyond the memory limit of the process.
+        let full = || File::options().write(true).open("/dev/full");
+        for strategy in [LargePayloadStrategy::Memfd, LargePayloadStrategy::Auto] {
+            let error = large_payload_file(strategy, &slices, full).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::OutOfMemory);
+        }
+    }
+
+    #[test]
+    fn memfd_write_error_out_of_memory() {
+        for errno in [Errno::NOMEM, Errno::NOSPC] {
+            let error = memfd_write_error(errno.into());
+            assert_eq!(error.kind(), ErrorKind::OutOfMemory);
+            let source = error.get_ref().unwrap().downcast_ref::<std::io::Error>();
+            assert_eq!(source.and_then(Errno::from_io_error), Some(errno));
+        }
+    }
+
+    #[test]
+    fn memfd_write_error_other() {
+        let error = memfd_write_error(Errno::BADF.into());
+        assert_eq!(Errno::from_io_error(&error), Some(Errno::BADF));
+    }
+}
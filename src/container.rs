@@ -0,0 +1,64 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Detect the name of the container this process runs in.
+
+use std::path::Path;
+
+/// Detect the name of the container of the current process, if any.
+///
+/// Use the name from `/run/.containerenv`, which podman creates in every
+/// container, but only fills for privileged containers.  Otherwise, if the
+/// process runs in a container, i.e. if `/run/.containerenv` or `/.dockerenv`
+/// exist or if `$container` is set (see systemd's container interface), use
+/// the hostname, which container engines set to the name or the ID of the
+/// container by default.
+///
+/// Return `None` if the process does not appear to run in a container, or if
+/// the name is not available.
+pub fn detect_container_name() -> Option<String> {
+    let containerenv = std::fs::read_to_string("/run/.containerenv").ok();
+    if let Some(name) = containerenv.as_deref().and_then(parse_containerenv_name) {
+        return Some(name.to_string());
+    }
+    if containerenv.is_some()
+        || std::env::var_os("container").is_some()
+        || Path::new("/.dockerenv").exists()
+    {
+        let hostname = std::fs::read_to_string("/etc/hostname").ok()?;
+        let hostname = hostname.trim();
+        if !hostname.is_empty() {
+            return Some(hostname.to_string());
+        }
+    }
+    None
+}
+
+/// Get the container name from the contents of `/run/.containerenv`.
+///
+/// The file has lines of `key="value"`, and the `name` key holds the
+/// container name.
+fn parse_containerenv_name(contents: &str) -> Option<&str> {
+    contents
+        .lines()
+        .filter_map(|line| line.strip_prefix("name="))
+        .map(|value| value.trim().trim_matches('"'))
+        .find(|name| !name.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn parse_containerenv_name() {
+        let contents = "engine=\"podman-4.9.3\"\nname=\"my-service\"\nid=\"2b8f\"\n";
+        assert_eq!(super::parse_containerenv_name(contents), Some("my-service"));
+        assert_eq!(super::parse_containerenv_name("name=\"\"\n"), None);
+        // Rootless podman creates an empty file
+        assert_eq!(super::parse_containerenv_name(""), None);
+    }
+}
@@ -0,0 +1,62 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A thread-local stack of context fields.
+
+use std::cell::RefCell;
+
+thread_local! {
+    /// The context of the current thread, dropped when the thread exits.
+    static CONTEXT: RefCell<Vec<(String, String)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Push a context field with `key` and `value` for the current thread.
+///
+/// A logger with a context stack (see
+/// [`crate::JournalLog::with_context_stack`]) adds all context fields of the
+/// current thread to every record logged on this thread, much like a mapped
+/// diagnostic context in other logging frameworks.  Use [`pop_context`] to
+/// remove the field again, once the work the context applies to is done:
+///
+/// ```rust
+/// use systemd_journal_logger::{pop_context, push_context};
+///
+/// push_context("REQUEST_ID", "42");
+/// log::info!("Handling request");
+/// pop_context();
+/// ```
+///
+/// Keys are escaped like the keys of record key-values.
+pub fn push_context<K: Into<String>, V: Into<String>>(key: K, value: V) {
+    // If the thread is exiting there's no more logging to add context to.
+    let _ = CONTEXT.try_with(|context| context.borrow_mut().push((key.into(), value.into())));
+}
+
+/// Remove the most recently pushed context field of the current thread.
+///
+/// Return the key and the value of the field, or `None` if the context of the
+/// current thread is empty.  See [`push_context`].
+pub fn pop_context() -> Option<(String, String)> {
+    CONTEXT
+        .try_with(|context| context.borrow_mut().pop())
+        .ok()
+        .flatten()
+}
+
+/// Call `f` with every context field of the current thread, oldest first.
+pub fn for_each_context_field<F: FnMut(&str, &str)>(mut f: F) {
+    let _ = CONTEXT.try_with(|context| {
+        // Skip the context if formatting a field value logs recursively
+        // while we already hold the context.
+        if let Ok(context) = context.try_borrow() {
+            for (key, value) in context.iter() {
+                f(key, value);
+            }
+        }
+    });
+}
@@ -0,0 +1,243 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Periodic summaries of dropped records.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::fields::{put_field_bytes, put_field_u64, FieldName};
+use crate::MessageId;
+
+/// The message ID of summaries of dropped records.
+///
+/// See [`crate::JournalLog::with_drop_summary_interval`].
+pub const DROP_SUMMARY_MESSAGE_ID: MessageId =
+    MessageId::from_u128(0x854e5a3fc8224016a717f79e10e2e348);
+
+/// The number of dropped records, by reason.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct DropCounts {
+    /// Records discarded by sampling.
+    pub sampled: u64,
+    /// Records dropped because sending them would block.
+    pub would_block: u64,
+    /// Records dropped while the logger was paused.
+    pub paused: u64,
+}
+
+impl DropCounts {
+    /// The total number of dropped records.
+    pub fn total(&self) -> u64 {
+        self.sampled + self.would_block + self.paused
+    }
+
+    /// The number of records dropped since `earlier`.
+    fn since(&self, earlier: &Self) -> Self {
+        Self {
+            sampled: self.sampled - earlier.sampled,
+            would_block: self.would_block - earlier.would_block,
+            paused: self.paused - earlier.paused,
+        }
+    }
+}
+
+/// How often the summary thread checks for dropped records at most.
+///
+/// Keeps the thread from spinning with a zero interval.
+const MIN_CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Decide when to summarize dropped records.
+pub struct DropSummary {
+    interval: Duration,
+    /// When we last checked for dropped records.
+    last_summary: Instant,
+    /// The counts at the last summary.
+    reported: DropCounts,
+}
+
+impl DropSummary {
+    /// Summarize dropped records at most once every `interval`.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last_summary: Instant::now(),
+            reported: DropCounts::default(),
+        }
+    }
+
+    /// Get the records dropped since the last summary, if a summary is due.
+    ///
+    /// `counts` are the total numbers of dropped records so far.  Return the
+    /// records dropped since the last summary and the time since the last
+    /// summary, if `interval` elapsed since the last summary and any records
+    /// were dropped in the meantime.
+    pub fn due(&mut self, counts: DropCounts) -> Option<(DropCounts, Duration)> {
+        let elapsed = self.last_summary.elapsed();
+        if elapsed < self.interval {
+            return None;
+        }
+        let dropped = counts.since(&self.reported);
+        self.last_summary = Instant::now();
+        self.reported = counts;
+        if dropped.total() == 0 {
+            None
+        } else {
+            Some((dropped, elapsed))
+        }
+    }
+}
+
+/// A thread which periodically summarizes dropped records, started on demand.
+pub struct DropSummaryThread {
+    interval: Duration,
+    /// Whether the thread still needs to be started.
+    pending: AtomicBool,
+    /// Disconnects to terminate the thread.
+    stop: Mutex<Option<Sender<()>>>,
+}
+
+impl DropSummaryThread {
+    /// Check for dropped records every `interval`, once started.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            pending: AtomicBool::new(true),
+            stop: Mutex::new(None),
+        }
+    }
+
+    /// Whether the thread still needs to be started.
+    ///
+    /// Return `true` only once, for the caller which should then call
+    /// [`Self::spawn`].
+    pub fn should_start(&self) -> bool {
+        self.pending.load(Ordering::Relaxed) && self.pending.swap(false, Ordering::Relaxed)
+    }
+
+    /// Spawn the thread.
+    ///
+    /// Every `interval` get the total numbers of dropped records so far from
+    /// `counts`, and pass the records dropped since the last summary and the
+    /// time since the last summary to `send`, if any records were dropped.
+    /// The thread terminates once `self` is dropped.
+    pub fn spawn<C, S>(&self, counts: C, send: S) -> std::io::Result<()>
+    where
+        C: Fn() -> DropCounts + Send + 'static,
+        S: Fn(DropCounts, Duration) + Send + 'static,
+    {
+        let (stop, stopped) = channel::<()>();
+        let mut summary = DropSummary::new(self.interval);
+        let wait = self.interval.max(MIN_CHECK_INTERVAL);
+        std::thread::Builder::new()
+            .name("systemd-journal-logger-drop-summary".to_string())
+            .spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(wait) {
+                    if let Some((dropped, elapsed)) = summary.due(counts()) {
+                        send(dropped, elapsed);
+                    }
+                }
+            })?;
+        *self.stop.lock().unwrap() = Some(stop);
+        Ok(())
+    }
+}
+
+/// Create the journal fields for a summary of `dropped` records in the last
+/// `elapsed` time.
+///
+/// See [`crate::JournalLog::with_drop_summary_interval`].
+pub fn payload(syslog_identifier: &str, dropped: DropCounts, elapsed: Duration) -> Vec<u8> {
+    use FieldName::*;
+    let mut buffer = Vec::with_capacity(512);
+    // warning
+    put_field_bytes(&mut buffer, WellFormed("PRIORITY"), b"4");
+    let message = format!(
+        "Dropped {} records in the last {} seconds",
+        dropped.total(),
+        elapsed.as_secs()
+    );
+    put_field_bytes(&mut buffer, WellFormed("MESSAGE"), message.as_bytes());
+    put_field_bytes(
+        &mut buffer,
+        WellFormed("MESSAGE_ID"),
+        DROP_SUMMARY_MESSAGE_ID.to_string().as_bytes(),
+    );
+    put_field_u64(&mut buffer, WellFormed("DROPPED_SAMPLED"), dropped.sampled);
+    put_field_u64(
+        &mut buffer,
+        WellFormed("DROPPED_WOULD_BLOCK"),
+        dropped.would_block,
+    );
+    put_field_u64(&mut buffer, WellFormed("DROPPED_PAUSED"), dropped.paused);
+    put_field_u64(
+        &mut buffer,
+        WellFormed("SYSLOG_PID"),
+        u64::from(std::process::id()),
+    );
+    if !syslog_identifier.is_empty() {
+        put_field_bytes(
+            &mut buffer,
+            WellFormed("SYSLOG_IDENTIFIER"),
+            syslog_identifier.as_bytes(),
+        );
+    }
+    put_field_bytes(
+        &mut buffer,
+        WellFormed("TARGET"),
+        env!("CARGO_CRATE_NAME").as_bytes(),
+    );
+    buffer
+}
+
+#[cfg(test)]
+mod tests {
+    use similar_asserts::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn due() {
+        let mut summary = DropSummary::new(Duration::ZERO);
+        let counts = DropCounts {
+            sampled: 3,
+            would_block: 2,
+            paused: 1,
+        };
+        assert_eq!(
+            summary.due(counts).map(|(dropped, _)| dropped),
+            Some(counts)
+        );
+        // Nothing dropped since
+        assert_eq!(summary.due(counts), None);
+        let later = DropCounts {
+            sampled: 5,
+            ..counts
+        };
+        assert_eq!(
+            summary.due(later).map(|(dropped, _)| dropped),
+            Some(DropCounts {
+                sampled: 2,
+                would_block: 0,
+                paused: 0
+            })
+        );
+    }
+
+    #[test]
+    fn due_after_interval() {
+        let mut summary = DropSummary::new(Duration::from_secs(3600));
+        let counts = DropCounts {
+            sampled: 1,
+            ..DropCounts::default()
+        };
+        assert_eq!(summary.due(counts), None);
+    }
+}
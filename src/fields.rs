@@ -8,14 +8,43 @@
 
 //! Write well-formated journal fields to buffers.
 
-use std::fmt::Arguments;
+use std::fmt::{Arguments, Display, Formatter};
 use std::io::Write;
+use std::ops::Range;
 
 use log::kv::Value;
 
+/// What to do with record keys which escape to a standard field name.
+///
+/// The logger itself writes standard fields like `MESSAGE` or `PRIORITY` for
+/// every record.  If a record also has a key-value with the same field name,
+/// e.g. `message`, the journal entry ends up with two values for this field,
+/// and `journalctl` only shows the first, i.e. silently hides the other value.
+///
+/// See [`crate::JournalLog::with_reserved_field_policy`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum ReservedFieldPolicy {
+    /// Write the key-value as is, and add a second value to the standard field.
+    #[default]
+    Allow,
+    /// Prefix the field name with `FIELD_`, e.g. write the key `message` to
+    /// `FIELD_MESSAGE`.
+    Rename,
+    /// Skip the key-value, and count skipped key-values.
+    Skip,
+}
+
+/// The name of a field to write.
 pub enum FieldName<'a> {
+    /// A valid journal field name, to write as is.
+    ///
+    /// The name must not come from users unless validated or escaped, because
+    /// a newline or `=` in a name would corrupt the payload.
     WellFormed(&'a str),
+    /// Escape the name, see [`escape_field_name`].
     WriteEscaped(&'a str),
+    /// Escape the name, and replace the tail of long names with a hash.
+    WriteEscapedHashed(&'a str),
 }
 
 /// Whether `c` is a valid character in the key of a journal field.
@@ -26,86 +55,648 @@ fn is_valid_key_char(c: char) -> bool {
     matches!(c, 'A'..='Z' | '0'..='9' | '_')
 }
 
+/// Why a field name is not a valid journal field name.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum InvalidFieldNameReason {
+    /// The name is empty.
+    Empty,
+    /// The name is longer than 64 bytes.
+    TooLong,
+    /// The name starts with a digit.
+    StartsWithDigit,
+    /// The name starts with an underscore, which journald reserves for trusted fields.
+    StartsWithUnderscore,
+    /// The name contains a character other than ASCII uppercase letters,
+    /// digits, and the underscore.
+    InvalidCharacter(char),
+}
+
+impl Display for InvalidFieldNameReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InvalidFieldNameReason::Empty => write!(f, "name is empty"),
+            InvalidFieldNameReason::TooLong => write!(f, "name is longer than 64 bytes"),
+            InvalidFieldNameReason::StartsWithDigit => write!(f, "name starts with a digit"),
+            InvalidFieldNameReason::StartsWithUnderscore => {
+                write!(f, "name starts with an underscore")
+            }
+            InvalidFieldNameReason::InvalidCharacter(c) => {
+                write!(f, "name contains invalid character {:?}", c)
+            }
+        }
+    }
+}
+
+/// A field name which is not a valid journal field name.
+///
+/// See [`crate::JournalLog::try_add_extra_field`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidFieldName {
+    name: String,
+    reason: InvalidFieldNameReason,
+}
+
+impl InvalidFieldName {
+    /// The offending field name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Why the field name is invalid.
+    pub fn reason(&self) -> InvalidFieldNameReason {
+        self.reason
+    }
+}
+
+impl Display for InvalidFieldName {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Invalid journal field name {:?}: {}",
+            self.name, self.reason
+        )
+    }
+}
+
+impl std::error::Error for InvalidFieldName {}
+
+/// Check whether `name` is a valid journal field name.
+///
+/// See [`crate::JournalLog`] for the rules.
+pub fn validate_field_name(name: &str) -> Result<(), InvalidFieldName> {
+    match invalid_field_name_reason(name) {
+        None => Ok(()),
+        Some(reason) => Err(InvalidFieldName {
+            name: name.to_string(),
+            reason,
+        }),
+    }
+}
+
+/// Whether `name` is a valid journal field name.
+///
+/// Like [`validate_field_name`], but without allocating an error.
+pub fn is_valid_journal_field_name(name: &str) -> bool {
+    invalid_field_name_reason(name).is_none()
+}
+
+/// Why `name` is not a valid journal field name, or `None` if it is valid.
+fn invalid_field_name_reason(name: &str) -> Option<InvalidFieldNameReason> {
+    if name.is_empty() {
+        Some(InvalidFieldNameReason::Empty)
+    } else if 64 < name.len() {
+        Some(InvalidFieldNameReason::TooLong)
+    } else if name.starts_with(|c: char| c.is_ascii_digit()) {
+        Some(InvalidFieldNameReason::StartsWithDigit)
+    } else if name.starts_with('_') {
+        Some(InvalidFieldNameReason::StartsWithUnderscore)
+    } else {
+        name.chars()
+            .find(|c| !is_valid_key_char(*c))
+            .map(InvalidFieldNameReason::InvalidCharacter)
+    }
+}
+
+/// Why a blob of raw fields is not well-formed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvalidRawFieldsReason {
+    /// A field has an invalid name.
+    InvalidFieldName(InvalidFieldName),
+    /// A field is not terminated by a newline.
+    MissingNewline,
+    /// A length-encoded field value is shorter than its length.
+    TruncatedValue,
+}
+
+impl Display for InvalidRawFieldsReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InvalidRawFieldsReason::InvalidFieldName(error) => error.fmt(f),
+            InvalidRawFieldsReason::MissingNewline => write!(f, "field not terminated by newline"),
+            InvalidRawFieldsReason::TruncatedValue => {
+                write!(f, "length-encoded value shorter than its length")
+            }
+        }
+    }
+}
+
+/// A blob of raw fields which is not well-formed.
+///
+/// See [`crate::JournalLog::add_raw_fields`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidRawFields {
+    offset: usize,
+    reason: InvalidRawFieldsReason,
+}
+
+impl InvalidRawFields {
+    /// The offset of the offending field within the blob.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Why the blob is not well-formed.
+    pub fn reason(&self) -> &InvalidRawFieldsReason {
+        &self.reason
+    }
+}
+
+impl Display for InvalidRawFields {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Invalid raw journal field at offset {}: {}",
+            self.offset, self.reason
+        )
+    }
+}
+
+impl std::error::Error for InvalidRawFields {}
+
+/// Check whether `blob` consists of well-formed journal fields.
+///
+/// Each field must have a valid name (see [`crate::InvalidFieldNameReason`]),
+/// followed either by `=`, the value, and a newline, or by a newline, the
+/// length of the value as 64-bit little endian integer, the value, and a
+/// newline.  Nothing may follow the last field.
+///
+/// Use this function to check payloads built by hand, e.g. for
+/// [`crate::JournalLog::add_raw_fields`], before sending them to journald:
+///
+/// ```rust
+/// use systemd_journal_logger::{validate_payload, InvalidRawFieldsReason};
+///
+/// assert!(validate_payload(b"FOO=bar\nBAZ\n\x03\0\0\0\0\0\0\0q\nx\n").is_ok());
+///
+/// let error = validate_payload(b"FOO=bar\nBAZ\n\x05\0\0\0\0\0\0\0qux\n").unwrap_err();
+/// assert_eq!(error.offset(), 8);
+/// assert_eq!(error.reason(), &InvalidRawFieldsReason::TruncatedValue);
+/// ```
+///
+/// This function is stricter than journald, which accepts names with a
+/// leading underscore, but ignores their fields.
+///
+/// See <https://systemd.io/JOURNAL_NATIVE_PROTOCOL/> for the format.
+pub fn validate_payload(blob: &[u8]) -> Result<(), InvalidRawFields> {
+    let mut offset = 0;
+    while offset < blob.len() {
+        let error = |reason| InvalidRawFields { offset, reason };
+        let field = split_field(&blob[offset..]).map_err(error)?;
+        validate_field_name(&String::from_utf8_lossy(field.name))
+            .map_err(|e| error(InvalidRawFieldsReason::InvalidFieldName(e)))?;
+        offset += field.size;
+    }
+    Ok(())
+}
+
+/// Decode the names and values of all fields in the well-formed `blob`.
+///
+/// See [`validate_payload`] for the format; stop at the first malformed field.
+pub fn payload_fields(blob: &[u8]) -> Vec<(String, Vec<u8>)> {
+    let mut fields = Vec::new();
+    let mut rest = blob;
+    while let Ok(field) = split_field(rest) {
+        fields.push((
+            String::from_utf8_lossy(field.name).into_owned(),
+            field.value.to_vec(),
+        ));
+        rest = &rest[field.size..];
+    }
+    fields
+}
+
+/// Replace all fields named `name` in the well-formed `blob` with `value`.
+///
+/// Write a single field `name` with `value` in place of the first field named
+/// `name`, or at the end of `blob` if there is no such field, and remove all
+/// other fields named `name`.  `name` must be a valid journal field name.
+///
+/// Return the range of the new field in `blob`.
+pub fn replace_field(blob: &mut Vec<u8>, name: &str, value: &[u8]) -> Range<usize> {
+    let mut replaced = Vec::with_capacity(blob.len() + name.len() + value.len() + 10);
+    let mut range = None;
+    let mut rest = blob.as_slice();
+    while let Ok(field) = split_field(rest) {
+        if field.name != name.as_bytes() {
+            replaced.extend_from_slice(&rest[..field.size]);
+        } else if range.is_none() {
+            let start = replaced.len();
+            put_field_bytes(&mut replaced, FieldName::WellFormed(name), value);
+            range = Some(start..replaced.len());
+        }
+        rest = &rest[field.size..];
+    }
+    let range = range.unwrap_or_else(|| {
+        let start = replaced.len();
+        put_field_bytes(&mut replaced, FieldName::WellFormed(name), value);
+        start..replaced.len()
+    });
+    *blob = replaced;
+    range
+}
+
+/// A single field at the start of a payload.
+struct RawField<'a> {
+    name: &'a [u8],
+    value: &'a [u8],
+    /// The size of the entire field, including the trailing newline.
+    size: usize,
+}
+
+/// Split the first field off `blob`, without validating its name.
+fn split_field(blob: &[u8]) -> Result<RawField<'_>, InvalidRawFieldsReason> {
+    let name_end = blob
+        .iter()
+        .position(|b| *b == b'=' || *b == b'\n')
+        .ok_or(InvalidRawFieldsReason::MissingNewline)?;
+    let name = &blob[..name_end];
+    if blob[name_end] == b'=' {
+        let value_len = blob[name_end + 1..]
+            .iter()
+            .position(|b| *b == b'\n')
+            .ok_or(InvalidRawFieldsReason::MissingNewline)?;
+        let value_end = name_end + 1 + value_len;
+        Ok(RawField {
+            name,
+            value: &blob[name_end + 1..value_end],
+            size: value_end + 1,
+        })
+    } else {
+        let value_start = name_end + 1 + 8;
+        let length_bytes = blob
+            .get(name_end + 1..value_start)
+            .ok_or(InvalidRawFieldsReason::TruncatedValue)?;
+        let value_len = u64::from_le_bytes(length_bytes.try_into().unwrap());
+        let value_end = usize::try_from(value_len)
+            .ok()
+            .and_then(|len| value_start.checked_add(len))
+            .filter(|end| *end <= blob.len())
+            .ok_or(InvalidRawFieldsReason::TruncatedValue)?;
+        if blob.get(value_end) != Some(&b'\n') {
+            return Err(InvalidRawFieldsReason::MissingNewline);
+        }
+        Ok(RawField {
+            name,
+            value: &blob[value_start..value_end],
+            size: value_end + 1,
+        })
+    }
+}
+
+/// A buffer to write journal fields to.
+///
+/// We write fields either to a growing [`Vec`], or to a fixed slice provided
+/// by the caller, to log without allocating (see [`SliceBuffer`]).
+pub trait FieldBuffer: Write {
+    /// The number of bytes written to this buffer so far.
+    fn written(&self) -> usize;
+
+    /// Append `bytes` to this buffer.
+    fn put(&mut self, bytes: &[u8]);
+
+    /// Overwrite the bytes at `offset` with `bytes`.
+    ///
+    /// All overwritten bytes must have been written before.
+    fn overwrite(&mut self, offset: usize, bytes: &[u8]);
+
+    /// Discard all bytes after the first `len` bytes.
+    fn truncate(&mut self, len: usize);
+}
+
+impl FieldBuffer for Vec<u8> {
+    fn written(&self) -> usize {
+        self.len()
+    }
+
+    fn put(&mut self, bytes: &[u8]) {
+        self.extend_from_slice(bytes)
+    }
+
+    fn overwrite(&mut self, offset: usize, bytes: &[u8]) {
+        self[offset..offset + bytes.len()].copy_from_slice(bytes)
+    }
+
+    fn truncate(&mut self, len: usize) {
+        Vec::truncate(self, len)
+    }
+}
+
+/// A buffer which writes fields to a fixed slice.
+///
+/// If the fields do not fit into the slice, the buffer keeps counting the
+/// bytes written, to tell the caller how large the slice must be (see
+/// [`Self::finish`]), but discards their contents.
+pub struct SliceBuffer<'a> {
+    slice: &'a mut [u8],
+    written: usize,
+}
+
+impl<'a> SliceBuffer<'a> {
+    /// Write fields to `slice`.
+    pub fn new(slice: &'a mut [u8]) -> Self {
+        Self { slice, written: 0 }
+    }
+
+    /// Finish writing to the slice.
+    ///
+    /// Return the written part of the slice, or an error if the fields did
+    /// not fit into the slice.
+    pub fn finish(self) -> Result<&'a [u8], Overflow> {
+        if self.written <= self.slice.len() {
+            Ok(&self.slice[..self.written])
+        } else {
+            Err(Overflow {
+                required: self.written,
+            })
+        }
+    }
+}
+
+impl FieldBuffer for SliceBuffer<'_> {
+    fn written(&self) -> usize {
+        self.written
+    }
+
+    fn put(&mut self, bytes: &[u8]) {
+        let end = self.written + bytes.len();
+        if let Some(target) = self.slice.get_mut(self.written..end) {
+            target.copy_from_slice(bytes);
+        }
+        self.written = end;
+    }
+
+    fn overwrite(&mut self, offset: usize, bytes: &[u8]) {
+        if let Some(target) = self.slice.get_mut(offset..offset + bytes.len()) {
+            target.copy_from_slice(bytes);
+        }
+    }
+
+    fn truncate(&mut self, len: usize) {
+        self.written = self.written.min(len);
+    }
+}
+
+impl Write for SliceBuffer<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.put(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A buffer was too small for a journal entry.
+///
+/// See [`crate::JournalLog::format_record_into`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Overflow {
+    required: usize,
+}
+
+impl Overflow {
+    /// The size of the buffer the journal entry requires.
+    pub fn required(&self) -> usize {
+        self.required
+    }
+}
+
+impl Display for Overflow {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Buffer too small for journal entry, requires {} bytes",
+            self.required
+        )
+    }
+}
+
+impl std::error::Error for Overflow {}
+
 /// Escape a `key` for use in a systemd journal field.
 ///
 /// See [`crate::JournalLog`] for these rules.
+///
+/// Escaping is idempotent: The escaped key is always a valid field name, and
+/// escaping leaves valid field names unchanged, so escaping an escaped key
+/// again does not change it.
+///
+/// The logger escapes keys right into its buffer, so only tests need this.
+#[cfg(test)]
 pub fn escape_journal_key(key: &str) -> Vec<u8> {
-    let mut escaped = key
-        .to_ascii_uppercase()
-        .replace(|c| !is_valid_key_char(c), "_");
-    if escaped.starts_with(|c: char| matches!(c, '_' | '0'..='9')) {
-        escaped = format!("ESCAPED_{}", escaped);
+    let mut escaped = Vec::new();
+    put_escaped_key(&mut escaped, key, false);
+    escaped
+}
+
+/// Escape a `key` for use in a systemd journal field, and hash long keys.
+///
+/// Like [`escape_journal_key`], but instead of merely truncating escaped keys
+/// longer than 64 bytes, replace the tail of long keys with a hash of `key`,
+/// so that distinct long keys remain distinct.
+#[cfg(test)]
+pub fn escape_journal_key_hashed(key: &str) -> Vec<u8> {
+    let mut escaped = Vec::new();
+    put_escaped_key(&mut escaped, key, true);
+    escaped
+}
+
+/// A stable 32-bit FNV-1a hash of `data`.
+///
+/// We need a hash which remains the same across processes and Rust versions,
+/// so that long keys map to the same journal field in every process.
+fn fnv1a(data: &[u8]) -> u32 {
+    data.iter().fold(0x811c_9dc5, |hash, byte| {
+        (hash ^ u32::from(*byte)).wrapping_mul(0x0100_0193)
+    })
+}
+
+/// Escape a single character of a key.
+///
+/// Replace every invalid char, including every non-ASCII char, with a single
+/// underscore, so that only ASCII remains and we can safely truncate bytes.
+fn escape_key_char(c: char) -> u8 {
+    let c = c.to_ascii_uppercase();
+    if is_valid_key_char(c) {
+        c as u8
+    } else {
+        b'_'
     }
-    let mut payload = escaped.into_bytes();
-    payload.truncate(64);
-    payload
 }
 
-fn put_field_name(buffer: &mut Vec<u8>, name: FieldName<'_>) {
+/// Write `key` escaped to `buffer`, without allocating.
+///
+/// If `hash_long_keys` replace the tail of keys longer than 64 bytes after
+/// escaping with a hash, see [`escape_journal_key_hashed`].
+fn put_escaped_key<B: FieldBuffer + ?Sized>(buffer: &mut B, key: &str, hash_long_keys: bool) {
+    if key.is_empty() {
+        buffer.put(b"EMPTY");
+        return;
+    }
+    // Field names have at most 64 bytes, so we can escape on the stack
+    let mut escaped = [0; 64];
+    let prefix: &[u8] = if matches!(
+        key.chars().next().map(escape_key_char),
+        Some(b'_' | b'0'..=b'9')
+    ) {
+        // The prefix is shorter than the maximum length, so the name still
+        // starts with a valid letter after truncation.
+        b"ESCAPED_"
+    } else {
+        b""
+    };
+    let escaped_len = prefix.len() + key.chars().count();
+    let max_len = if hash_long_keys && 64 < escaped_len {
+        // Keep 57 bytes, and append an underscore and 6 hex digits
+        57
+    } else {
+        64
+    };
+    let mut len = 0;
+    for (target, byte) in escaped[..max_len].iter_mut().zip(
+        prefix
+            .iter()
+            .copied()
+            .chain(key.chars().map(escape_key_char)),
+    ) {
+        *target = byte;
+        len += 1;
+    }
+    if max_len < 64 {
+        write!(
+            &mut escaped[len..],
+            "_{:06X}",
+            fnv1a(key.as_bytes()) & 0xff_ffff
+        )
+        .unwrap();
+        len += 7;
+    }
+    debug_assert!(escaped[..len].is_ascii());
+    buffer.put(&escaped[..len]);
+}
+
+/// Escape `name` for use as a journal field name, like [`put_field_name`] does.
+pub fn escape_field_name(name: &str) -> String {
+    let mut escaped = Vec::new();
+    put_field_name(&mut escaped, FieldName::WriteEscaped(name));
+    // Escaping leaves only ASCII characters in the name
+    String::from_utf8(escaped).unwrap()
+}
+
+pub fn put_field_name<B: FieldBuffer + ?Sized>(buffer: &mut B, name: FieldName<'_>) {
     match name {
-        FieldName::WellFormed(name) => buffer.extend_from_slice(name.as_bytes()),
-        FieldName::WriteEscaped("") => buffer.extend_from_slice(b"EMPTY"),
-        // FIXME: We should try to find a way to do this with less allocations.
-        FieldName::WriteEscaped(name) => buffer.extend_from_slice(&escape_journal_key(name)),
+        FieldName::WellFormed(name) => {
+            debug_assert!(
+                is_valid_journal_field_name(name),
+                "well-formed field name {:?} is not valid",
+                name
+            );
+            buffer.put(name.as_bytes())
+        }
+        // Escaping leaves valid names unchanged, so copy them right away.
+        FieldName::WriteEscaped(name) | FieldName::WriteEscapedHashed(name)
+            if is_valid_journal_field_name(name) =>
+        {
+            buffer.put(name.as_bytes())
+        }
+        FieldName::WriteEscaped(name) => put_escaped_key(buffer, name, false),
+        FieldName::WriteEscapedHashed(name) => put_escaped_key(buffer, name, true),
     }
 }
 
 pub trait PutAsFieldValue {
-    fn put_field_value(self, buffer: &mut Vec<u8>);
+    fn put_field_value<B: FieldBuffer + ?Sized>(self, buffer: &mut B);
 }
 
 impl PutAsFieldValue for &[u8] {
-    fn put_field_value(self, buffer: &mut Vec<u8>) {
-        buffer.extend_from_slice(self)
+    fn put_field_value<B: FieldBuffer + ?Sized>(self, buffer: &mut B) {
+        buffer.put(self)
     }
 }
 
 impl PutAsFieldValue for &Arguments<'_> {
-    fn put_field_value(self, buffer: &mut Vec<u8>) {
+    fn put_field_value<B: FieldBuffer + ?Sized>(self, buffer: &mut B) {
         match self.as_str() {
-            Some(s) => buffer.extend_from_slice(s.as_bytes()),
+            Some(s) => buffer.put(s.as_bytes()),
             None => write!(buffer, "{}", self).unwrap(),
         }
     }
 }
 
-impl PutAsFieldValue for Value<'_> {
-    fn put_field_value(self, buffer: &mut Vec<u8>) {
+impl PutAsFieldValue for &Value<'_> {
+    fn put_field_value<B: FieldBuffer + ?Sized>(self, buffer: &mut B) {
         // TODO: We can probably write the value more efficiently by visiting it?
         write!(buffer, "{}", self).unwrap()
     }
 }
 
-pub fn put_field_length_encoded<V: PutAsFieldValue>(
-    buffer: &mut Vec<u8>,
+/// Write a length-encoded field, and return the length of the value.
+pub fn put_field_length_encoded<B: FieldBuffer + ?Sized, V: PutAsFieldValue>(
+    buffer: &mut B,
     name: FieldName<'_>,
     value: V,
-) {
+) -> usize {
     put_field_name(buffer, name);
-    buffer.push(b'\n');
+    buffer.put(b"\n");
     // Reserve the length tag
-    buffer.extend_from_slice(&[0; 8]);
-    let value_start = buffer.len();
+    buffer.put(&[0; 8]);
+    let value_start = buffer.written();
     value.put_field_value(buffer);
-    let value_end = buffer.len();
+    let value_end = buffer.written();
     // Fill the length tag
-    let length_bytes = ((value_end - value_start) as u64).to_le_bytes();
-    buffer[value_start - 8..value_start].copy_from_slice(&length_bytes);
-    buffer.push(b'\n');
+    let length = value_end - value_start;
+    buffer.overwrite(value_start - 8, &(length as u64).to_le_bytes());
+    buffer.put(b"\n");
+    length
 }
 
-pub fn put_field_bytes(buffer: &mut Vec<u8>, name: FieldName<'_>, value: &[u8]) {
-    if value.contains(&b'\n') {
+/// The value size from which [`put_field_bytes`] always length-encodes fields.
+///
+/// For all but small values, scanning the value for newlines takes longer than
+/// writing its length, and the eight bytes of the length do not matter.
+const LENGTH_ENCODED_MIN_SIZE: usize = 256;
+
+/// Write a field with `value`.
+///
+/// Write `NAME=value` if possible, and a length-encoded field if `value` has a
+/// newline, or if it's large, to avoid scanning it for newlines.
+pub fn put_field_bytes<B: FieldBuffer + ?Sized>(buffer: &mut B, name: FieldName<'_>, value: &[u8]) {
+    if LENGTH_ENCODED_MIN_SIZE <= value.len() || value.contains(&b'\n') {
         // Write as length encoded field
         put_field_length_encoded(buffer, name, value);
     } else {
         put_field_name(buffer, name);
-        buffer.push(b'=');
-        buffer.extend_from_slice(value);
-        buffer.push(b'\n');
+        buffer.put(b"=");
+        buffer.put(value);
+        buffer.put(b"\n");
     }
 }
 
+/// Write a field with the decimal digits of `value`.
+///
+/// Like `writeln!(buffer, "{}={}", name, value)`, but write the digits
+/// directly, without the formatting machinery, because every record has
+/// numeric fields.
+pub fn put_field_u64<B: FieldBuffer + ?Sized>(buffer: &mut B, name: FieldName<'_>, value: u64) {
+    // u64::MAX has 20 digits
+    let mut digits = [0; 20];
+    let mut start = digits.len();
+    let mut rest = value;
+    loop {
+        start -= 1;
+        digits[start] = b'0' + (rest % 10) as u8;
+        rest /= 10;
+        if rest == 0 {
+            break;
+        }
+    }
+    put_field_name(buffer, name);
+    buffer.put(b"=");
+    buffer.put(&digits[start..]);
+    buffer.put(b"\n");
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -135,6 +726,207 @@ mod tests {
         }
     }
 
+    /// A random key of up to 100 characters.
+    fn random_key<R: rand::Rng>(rng: &mut R) -> String {
+        use rand::distributions::{Alphanumeric, DistString};
+
+        let length = rng.gen_range(0..100);
+        // Mix arbitrary Unicode with ASCII, to cover both invalid
+        // characters and keys which only need partial escaping.
+        if rng.gen() {
+            (0..length).map(|_| rng.gen::<char>()).collect()
+        } else {
+            Alphanumeric.sample_string(rng, length)
+        }
+    }
+
+    #[test]
+    fn escape_journal_key_always_valid() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..10_000 {
+            let key = random_key(&mut rng);
+            let escaped = String::from_utf8(super::escape_journal_key(&key)).unwrap();
+            assert_eq!(
+                super::validate_field_name(&escaped),
+                Ok(()),
+                "escaped {:?} to invalid name {:?}",
+                key,
+                escaped
+            );
+        }
+
+        for key in [
+            "",
+            "_",
+            "1",
+            &"_".repeat(100),
+            &"1".repeat(100),
+            &"ö".repeat(100),
+        ] {
+            let escaped = String::from_utf8(super::escape_journal_key(key)).unwrap();
+            assert_eq!(super::validate_field_name(&escaped), Ok(()));
+        }
+    }
+
+    #[test]
+    fn escape_journal_key_idempotent() {
+        let mut rng = rand::thread_rng();
+        let keys = (0..10_000).map(|_| random_key(&mut rng)).chain(
+            [
+                "",
+                "_foo",
+                "1foo",
+                "ESCAPED__FOO",
+                "ESCAPED_1FOO",
+                "escaped_1foo",
+            ]
+            .iter()
+            .map(|s| s.to_string()),
+        );
+        for key in keys {
+            for escape in [super::escape_journal_key, super::escape_journal_key_hashed] {
+                let escaped = String::from_utf8(escape(&key)).unwrap();
+                assert_eq!(
+                    String::from_utf8(escape(&escaped)).unwrap(),
+                    escaped,
+                    "escaping {:?} twice changed it",
+                    key
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn escape_journal_key_hashed() {
+        for case in ["foo", "_foo", &"a".repeat(64)] {
+            assert_eq!(
+                super::escape_journal_key_hashed(case),
+                super::escape_journal_key(case)
+            );
+        }
+
+        let prefix = "a".repeat(64);
+        let first = format!("{}_first", prefix);
+        let second = format!("{}_second", prefix);
+        // Plain escaping truncates both keys to the same name
+        assert_eq!(
+            super::escape_journal_key(&first),
+            super::escape_journal_key(&second)
+        );
+
+        let first = String::from_utf8(super::escape_journal_key_hashed(&first)).unwrap();
+        let second = String::from_utf8(super::escape_journal_key_hashed(&second)).unwrap();
+        assert_ne!(first, second);
+        for escaped in [&first, &second] {
+            assert_eq!(escaped.len(), 64);
+            assert!(escaped.starts_with(&"A".repeat(57)));
+            assert_eq!(super::validate_field_name(escaped), Ok(()));
+        }
+        // The hash is stable
+        assert_eq!(first, format!("{}_{:06X}", "A".repeat(57), 0x75802c));
+    }
+
+    #[test]
+    fn validate_field_name() {
+        for case in &["FOO", "FOO_123", "F", &"F".repeat(64)] {
+            assert_eq!(super::validate_field_name(case), Ok(()));
+        }
+
+        let too_long = "F".repeat(65);
+        let cases = vec![
+            ("", InvalidFieldNameReason::Empty),
+            (too_long.as_str(), InvalidFieldNameReason::TooLong),
+            ("1FOO", InvalidFieldNameReason::StartsWithDigit),
+            ("_FOO", InvalidFieldNameReason::StartsWithUnderscore),
+            ("Foo", InvalidFieldNameReason::InvalidCharacter('o')),
+            ("HALLöCHEN", InvalidFieldNameReason::InvalidCharacter('ö')),
+        ];
+        for (name, reason) in cases {
+            let error = super::validate_field_name(name).unwrap_err();
+            assert_eq!(error.name(), name);
+            assert_eq!(error.reason(), reason);
+        }
+    }
+
+    #[test]
+    fn put_field_name_valid_names_unchanged() {
+        for name in ["FOO", "FOO_BAR", "F123", &"F".repeat(64)] {
+            assert!(super::is_valid_journal_field_name(name));
+            for field_name in [WriteEscaped(name), WriteEscapedHashed(name)] {
+                let mut buffer = Vec::new();
+                super::put_field_name(&mut buffer, field_name);
+                assert_eq!(buffer, name.as_bytes());
+                assert_eq!(buffer, super::escape_journal_key(name));
+            }
+        }
+        for name in ["", "foo", "_FOO", "1FOO", &"F".repeat(65)] {
+            assert!(!super::is_valid_journal_field_name(name));
+        }
+    }
+
+    #[test]
+    fn put_field_bytes_malicious_names() {
+        for name in ["FOO\nMESSAGE", "FOO=BAR", "MESSAGE=\n", "\n", "=", "FOO\0"] {
+            for field_name in [WriteEscaped(name), WriteEscapedHashed(name)] {
+                let mut buffer = Vec::new();
+                super::put_field_bytes(&mut buffer, field_name, b"value");
+                let (name, value) = std::str::from_utf8(&buffer)
+                    .unwrap()
+                    .split_once('=')
+                    .unwrap();
+                assert!(super::is_valid_journal_field_name(name), "{:?}", name);
+                assert_eq!(value, "value\n");
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "is not valid")]
+    fn put_field_name_invalid_well_formed() {
+        super::put_field_name(&mut Vec::new(), WellFormed("FOO\nBAR"));
+    }
+
+    #[test]
+    fn validate_payload() {
+        for case in [
+            &b""[..],
+            b"FOO=BAR\n",
+            b"FOO=BAR\nSPAM=\n",
+            b"FOO\n\x03\0\0\0\0\0\0\0B\nR\nSPAM=EGGS\n",
+        ] {
+            assert_eq!(super::validate_payload(case), Ok(()));
+        }
+
+        let cases = vec![
+            (&b"FOO=BAR"[..], 0, InvalidRawFieldsReason::MissingNewline),
+            (b"FOO=BAR\nSPAM", 8, InvalidRawFieldsReason::MissingNewline),
+            (b"FOO\n\x03\0\0", 0, InvalidRawFieldsReason::TruncatedValue),
+            (
+                b"FOO\n\x05\0\0\0\0\0\0\0BAR\n",
+                0,
+                InvalidRawFieldsReason::TruncatedValue,
+            ),
+            (
+                b"FOO\n\x02\0\0\0\0\0\0\0BAR\n",
+                0,
+                InvalidRawFieldsReason::MissingNewline,
+            ),
+        ];
+        for (blob, offset, reason) in cases {
+            let error = super::validate_payload(blob).unwrap_err();
+            assert_eq!(error.offset(), offset);
+            assert_eq!(error.reason(), &reason);
+        }
+
+        let error = super::validate_payload(b"FOO=BAR\nfoo=bar\n").unwrap_err();
+        assert_eq!(error.offset(), 8);
+        assert!(matches!(
+            error.reason(),
+            InvalidRawFieldsReason::InvalidFieldName(_)
+        ));
+    }
+
     #[test]
     fn put_field_length_encoded() {
         let mut buffer = Vec::new();
@@ -150,6 +942,18 @@ mod tests {
         assert_eq!(&buffer, b"FOO=BAR\n");
     }
 
+    #[test]
+    fn put_field_bytes_large() {
+        let value = vec![b'x'; LENGTH_ENCODED_MIN_SIZE];
+        let mut buffer = Vec::new();
+        super::put_field_bytes(&mut buffer, WellFormed("FOO"), &value[1..]);
+        super::put_field_bytes(&mut buffer, WellFormed("FOO"), &value);
+        assert_eq!(buffer[..4], *b"FOO=");
+        let large = &buffer[4 + value.len()..];
+        assert_eq!(large[..12], *b"FOO\n\x00\x01\0\0\0\0\0\0");
+        assert_eq!(large.len(), 12 + value.len() + 1);
+    }
+
     #[test]
     fn put_field_bytes_newline() {
         let mut buffer = Vec::new();
@@ -160,4 +964,60 @@ mod tests {
         );
         assert_eq!(&buffer, b"FOO\n\x12\0\0\0\0\0\0\0BAR\nSPAM_WITH_EGGS\n");
     }
+
+    #[test]
+    fn put_field_u64() {
+        for value in [0, 7, 10, 42, 1_000_000, u64::from(u32::MAX), u64::MAX] {
+            let mut buffer = Vec::new();
+            super::put_field_u64(&mut buffer, WellFormed("FOO"), value);
+            assert_eq!(buffer, format!("FOO={}\n", value).into_bytes());
+        }
+    }
+
+    #[test]
+    fn payload_fields() {
+        assert_eq!(
+            super::payload_fields(b"FOO=bar\nEMPTY=\nBAZ\n\x04\0\0\0\0\0\0\0q\nux\n"),
+            vec![
+                ("FOO".to_string(), b"bar".to_vec()),
+                ("EMPTY".to_string(), Vec::new()),
+                ("BAZ".to_string(), b"q\nux".to_vec()),
+            ]
+        );
+        assert_eq!(super::payload_fields(b""), Vec::new());
+    }
+
+    #[test]
+    fn replace_field() {
+        let mut blob = b"FOO=1\nBAR=2\nFOO=3\n".to_vec();
+        assert_eq!(super::replace_field(&mut blob, "FOO", b"a\nb"), 0..16);
+        assert_eq!(blob, b"FOO\n\x03\0\0\0\0\0\0\0a\nb\nBAR=2\n");
+        assert_eq!(super::replace_field(&mut blob, "FOO", b"4"), 0..6);
+        assert_eq!(blob, b"FOO=4\nBAR=2\n");
+        assert_eq!(super::replace_field(&mut blob, "BAZ", b"5"), 12..18);
+        assert_eq!(blob, b"FOO=4\nBAR=2\nBAZ=5\n");
+    }
+
+    #[test]
+    fn slice_buffer() {
+        let mut slice = [0; 32];
+        let mut buffer = SliceBuffer::new(&mut slice);
+        super::put_field_length_encoded(&mut buffer, WellFormed("FOO"), "BAR".as_bytes());
+        super::put_field_bytes(&mut buffer, WriteEscaped("foo bar"), "BAR".as_bytes());
+        assert_eq!(
+            buffer.finish().unwrap(),
+            b"FOO\n\x03\0\0\0\0\0\0\0BAR\nFOO_BAR=BAR\n"
+        );
+    }
+
+    #[test]
+    fn slice_buffer_overflow() {
+        let mut slice = [0; 8];
+        let mut buffer = SliceBuffer::new(&mut slice);
+        super::put_field_length_encoded(&mut buffer, WellFormed("FOO"), "BAR".as_bytes());
+        super::put_field_bytes(&mut buffer, WellFormed("FOO"), "BAR".as_bytes());
+        assert_eq!(buffer.finish().unwrap_err().required(), 16 + 8);
+        // The buffer keeps what fit
+        assert_eq!(&slice, b"FOO\n\0\0\0\0");
+    }
 }
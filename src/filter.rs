@@ -0,0 +1,137 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Filter records by target and level, with `RUST_LOG` syntax.
+
+use std::cmp::Reverse;
+
+use log::{LevelFilter, Metadata};
+
+/// Filter records by their target and level.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Filter {
+    /// The level for targets without a directive.
+    default: LevelFilter,
+    /// Levels for targets, sorted by descending length of the target.
+    directives: Vec<(String, LevelFilter)>,
+}
+
+impl Filter {
+    /// Parse a filter from `spec`.
+    ///
+    /// `spec` is a comma-separated list of directives, like `RUST_LOG` for
+    /// `env_logger`.  A directive is either a level, which applies to all
+    /// targets without a more specific directive, `target=level`, which
+    /// applies to `target` and all its submodules, or just `target`, which
+    /// enables all levels for `target`.  The last directive for a target wins;
+    /// the default level is `error`.
+    ///
+    /// Ignore directives with invalid levels, and empty directives.
+    pub fn parse(spec: &str) -> Self {
+        let mut default = LevelFilter::Error;
+        let mut directives: Vec<(String, LevelFilter)> = Vec::new();
+        for directive in spec.split(',').map(str::trim) {
+            let (target, level) = match directive.split_once('=') {
+                Some((target, level)) => match level.trim().parse() {
+                    Ok(level) => (Some(target.trim()), level),
+                    Err(_) => continue,
+                },
+                None => match directive.parse() {
+                    Ok(level) => (None, level),
+                    Err(_) => (Some(directive), LevelFilter::Trace),
+                },
+            };
+            match target {
+                None => default = level,
+                Some("") => continue,
+                Some(target) => {
+                    directives.retain(|(existing, _)| existing != target);
+                    directives.push((target.to_string(), level));
+                }
+            }
+        }
+        // Check the most specific target first
+        directives.sort_by_key(|(target, _)| Reverse(target.len()));
+        Self {
+            default,
+            directives,
+        }
+    }
+
+    /// The level for `target`.
+    fn level(&self, target: &str) -> LevelFilter {
+        self.directives
+            .iter()
+            .find(|(name, _)| {
+                target
+                    .strip_prefix(name.as_str())
+                    .map_or(false, |rest| rest.is_empty() || rest.starts_with("::"))
+            })
+            .map_or(self.default, |(_, level)| *level)
+    }
+
+    /// Whether this filter accepts records with `metadata`.
+    pub fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level(metadata.target())
+    }
+
+    /// The most verbose level this filter accepts for any target.
+    pub fn max_level(&self) -> LevelFilter {
+        self.directives
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, Ord::max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use log::Level;
+    use similar_asserts::assert_eq;
+
+    use super::*;
+
+    fn enabled(filter: &Filter, target: &str, level: Level) -> bool {
+        filter.enabled(&Metadata::builder().target(target).level(level).build())
+    }
+
+    #[test]
+    fn parse() {
+        let filter = Filter::parse("info,mycrate=debug,noisy=off");
+        assert_eq!(filter.max_level(), LevelFilter::Debug);
+        assert!(enabled(&filter, "other", Level::Info));
+        assert!(!enabled(&filter, "other", Level::Debug));
+        assert!(enabled(&filter, "mycrate", Level::Debug));
+        assert!(enabled(&filter, "mycrate::module", Level::Debug));
+        assert!(!enabled(&filter, "mycrate::module", Level::Trace));
+        assert!(!enabled(&filter, "mycrate_other", Level::Debug));
+        assert!(!enabled(&filter, "noisy", Level::Error));
+    }
+
+    #[test]
+    fn parse_most_specific_target_wins() {
+        let filter = Filter::parse("a=warn,a::b=trace");
+        assert!(!enabled(&filter, "a::c", Level::Info));
+        assert!(enabled(&filter, "a::b::c", Level::Trace));
+        assert!(!enabled(&filter, "other", Level::Warn));
+        assert!(enabled(&filter, "other", Level::Error));
+    }
+
+    #[test]
+    fn parse_target_without_level() {
+        let filter = Filter::parse("mycrate");
+        assert!(enabled(&filter, "mycrate", Level::Trace));
+        assert_eq!(filter.max_level(), LevelFilter::Trace);
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert_eq!(Filter::parse(",, foo=bogus ,=info"), Filter::parse(""));
+        assert_eq!(Filter::parse("").max_level(), LevelFilter::Error);
+    }
+}
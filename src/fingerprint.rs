@@ -0,0 +1,78 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Fingerprints of messages.
+
+use std::fmt::{Arguments, Write};
+
+/// The size of a fingerprint field with a name of `name_len` bytes.
+///
+/// The name, a newline, the length tag, 16 hex digits, and a trailing newline.
+pub fn fingerprint_field_size(name_len: usize) -> usize {
+    name_len + 1 + 8 + 16 + 1
+}
+
+/// The fingerprint of the formatted `message`.
+///
+/// Hash the UTF-8 bytes of the formatted message with 64-bit FNV-1a.  The hash
+/// is stable across processes, platforms, and Rust versions, so that the same
+/// message always has the same fingerprint.
+pub fn message_fingerprint(message: &Arguments) -> u64 {
+    let mut hasher = Fnv1a64(0xcbf2_9ce4_8422_2325);
+    match message.as_str() {
+        Some(message) => hasher.write_bytes(message.as_bytes()),
+        // Writing to the hasher never fails
+        None => std::fmt::write(&mut hasher, *message).unwrap(),
+    }
+    hasher.0
+}
+
+/// A 64-bit FNV-1a hasher for formatted text.
+struct Fnv1a64(u64);
+
+impl Fnv1a64 {
+    fn write_bytes(&mut self, data: &[u8]) {
+        for byte in data {
+            self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+}
+
+impl Write for Fnv1a64 {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        self.write_bytes(s.as_bytes());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use similar_asserts::assert_eq;
+
+    #[test]
+    fn message_fingerprint() {
+        // Reference values of 64-bit FNV-1a
+        assert_eq!(
+            super::message_fingerprint(&format_args!("")),
+            0xcbf2_9ce4_8422_2325
+        );
+        assert_eq!(
+            super::message_fingerprint(&format_args!("a")),
+            0xaf63_dc4c_8601_ec8c
+        );
+        assert_eq!(
+            super::message_fingerprint(&format_args!("foobar")),
+            0x8594_4171_f739_67e8
+        );
+        // Formatted messages hash like their text
+        assert_eq!(
+            super::message_fingerprint(&format_args!("foo{}", "bar")),
+            0x8594_4171_f739_67e8
+        );
+    }
+}
@@ -0,0 +1,981 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Flatten structured values into separate fields.
+
+use std::fmt::Display;
+
+use log::kv::Value;
+use serde::ser::{
+    Impossible, Serialize, SerializeMap, SerializeSeq, SerializeStruct, SerializeStructVariant,
+    SerializeTuple, SerializeTupleStruct, SerializeTupleVariant, Serializer,
+};
+
+use crate::fields::{put_field_length_encoded, FieldBuffer, FieldName};
+
+/// How deep to flatten nested values.
+///
+/// We omit values nested deeper.
+pub const MAX_DEPTH: usize = 8;
+
+/// Write `value` to `buffer`, flattening nested values into separate fields.
+///
+/// Write a field `name` for a plain value, and a field `name_child` for every
+/// child of a structured value, recursively, e.g. `USER_ID` and `USER_NAME`
+/// for a `user` struct with `id` and `name` fields.  Join names with `_`,
+/// and then escape them, with hashing if `hash_long_keys`.
+///
+/// Omit values nested deeper than [`MAX_DEPTH`], and entries of maps whose
+/// keys are neither strings nor numbers.
+pub fn put_flattened<B: FieldBuffer + ?Sized>(
+    buffer: &mut B,
+    name: &str,
+    value: &Value,
+    hash_long_keys: bool,
+) {
+    let flatten = Flatten {
+        buffer,
+        name,
+        depth: 0,
+        hash_long_keys,
+    };
+    // We never fail ourselves, and the value of a record can't do anything
+    // about a failed serialization either.
+    let _ = value.serialize(flatten);
+}
+
+/// Write every element of a sequence `value` as a separate field `name`.
+///
+/// Write at most `max_elements` elements, if any, and flatten structured
+/// elements like [`put_flattened`].  Return the number of written and
+/// dropped elements, or `None` and write nothing if `value` is no sequence.
+pub fn put_sequence<B: FieldBuffer + ?Sized>(
+    buffer: &mut B,
+    name: &str,
+    value: &Value,
+    hash_long_keys: bool,
+    max_elements: Option<usize>,
+) -> Option<(usize, usize)> {
+    value
+        .serialize(Sequence {
+            buffer,
+            name,
+            hash_long_keys,
+            max_elements,
+        })
+        .ok()
+}
+
+/// An error while flattening a value.
+#[derive(Debug)]
+pub struct Error(String);
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl serde::ser::Error for Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        Self(msg.to_string())
+    }
+}
+
+struct Flatten<'a, B: ?Sized> {
+    buffer: &'a mut B,
+    name: &'a str,
+    depth: usize,
+    hash_long_keys: bool,
+}
+
+impl<'a, B: FieldBuffer + ?Sized> Flatten<'a, B> {
+    /// Write a field with this name and `value`.
+    fn put<V: Display>(self, value: V) -> Result<(), Error> {
+        let name = if self.hash_long_keys {
+            FieldName::WriteEscapedHashed(self.name)
+        } else {
+            FieldName::WriteEscaped(self.name)
+        };
+        put_field_length_encoded(self.buffer, name, &format_args!("{}", value));
+        Ok(())
+    }
+
+    /// Flatten the children of this value.
+    fn children(self, variant: Option<&str>) -> Children<'a, B> {
+        let name = match variant {
+            Some(variant) => format!("{}_{}", self.name, variant),
+            None => self.name.to_string(),
+        };
+        Children {
+            buffer: self.buffer,
+            name,
+            depth: self.depth + 1,
+            hash_long_keys: self.hash_long_keys,
+            index: 0,
+            key: None,
+        }
+    }
+}
+
+/// Flatten the children of a structured value.
+struct Children<'a, B: ?Sized> {
+    buffer: &'a mut B,
+    /// The name of the parent.
+    name: String,
+    /// The depth of the children.
+    depth: usize,
+    hash_long_keys: bool,
+    /// The index of the next element of a sequence.
+    index: usize,
+    /// The key of the next map value, if supported.
+    key: Option<String>,
+}
+
+impl<B: FieldBuffer + ?Sized> Children<'_, B> {
+    /// Write the child with `name`.
+    fn put<T: Serialize + ?Sized, N: Display>(&mut self, name: N, value: &T) -> Result<(), Error> {
+        if MAX_DEPTH < self.depth {
+            return Ok(());
+        }
+        let name = format!("{}_{}", self.name, name);
+        value.serialize(Flatten {
+            buffer: &mut *self.buffer,
+            name: &name,
+            depth: self.depth,
+            hash_long_keys: self.hash_long_keys,
+        })
+    }
+
+    /// Write the next element of a sequence.
+    fn put_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        let index = self.index;
+        self.index += 1;
+        self.put(index, value)
+    }
+}
+
+impl<'a, B: FieldBuffer + ?Sized> Serializer for Flatten<'a, B> {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = Children<'a, B>;
+    type SerializeTuple = Children<'a, B>;
+    type SerializeTupleStruct = Children<'a, B>;
+    type SerializeTupleVariant = Children<'a, B>;
+    type SerializeMap = Children<'a, B>;
+    type SerializeStruct = Children<'a, B>;
+    type SerializeStructVariant = Children<'a, B>;
+
+    fn serialize_bool(self, v: bool) -> Result<(), Error> {
+        self.put(v)
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<(), Error> {
+        self.put(v)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<(), Error> {
+        self.put(v)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<(), Error> {
+        self.put(v)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<(), Error> {
+        self.put(v)
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<(), Error> {
+        self.put(v)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<(), Error> {
+        self.put(v)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<(), Error> {
+        self.put(v)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<(), Error> {
+        self.put(v)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<(), Error> {
+        self.put(v)
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<(), Error> {
+        self.put(v)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<(), Error> {
+        self.put(v)
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<(), Error> {
+        self.put(v)
+    }
+
+    fn serialize_char(self, v: char) -> Result<(), Error> {
+        self.put(v)
+    }
+
+    fn serialize_str(self, v: &str) -> Result<(), Error> {
+        self.put(v)
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<(), Error> {
+        let name = if self.hash_long_keys {
+            FieldName::WriteEscapedHashed(self.name)
+        } else {
+            FieldName::WriteEscaped(self.name)
+        };
+        put_field_length_encoded(self.buffer, name, v);
+        Ok(())
+    }
+
+    /// Omit missing values.
+    fn serialize_none(self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), Error> {
+        value.serialize(self)
+    }
+
+    /// Omit empty values.
+    fn serialize_unit(self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<(), Error> {
+        self.put(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.children(None).put(variant, value)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Children<'a, B>, Error> {
+        Ok(self.children(None))
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Children<'a, B>, Error> {
+        Ok(self.children(None))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Children<'a, B>, Error> {
+        Ok(self.children(None))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Children<'a, B>, Error> {
+        Ok(self.children(Some(variant)))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Children<'a, B>, Error> {
+        Ok(self.children(None))
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Children<'a, B>, Error> {
+        Ok(self.children(None))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Children<'a, B>, Error> {
+        Ok(self.children(Some(variant)))
+    }
+}
+
+impl<B: FieldBuffer + ?Sized> SerializeSeq for Children<'_, B> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.put_element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl<B: FieldBuffer + ?Sized> SerializeTuple for Children<'_, B> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.put_element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl<B: FieldBuffer + ?Sized> SerializeTupleStruct for Children<'_, B> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.put_element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl<B: FieldBuffer + ?Sized> SerializeTupleVariant for Children<'_, B> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.put_element(value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl<B: FieldBuffer + ?Sized> SerializeMap for Children<'_, B> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Error> {
+        self.key = key.serialize(MapKey).ok();
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        match self.key.take() {
+            Some(key) => self.put(key, value),
+            // Skip values of unsupported keys
+            None => Ok(()),
+        }
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl<B: FieldBuffer + ?Sized> SerializeStruct for Children<'_, B> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.put(key, value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl<B: FieldBuffer + ?Sized> SerializeStructVariant for Children<'_, B> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.put(key, value)
+    }
+
+    fn end(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Write the elements of sequences as repeated fields, and fail for all other
+/// values.
+struct Sequence<'a, B: ?Sized> {
+    buffer: &'a mut B,
+    name: &'a str,
+    hash_long_keys: bool,
+    max_elements: Option<usize>,
+}
+
+impl<B: ?Sized> Sequence<'_, B> {
+    fn unsupported<T>() -> Result<T, Error> {
+        Err(Error("not a sequence".to_string()))
+    }
+}
+
+/// Write the elements of a sequence.
+struct Elements<'a, B: ?Sized> {
+    buffer: &'a mut B,
+    name: &'a str,
+    hash_long_keys: bool,
+    max_elements: Option<usize>,
+    /// The number of written elements.
+    written: usize,
+    /// The number of elements dropped because of `max_elements`.
+    dropped: usize,
+}
+
+impl<B: FieldBuffer + ?Sized> Elements<'_, B> {
+    fn put<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        if self.max_elements.map_or(false, |max| max <= self.written) {
+            self.dropped += 1;
+            return Ok(());
+        }
+        self.written += 1;
+        value.serialize(Flatten {
+            buffer: &mut *self.buffer,
+            name: self.name,
+            depth: 1,
+            hash_long_keys: self.hash_long_keys,
+        })
+    }
+}
+
+impl<B: FieldBuffer + ?Sized> SerializeSeq for Elements<'_, B> {
+    type Ok = (usize, usize);
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.put(value)
+    }
+
+    fn end(self) -> Result<(usize, usize), Error> {
+        Ok((self.written, self.dropped))
+    }
+}
+
+impl<B: FieldBuffer + ?Sized> SerializeTuple for Elements<'_, B> {
+    type Ok = (usize, usize);
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.put(value)
+    }
+
+    fn end(self) -> Result<(usize, usize), Error> {
+        Ok((self.written, self.dropped))
+    }
+}
+
+impl<'a, B: FieldBuffer + ?Sized> Serializer for Sequence<'a, B> {
+    type Ok = (usize, usize);
+    type Error = Error;
+    type SerializeSeq = Elements<'a, B>;
+    type SerializeTuple = Elements<'a, B>;
+    type SerializeTupleStruct = Impossible<(usize, usize), Error>;
+    type SerializeTupleVariant = Impossible<(usize, usize), Error>;
+    type SerializeMap = Impossible<(usize, usize), Error>;
+    type SerializeStruct = Impossible<(usize, usize), Error>;
+    type SerializeStructVariant = Impossible<(usize, usize), Error>;
+
+    fn serialize_bool(self, _v: bool) -> Result<(usize, usize), Error> {
+        Self::unsupported()
+    }
+
+    fn serialize_i8(self, _v: i8) -> Result<(usize, usize), Error> {
+        Self::unsupported()
+    }
+
+    fn serialize_i16(self, _v: i16) -> Result<(usize, usize), Error> {
+        Self::unsupported()
+    }
+
+    fn serialize_i32(self, _v: i32) -> Result<(usize, usize), Error> {
+        Self::unsupported()
+    }
+
+    fn serialize_i64(self, _v: i64) -> Result<(usize, usize), Error> {
+        Self::unsupported()
+    }
+
+    fn serialize_u8(self, _v: u8) -> Result<(usize, usize), Error> {
+        Self::unsupported()
+    }
+
+    fn serialize_u16(self, _v: u16) -> Result<(usize, usize), Error> {
+        Self::unsupported()
+    }
+
+    fn serialize_u32(self, _v: u32) -> Result<(usize, usize), Error> {
+        Self::unsupported()
+    }
+
+    fn serialize_u64(self, _v: u64) -> Result<(usize, usize), Error> {
+        Self::unsupported()
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<(usize, usize), Error> {
+        Self::unsupported()
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<(usize, usize), Error> {
+        Self::unsupported()
+    }
+
+    fn serialize_char(self, _v: char) -> Result<(usize, usize), Error> {
+        Self::unsupported()
+    }
+
+    fn serialize_str(self, _v: &str) -> Result<(usize, usize), Error> {
+        Self::unsupported()
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<(usize, usize), Error> {
+        Self::unsupported()
+    }
+
+    fn serialize_none(self) -> Result<(usize, usize), Error> {
+        Self::unsupported()
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(usize, usize), Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(usize, usize), Error> {
+        Self::unsupported()
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(usize, usize), Error> {
+        Self::unsupported()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<(usize, usize), Error> {
+        Self::unsupported()
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(usize, usize), Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<(usize, usize), Error> {
+        Self::unsupported()
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Elements<'a, B>, Error> {
+        Ok(Elements {
+            buffer: self.buffer,
+            name: self.name,
+            hash_long_keys: self.hash_long_keys,
+            max_elements: self.max_elements,
+            written: 0,
+            dropped: 0,
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Elements<'a, B>, Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, Error> {
+        Self::unsupported()
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Error> {
+        Self::unsupported()
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Error> {
+        Self::unsupported()
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, Error> {
+        Self::unsupported()
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Error> {
+        Self::unsupported()
+    }
+}
+
+/// Serialize map keys to strings.
+///
+/// Support strings, chars, numbers, and booleans as keys, and fail for all
+/// other keys.
+struct MapKey;
+
+impl MapKey {
+    fn unsupported<T>() -> Result<T, Error> {
+        Err(Error("unsupported map key".to_string()))
+    }
+}
+
+impl Serializer for MapKey {
+    type Ok = String;
+    type Error = Error;
+    type SerializeSeq = Impossible<String, Error>;
+    type SerializeTuple = Impossible<String, Error>;
+    type SerializeTupleStruct = Impossible<String, Error>;
+    type SerializeTupleVariant = Impossible<String, Error>;
+    type SerializeMap = Impossible<String, Error>;
+    type SerializeStruct = Impossible<String, Error>;
+    type SerializeStructVariant = Impossible<String, Error>;
+
+    fn serialize_bool(self, v: bool) -> Result<String, Error> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<String, Error> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<String, Error> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<String, Error> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<String, Error> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<String, Error> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<String, Error> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<String, Error> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<String, Error> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<String, Error> {
+        Self::unsupported()
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<String, Error> {
+        Self::unsupported()
+    }
+
+    fn serialize_char(self, v: char) -> Result<String, Error> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_str(self, v: &str) -> Result<String, Error> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<String, Error> {
+        Self::unsupported()
+    }
+
+    fn serialize_none(self) -> Result<String, Error> {
+        Self::unsupported()
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<String, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<String, Error> {
+        Self::unsupported()
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<String, Error> {
+        Self::unsupported()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<String, Error> {
+        Ok(variant.to_string())
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<String, Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<String, Error> {
+        Self::unsupported()
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Error> {
+        Self::unsupported()
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Error> {
+        Self::unsupported()
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, Error> {
+        Self::unsupported()
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Error> {
+        Self::unsupported()
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Error> {
+        Self::unsupported()
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, Error> {
+        Self::unsupported()
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Error> {
+        Self::unsupported()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use serde::Serialize;
+    use similar_asserts::assert_eq;
+
+    use super::*;
+
+    #[derive(Serialize)]
+    struct User {
+        id: u64,
+        name: &'static str,
+        groups: Vec<&'static str>,
+        email: Option<&'static str>,
+    }
+
+    fn flatten(name: &str, value: &Value) -> String {
+        let mut buffer = Vec::new();
+        put_flattened(&mut buffer, name, value, false);
+        strip_length_tags(&buffer)
+    }
+
+    fn strip_length_tags(buffer: &[u8]) -> String {
+        let mut fields = Vec::new();
+        let mut rest = buffer;
+        while let Some(newline) = rest.iter().position(|b| *b == b'\n') {
+            let name = String::from_utf8(rest[..newline].to_vec()).unwrap();
+            let length = u64::from_le_bytes(rest[newline + 1..newline + 9].try_into().unwrap());
+            let value_start = newline + 9;
+            let value_end = value_start + length as usize;
+            let value = String::from_utf8(rest[value_start..value_end].to_vec()).unwrap();
+            fields.push(format!("{}={}", name, value));
+            rest = &rest[value_end + 1..];
+        }
+        fields.join("\n")
+    }
+
+    #[test]
+    fn flatten_struct() {
+        let user = User {
+            id: 5,
+            name: "x",
+            groups: vec!["wheel", "users"],
+            email: None,
+        };
+        assert_eq!(
+            flatten("USER", &Value::from_serde(&user)),
+            "USER_ID=5\nUSER_NAME=x\nUSER_GROUPS_0=wheel\nUSER_GROUPS_1=users"
+        );
+    }
+
+    #[test]
+    fn flatten_plain_value() {
+        assert_eq!(flatten("FOO", &Value::from(42)), "FOO=42");
+        assert_eq!(flatten("FOO", &Value::from("bar")), "FOO=bar");
+        assert_eq!(
+            flatten("FOO", &Value::from_display(&format_args!("a{}", 1))),
+            "FOO=a1"
+        );
+    }
+
+    #[test]
+    fn flatten_map() {
+        let mut map = BTreeMap::new();
+        map.insert("first key", 1);
+        map.insert("second", 2);
+        assert_eq!(
+            flatten("MAP", &Value::from_serde(&map)),
+            "MAP_FIRST_KEY=1\nMAP_SECOND=2"
+        );
+        let mut map = BTreeMap::new();
+        map.insert((1, 2), 1);
+        assert_eq!(flatten("MAP", &Value::from_serde(&map)), "");
+    }
+
+    #[test]
+    fn put_sequence() {
+        let tags = vec!["a", "b", "c"];
+        let mut buffer = Vec::new();
+        let counts =
+            super::put_sequence(&mut buffer, "TAG", &Value::from_serde(&tags), false, None);
+        assert_eq!(counts, Some((3, 0)));
+        assert_eq!(strip_length_tags(&buffer), "TAG=a\nTAG=b\nTAG=c");
+
+        let mut buffer = Vec::new();
+        let counts = super::put_sequence(
+            &mut buffer,
+            "TAG",
+            &Value::from_serde(&tags),
+            false,
+            Some(2),
+        );
+        assert_eq!(counts, Some((2, 1)));
+        assert_eq!(strip_length_tags(&buffer), "TAG=a\nTAG=b");
+
+        let users = vec![User {
+            id: 5,
+            name: "x",
+            groups: vec![],
+            email: None,
+        }];
+        let mut buffer = Vec::new();
+        let counts =
+            super::put_sequence(&mut buffer, "USER", &Value::from_serde(&users), false, None);
+        assert_eq!(counts, Some((1, 0)));
+        assert_eq!(strip_length_tags(&buffer), "USER_ID=5\nUSER_NAME=x");
+    }
+
+    #[test]
+    fn put_sequence_not_a_sequence() {
+        for value in [Value::from("a"), Value::from(42), Value::from_serde(&"a")] {
+            let mut buffer = Vec::new();
+            assert_eq!(
+                super::put_sequence(&mut buffer, "TAG", &value, false, None),
+                None
+            );
+            assert!(buffer.is_empty());
+        }
+    }
+
+    #[test]
+    fn flatten_max_depth() {
+        let nested = vec![vec![vec![vec![vec![vec![vec![vec![vec![vec![1]]]]]]]]]];
+        assert_eq!(flatten("N", &Value::from_serde(&nested)), "");
+        let nested = vec![vec![vec![vec![vec![vec![vec![vec![1]]]]]]]];
+        assert_eq!(
+            flatten("N", &Value::from_serde(&nested)),
+            "N_0_0_0_0_0_0_0_0=1"
+        );
+    }
+}
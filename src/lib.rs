@@ -57,21 +57,125 @@
 //!
 //! You can display these extra fields with `journalctl --output=verbose` and extract them with any of the structured
 //! output formats of `journalctl`, e.g. `journalctl --output=json`.
+//!
+//! ## System, user, and namespace journals
+//!
+//! By default the logger sends records to the socket of the system journal
+//! (see [`SYSTEM_JOURNAL_SOCKET`]), which is the only socket of the default
+//! journald instance.  There is no separate socket for the journal of a user:
+//! journald itself stores records of user processes in the journal of their
+//! user, depending on `SplitMode=` in `journald.conf(5)`, so that
+//! `journalctl --user` shows them.
+//!
+//! Separate journald instances for journal namespaces (see `LogNamespace=`
+//! in `systemd.exec(5)`) listen on separate sockets; use
+//! [`JournalLog::new_for_namespace`] to log to a namespace, and
+//! [`JournalLog::new_with_socket_path`] for any other journald socket.
+//!
+//! ## Without a global logger
+//!
+//! [`JournalLog::install`] makes the logger the global logger of the process.
+//! Alternatively, use a [`JournalLog`] directly, e.g. to send only specific
+//! records to the journal while another global logger handles all other
+//! records, or to use differently configured loggers in tests.
+//! [`JournalLog`] is [`Send`] and [`Sync`], so it can be shared across
+//! threads in an [`Arc`]; [`JournalLog::log_record`] logs a single record,
+//! and the logger also works as `&dyn Log`:
+//!
+//! ```rust
+//! use std::sync::Arc;
+//! use log::{Level, Log, Record};
+//! use systemd_journal_logger::JournalLog;
+//!
+//! let logger = Arc::new(JournalLog::new().unwrap());
+//! let record = Record::builder()
+//!     .level(Level::Info)
+//!     .args(format_args!("Only to the journal"))
+//!     .build();
+//! logger.log_record(&record).unwrap();
+//!
+//! let log: &dyn Log = logger.as_ref();
+//! log.log(&record);
+//! ```
+//!
+//! ## Static max level
+//!
+//! Use the static max level features of [`log`], e.g. `max_level_info` or
+//! `release_max_level_info`, to discard verbose records at compile time; they
+//! remove logging statements entirely, including the evaluation of their
+//! arguments.  Enable them in the binary, not in libraries: Cargo unifies
+//! features across the whole build, so these features apply to all crates
+//! which log.
 
 #![deny(warnings, missing_docs, clippy::all)]
-#![forbid(unsafe_code)]
+// The libc backend needs unsafe code for system calls, see the sys module.
+#![cfg_attr(not(feature = "libc-backend"), forbid(unsafe_code))]
+#![cfg_attr(feature = "libc-backend", deny(unsafe_code))]
 
+use std::fmt::Arguments;
 use std::io::prelude::*;
-use std::os::fd::AsFd;
+use std::io::IoSlice;
+use std::os::fd::{AsFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use background::BackgroundSender;
 use client::JournalClient;
-use log::kv::{Error, Key, Value, VisitSource};
-use log::{Level, Log, Metadata, Record, SetLoggerError};
+use drop_summary::{DropCounts, DropSummaryThread};
+use log::kv::{Error, Key, ToValue, Value, VisitSource};
+use log::{Level, LevelFilter, Log, Metadata, Record, RecordBuilder, SetLoggerError};
+use sampling::Sampling;
+use transport::{SlotOrClient, TransportSlot};
 
+mod background;
+mod catalog;
 mod client;
+mod container;
+mod context;
+mod drop_summary;
 mod fields;
+#[cfg(feature = "env_filter")]
+mod filter;
+mod fingerprint;
+#[cfg(feature = "flatten_structured")]
+mod flatten;
+mod message_id;
+mod record_filter;
+mod sampling;
+mod standard_fields;
+mod stream;
+mod sys;
+mod syslog_identifier;
+mod tee;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod timestamp;
+mod transport;
+mod unit_name;
 
+pub use catalog::{Catalog, UnknownCatalogEvent};
+pub use client::{LargePayloadStrategy, SYSTEM_JOURNAL_SOCKET};
+pub use context::{pop_context, push_context};
+pub use drop_summary::DROP_SUMMARY_MESSAGE_ID;
 use fields::*;
+pub use fields::{
+    validate_payload, InvalidFieldName, InvalidFieldNameReason, InvalidRawFields,
+    InvalidRawFieldsReason, Overflow, ReservedFieldPolicy,
+};
+pub use message_id::MessageId;
+pub use record_filter::RecordFilter;
+pub use standard_fields::StandardFields;
+pub use stream::StreamBackend;
+pub use syslog_identifier::{
+    sanitize_syslog_identifier, InvalidSyslogIdentifier, InvalidSyslogIdentifierReason,
+    MAX_SYSLOG_IDENTIFIER_LEN,
+};
+pub use tee::TeeLogger;
+pub use transport::JournalTransport;
+pub use unit_name::{InvalidUnitName, InvalidUnitNameReason};
 
 /// Whether the current process is directly connected to the systemd journal.
 ///
@@ -79,8 +183,8 @@ use fields::*;
 /// file descriptor match the value of `$JOURNAL_STREAM` (see `systemd.exec(5)`).
 /// Otherwise, return `false`.
 pub fn connected_to_journal() -> bool {
-    rustix::fs::fstat(std::io::stderr().as_fd())
-        .map(|stat| format!("{}:{}", stat.st_dev, stat.st_ino))
+    sys::device_and_inode(std::io::stderr().as_fd())
+        .map(|(device, inode)| format!("{}:{}", device, inode))
         .ok()
         .and_then(|stderr| {
             std::env::var_os("JOURNAL_STREAM").map(|s| s.to_string_lossy() == stderr.as_str())
@@ -97,11 +201,364 @@ pub fn current_exe_identifier() -> Option<String> {
     Some(executable.file_name()?.to_string_lossy().into_owned())
 }
 
-struct WriteKeyValues<'a>(&'a mut Vec<u8>);
+/// The syslog identifier if neither the executable nor `argv[0]` has a name.
+pub const UNKNOWN_SYSLOG_IDENTIFIER: &str = "unknown";
+
+/// Create the default syslog identifier of the current process.
+///
+/// Use the name of the current executable (see [`current_exe_identifier`]),
+/// and fall back to the name this process was invoked with (see
+/// [`argv0_identifier`]), e.g. if the executable was deleted, or if a sandbox
+/// hides `/proc`.  If both fail, return [`UNKNOWN_SYSLOG_IDENTIFIER`].
+pub fn default_identifier() -> String {
+    fallback_identifier(current_exe_identifier, argv0_identifier)
+}
+
+/// The identifier from `first`, else from `second`, else [`UNKNOWN_SYSLOG_IDENTIFIER`].
+fn fallback_identifier<A, B>(first: A, second: B) -> String
+where
+    A: FnOnce() -> Option<String>,
+    B: FnOnce() -> Option<String>,
+{
+    first()
+        .or_else(second)
+        .unwrap_or_else(|| UNKNOWN_SYSLOG_IDENTIFIER.to_string())
+}
+
+/// Create a journal log instance with the package name of the caller as syslog
+/// identifier.
+///
+/// Expand to [`JournalLog::new_with_package_identifier`] with
+/// `CARGO_PKG_NAME` of the package which invokes this macro, i.e. use the name
+/// of the package instead of the name of the executable as syslog identifier,
+/// if built with Cargo:
+///
+/// ```rust
+/// use systemd_journal_logger::journal_log;
+///
+/// journal_log!().unwrap().install().unwrap();
+/// log::set_max_level(log::LevelFilter::Info);
+/// log::info!("Logged with the name of the package");
+/// ```
+#[macro_export]
+macro_rules! journal_log {
+    () => {
+        $crate::JournalLog::new_with_package_identifier(::std::option_env!("CARGO_PKG_NAME"))
+    };
+}
+
+/// Create a syslog identifier from the name this process was invoked with.
+///
+/// Use the file name of `argv[0]`, i.e. strip all leading directories.  Unlike
+/// [`current_exe_identifier`] this returns the name of a symlink to the
+/// executable, e.g. the name of the applet for multi-call binaries such as
+/// busybox, and doesn't need to ask the kernel for the executable.
+///
+/// Return `None` if the process has no `argv[0]`, or if `argv[0]` has no file
+/// name, e.g. if it's empty.
+pub fn argv0_identifier() -> Option<String> {
+    let argv0 = std::env::args_os().next()?;
+    Some(
+        Path::new(&argv0)
+            .file_name()?
+            .to_string_lossy()
+            .into_owned(),
+    )
+}
+
+/// Set the source location of the record `builder` builds.
+///
+/// Set the `file`, `line`, and `module` of the record, which the logger writes
+/// to `CODE_FILE`, `CODE_LINE`, and `CODE_MODULE`.  Use this function in
+/// logging layers which build records from their own events, to keep the
+/// location of the original call site instead of the location of the layer:
+///
+/// ```rust
+/// use log::{Level, Log, Record};
+/// use systemd_journal_logger::{with_location, JournalLog};
+///
+/// /// An event from another logging framework.
+/// struct Event {
+///     message: String,
+///     file: &'static str,
+///     line: u32,
+///     module: &'static str,
+/// }
+///
+/// fn forward(logger: &JournalLog, event: &Event) {
+///     logger.log(
+///         &with_location(&mut Record::builder(), event.file, event.line, event.module)
+///             .level(Level::Info)
+///             .args(format_args!("{}", event.message))
+///             .build(),
+///     );
+/// }
+///
+/// forward(&JournalLog::new().unwrap(), &Event {
+///     message: "Hello".to_string(),
+///     file: file!(),
+///     line: line!(),
+///     module: module_path!(),
+/// });
+/// ```
+///
+/// To forward an existing record use [`Record::to_builder`] instead, which
+/// keeps the location of the record.
+pub fn with_location<'a, 'b>(
+    builder: &'b mut RecordBuilder<'a>,
+    file: &'a str,
+    line: u32,
+    module: &'a str,
+) -> &'b mut RecordBuilder<'a> {
+    builder
+        .file(Some(file))
+        .line(Some(line))
+        .module_path(Some(module))
+}
+
+/// The journal priority for `level`.
+///
+/// This is the mapping of [`JournalLog`] (see "Log levels and Priorities"),
+/// for code which wraps or extends the logger and needs the priority of a
+/// level, e.g. to query the journal:
+///
+/// ```rust
+/// use log::Level;
+/// use systemd_journal_logger::priority_for_level;
+///
+/// assert_eq!(priority_for_level(Level::Error), 3);
+/// assert_eq!(priority_for_level(Level::Trace), 7);
+/// ```
+pub fn priority_for_level(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 5,
+        Level::Debug => 6,
+        Level::Trace => 7,
+    }
+}
+
+/// Where to take the syslog identifier of a logger from.
+///
+/// See [`JournalLog::with_syslog_identifier_from`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum IdentifierSource {
+    /// The file name of the current executable, see [`current_exe_identifier`].
+    ///
+    /// This is the default identifier of [`JournalLog::new`].
+    #[default]
+    CurrentExe,
+    /// The file name of `argv[0]`, see [`argv0_identifier`].
+    Argv0,
+}
+
+impl IdentifierSource {
+    /// Get the identifier from this source.
+    ///
+    /// Return `None` if this source has no identifier.
+    pub fn identifier(self) -> Option<String> {
+        match self {
+            IdentifierSource::CurrentExe => current_exe_identifier(),
+            IdentifierSource::Argv0 => argv0_identifier(),
+        }
+    }
+}
+
+/// How long [`Log::flush`] waits for the socket to journald.
+pub const FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The key of a record key-value which suppresses the extra fields of the logger.
+///
+/// See "Custom fields and structured record fields" in [`JournalLog`].
+const SKIP_EXTRA_FIELDS: &str = "__skip_extra_fields";
+
+/// The key of a record key-value with the name of the calling function.
+///
+/// The logger writes the value of this key to the standard `CODE_FUNC` field.
+const CODE_FUNC: &str = "code_func";
+
+/// The key of a record key-value with the time of the event of the record.
+///
+/// The logger writes the value of this key, in microseconds since the epoch,
+/// to the `SOURCE_REALTIME_TIMESTAMP` and `SYSLOG_TIMESTAMP` fields.
+const SOURCE_REALTIME_TIMESTAMP: &str = "source_realtime_timestamp";
+
+/// The time of the event of `record`, if given with the reserved key
+/// [`SOURCE_REALTIME_TIMESTAMP`].
+fn record_timestamp(record: &Record) -> Option<SystemTime> {
+    record
+        .key_values()
+        .get(Key::from_str(SOURCE_REALTIME_TIMESTAMP))
+        .and_then(|value| value.to_u64())
+        .map(|micros| UNIX_EPOCH + Duration::from_micros(micros))
+}
+
+/// The key of a record key-value with the syslog severity of the record.
+///
+/// The logger writes the priority of a known severity name in the value of
+/// this key to the `PRIORITY` field, instead of the priority of the level.
+const SEVERITY: &str = "severity";
+
+/// The priority of the syslog severity `name`, ignoring case, if known.
+fn severity_priority(name: &str) -> Option<&'static [u8]> {
+    const SEVERITIES: [(&str, &[u8]); 14] = [
+        ("emerg", b"0"),
+        ("emergency", b"0"),
+        ("panic", b"0"),
+        ("alert", b"1"),
+        ("crit", b"2"),
+        ("critical", b"2"),
+        ("err", b"3"),
+        ("error", b"3"),
+        ("warning", b"4"),
+        ("warn", b"4"),
+        ("notice", b"5"),
+        ("info", b"6"),
+        ("informational", b"6"),
+        ("debug", b"7"),
+    ];
+    SEVERITIES
+        .iter()
+        .find(|(severity, _)| severity.eq_ignore_ascii_case(name))
+        .map(|(_, priority)| *priority)
+}
+
+/// The priority of the severity of `value`, if it's a known severity name.
+fn value_severity_priority(value: &Value) -> Option<&'static [u8]> {
+    // Format into a small buffer, to avoid allocating; the longest severity
+    // name has 13 characters, so longer values are unknown anyway.
+    let mut buffer = [0; 16];
+    let mut cursor = std::io::Cursor::new(&mut buffer[..]);
+    write!(cursor, "{}", value).ok()?;
+    let length = cursor.position() as usize;
+    severity_priority(std::str::from_utf8(&buffer[..length]).ok()?)
+}
+
+/// The priority of `record`.
+///
+/// Use the priority of the severity given with the reserved key [`SEVERITY`],
+/// if any, and the priority of the level of `record` otherwise.
+fn record_priority(record: &Record) -> &'static [u8] {
+    record
+        .key_values()
+        .get(Key::from_str(SEVERITY))
+        .and_then(|value| value_severity_priority(&value))
+        .unwrap_or_else(|| priority(record.level()))
+}
+
+/// Whether `record` asks to skip the extra fields of the logger.
+fn skips_extra_fields(record: &Record) -> bool {
+    record
+        .key_values()
+        .get(Key::from_str(SKIP_EXTRA_FIELDS))
+        .and_then(|value| value.to_bool())
+        .unwrap_or(false)
+}
+
+/// Collect all key-values of a record, to process them later.
+struct CollectKeyValues<'kvs>(Vec<(Key<'kvs>, Value<'kvs>)>);
+
+impl<'kvs> VisitSource<'kvs> for CollectKeyValues<'kvs> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), Error> {
+        self.0.push((key, value));
+        Ok(())
+    }
+}
+
+struct WriteKeyValues<'a, B: ?Sized> {
+    buffer: &'a mut B,
+    format: &'a RecordFormat,
+    /// The number of key-values written so far.
+    written: usize,
+    /// The number of key-values dropped because of the field limit.
+    truncated: usize,
+}
+
+impl<'a, B: FieldBuffer + ?Sized> WriteKeyValues<'a, B> {
+    fn new(buffer: &'a mut B, format: &'a RecordFormat) -> Self {
+        Self {
+            buffer,
+            format,
+            written: 0,
+            truncated: 0,
+        }
+    }
+
+    /// Mark the key-values as truncated, if we dropped any.
+    fn finish(self) {
+        if 0 < self.truncated {
+            put_field_u64(
+                self.buffer,
+                FieldName::WellFormed("FIELDS_TRUNCATED"),
+                self.truncated as u64,
+            );
+        }
+    }
+}
 
-impl<'kvs> VisitSource<'kvs> for WriteKeyValues<'_> {
+impl<'kvs, B: FieldBuffer + ?Sized> VisitSource<'kvs> for WriteKeyValues<'_, B> {
     fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), Error> {
-        put_field_length_encoded(self.0, FieldName::WriteEscaped(key.as_str()), value);
+        if key.as_str() == SKIP_EXTRA_FIELDS
+            || (key.as_str() == SOURCE_REALTIME_TIMESTAMP && value.to_u64().is_some())
+            || (key.as_str() == SEVERITY && value_severity_priority(&value).is_some())
+        {
+            return Ok(());
+        }
+        if self
+            .format
+            .max_fields
+            .map_or(false, |max| max <= self.written)
+        {
+            self.truncated += 1;
+            return Ok(());
+        }
+        let mut name_buffer = [0; RESERVED_FIELD_PREFIX.len() + 64];
+        let name = match self.format.key_field_name(key.as_str(), &mut name_buffer) {
+            Some(name) => name,
+            None => return Ok(()),
+        };
+        #[cfg(feature = "flatten_structured")]
+        if self.format.repeated_sequences {
+            let max_elements = self.format.max_fields.map(|max| max - self.written);
+            if let Some((written, dropped)) = flatten::put_sequence(
+                self.buffer,
+                name,
+                &value,
+                self.format.long_key_hashing,
+                max_elements,
+            ) {
+                self.written += written;
+                self.truncated += dropped;
+                return Ok(());
+            }
+        }
+        self.written += 1;
+        #[cfg(feature = "flatten_structured")]
+        if self.format.flatten_structured {
+            flatten::put_flattened(self.buffer, name, &value, self.format.long_key_hashing);
+            return Ok(());
+        }
+        let start = self.buffer.written();
+        let length = put_field_length_encoded(self.buffer, FieldName::WellFormed(name), &value);
+        if length == 0 && self.format.skip_empty_values {
+            self.buffer.truncate(start);
+            self.written -= 1;
+            return Ok(());
+        }
+        if self.format.error_chain {
+            let mut source = value.to_borrowed_error().and_then(|error| error.source());
+            let mut index = 0;
+            while let Some(error) = source {
+                put_field_length_encoded(
+                    self.buffer,
+                    self.format.escaped(&format!("{}_SOURCE_{}", key, index)),
+                    &format_args!("{}", error),
+                );
+                source = error.source();
+                index += 1;
+            }
+        }
         Ok(())
     }
 }
@@ -114,7 +571,8 @@ impl<'kvs> VisitSource<'kvs> for WriteKeyValues<'_> {
 ///
 /// The journald logger always sets the following standard [journal fields]:
 ///
-/// - `PRIORITY`: The log level mapped to a priority (see below).
+/// - `PRIORITY`: The log level mapped to a priority (see below), unless the
+///   reserved key `severity` of a record overrides it.
 /// - `MESSAGE`: The formatted log message (see [`log::Record::args()`]).
 /// - `SYSLOG_PID`: The PID of the running process (see [`std::process::id()`]).
 /// - `CODE_FILE`: The filename the log message originates from (see [`log::Record::file()`], only if present).
@@ -124,9 +582,14 @@ impl<'kvs> VisitSource<'kvs> for WriteKeyValues<'_> {
 ///
 /// Additionally it also adds the following non-standard fields:
 ///
-/// - `TARGET`: The target of the log record (see [`log::Record::target()`]).
+/// - `TARGET`: The target of the log record (see [`log::Record::target()`], only
+///   if not empty, and [`JournalLog::with_target_field_name`] to change the field
+///   name).
 /// - `CODE_MODULE`: The module path of the log record (see [`log::Record::module_path()`], only if present).
 ///
+/// Use [`JournalLog::with_standard_fields`] to omit `SYSLOG_PID`, `CODE_FILE`,
+/// `CODE_LINE`, `CODE_MODULE`, or `TARGET`.
+///
 /// [journal fields]: https://www.freedesktop.org/software/systemd/man/systemd.journal-fields.html
 ///
 /// ## Log levels and Priorities
@@ -139,7 +602,9 @@ impl<'kvs> VisitSource<'kvs> for WriteKeyValues<'_> {
 /// - [`Level::Debug`] → `6` (info)
 /// - [`Level::Trace`] → `7` (debug)
 ///
-/// Higher priorities (crit, alert, and emerg) are not used.
+/// Levels never map to the higher priorities crit, alert, and emerg; use the
+/// reserved key `severity` to log with these (see "Custom fields and
+/// structured record fields" below).
 ///
 /// ## Custom fields and structured record fields
 ///
@@ -160,7 +625,76 @@ impl<'kvs> VisitSource<'kvs> for WriteKeyValues<'_> {
 /// - Replace all invalid characters with underscore.
 /// - If the key starts with an underscore or digit, which is not permitted,
 ///   prepend `ESCAPED_`.
-/// - Cap the result to 64 bytes.
+/// - Cap the result to 64 bytes (see [`JournalLog::with_long_key_hashing`] to
+///   hash long keys instead).
+///
+/// If a key escapes to the name of a standard field the logger writes itself,
+/// e.g. `message`, the journal entry gets a second value for the standard
+/// field; see [`JournalLog::with_reserved_field_policy`] to rename or skip
+/// such keys instead.
+///
+/// The logger writes the value of the reserved key `code_func` to the standard
+/// `CODE_FUNC` field, for the name of the function which logged the record,
+/// like `sd_journal_print_with_location` does.  [`log`] does not know the
+/// function of a record, so callers have to provide it explicitly:
+///
+/// ```rust
+/// log::info!(code_func = "main"; "Started");
+/// ```
+///
+/// The logger writes the reserved key `source_realtime_timestamp`, with the
+/// microseconds since the epoch when the event of the record happened, to the
+/// `SOURCE_REALTIME_TIMESTAMP` and `SYSLOG_TIMESTAMP` fields, like
+/// [`JournalLog::send_with_timestamp`] does, e.g. to replay events with their
+/// original time:
+///
+/// ```rust
+/// log::info!(source_realtime_timestamp = 1_792_144_282_123_456_u64; "Replayed");
+/// ```
+///
+/// If the value of `source_realtime_timestamp` is not an unsigned integer, the
+/// logger writes it as an ordinary field.
+///
+/// The logger writes the priority of the syslog severity in the reserved key
+/// `severity` to `PRIORITY`, instead of the priority of the level, e.g. for
+/// events from other systems with their own severities:
+///
+/// ```rust
+/// log::error!(severity = "critical"; "Disk failed");
+/// ```
+///
+/// It recognizes the names `emerg`, `alert`, `crit`, `err`, `warning`,
+/// `notice`, `info`, and `debug`, ignoring case, as well as the aliases
+/// `emergency`, `panic`, `critical`, `error`, `warn` and `informational`.  It
+/// writes any other `severity` like other key-values, and uses the priority
+/// of the level.
+///
+/// Key-values whose keys start with `object_` escape to `OBJECT_` fields, e.g.
+/// `object_systemd_unit` to `OBJECT_SYSTEMD_UNIT`, to attribute records to
+/// another process or unit, e.g. in a supervisor:
+///
+/// ```rust
+/// log::warn!(object_systemd_unit = "worker@3.service"; "Worker restarted");
+/// ```
+///
+/// journald treats only `OBJECT_PID` specially: if a privileged process sets
+/// it, journald adds `OBJECT_UID`, `OBJECT_GID`, `OBJECT_COMM`, `OBJECT_EXE`,
+/// `OBJECT_CMDLINE`, `OBJECT_AUDIT_SESSION`, `OBJECT_AUDIT_LOGINUID`,
+/// `OBJECT_SYSTEMD_CGROUP`, `OBJECT_SYSTEMD_SESSION`,
+/// `OBJECT_SYSTEMD_OWNER_UID`, `OBJECT_SYSTEMD_UNIT`, and
+/// `OBJECT_SYSTEMD_USER_UNIT` for the process with this PID.  journald
+/// stores all other `OBJECT_` fields as ordinary fields, but `journalctl
+/// --unit` matches `OBJECT_SYSTEMD_UNIT` of entries from processes running as
+/// root, so these entries show up in the journal of the unit.
+///
+/// The logger does not write the reserved key `__skip_extra_fields` as a field.
+/// Instead, if a record has this key with value `true` the logger omits all
+/// extra fields from the journal entry of this record, e.g. to keep entries of
+/// chatty debug records small:
+///
+/// ```rust
+/// log::debug!(__skip_extra_fields = true; "Polled queue");
+/// ```
 ///
 /// [jfv]: https://github.com/systemd/systemd/blob/a8b53f4f1558b17169809effd865232580e4c4af/src/libsystemd/sd-journal/journal-file.c#L1698
 ///
@@ -170,34 +704,424 @@ impl<'kvs> VisitSource<'kvs> for WriteKeyValues<'_> {
 /// on feedback if journald is not available (e.g. in containers where the
 /// journald socket is not mounted into the container).
 ///
+/// Use [`JournalLog::new_lenient`] to skip this check, and start logging once
+/// journald becomes available, and [`JournalLog::new_with_startup_probe`] to
+/// decide at runtime whether to check.
+///
 /// Later on, the logger simply ignores any errors when sending log records to
 /// journald, simply because the log interface does not expose faillible operations.
+///
+/// # Background sending
+///
+/// By default the logger sends every record to journald directly from the
+/// logging thread, which may block the thread momentarily if journald is slow.
+/// With [`JournalLog::with_background_sender`] the logger instead queues
+/// records for a dedicated thread, and drops records if the queue is full.
+///
+/// When dropped the logger waits for the background thread to send all queued
+/// records (see [`JournalLog::shutdown`]).  Since [`log`] never drops the
+/// global logger, use [`JournalLog::install_with_handle`] to shut down the
+/// background thread of an installed logger.
 pub struct JournalLog {
     /// The journald client
     client: JournalClient,
-    /// Preformatted extra fields to be appended to every log message.
-    extra_fields: Vec<u8>,
+    /// The stream to write records to instead of journald's socket, if any.
+    stream: Option<StreamBackend>,
+    /// The background sender, if records are sent from a background thread.
+    background: Option<Arc<BackgroundSender>>,
+    /// How long to wait for the background sender on shutdown.
+    shutdown_timeout: Duration,
+    /// How to format the fields of records.
+    format: RecordFormat,
+    /// The escaped name of the sequence number field, if any.
+    sequence_field: Option<String>,
+    /// The next sequence number.
+    sequence: AtomicU64,
+    /// State shared with handles to this logger.
+    shared: Arc<Shared>,
+    /// How to sample verbose records, if at all.
+    sampling: Option<Sampling>,
+    /// The thread which summarizes dropped records, if any.
+    drop_summary: Option<DropSummaryThread>,
+    /// The clock for timestamp fields of records, if any.
+    clock: Option<Box<dyn Fn() -> SystemTime + Send + Sync>>,
+    /// The message IDs of events for [`Self::log_catalog`].
+    catalog: Catalog,
+    /// Whether to warn if the global max level is off once installed.
+    max_level_hint: bool,
+    /// Whether to check the global max level on the next call into the logger.
+    check_max_level: AtomicBool,
+    /// Filter records by target and level, if any.
+    #[cfg(feature = "env_filter")]
+    filter: Option<filter::Filter>,
+    /// A custom filter for records, if any.
+    record_filter: Option<Box<dyn RecordFilter>>,
+}
+
+/// The journal priority for `level`, as written to `PRIORITY`.
+fn priority(level: Level) -> &'static [u8] {
+    const PRIORITIES: [&[u8]; 8] = [b"0", b"1", b"2", b"3", b"4", b"5", b"6", b"7"];
+    PRIORITIES[usize::from(priority_for_level(level))]
+}
+
+/// How to format the fields of a record.
+struct RecordFormat {
     /// The syslog identifier.
     syslog_identifier: String,
+    /// The prefix for the message of every record.
+    message_prefix: String,
+    /// The name of the field for the message of records.
+    message_field: String,
+    /// Whether to add fields for the sources of error values.
+    error_chain: bool,
+    /// The name of the field for the target of records, if any.
+    target_field: Option<String>,
+    /// The escaped name of an additional field for the target of records, if any.
+    target_as_field: Option<String>,
+    /// Whether to hash long field names instead of truncating them.
+    long_key_hashing: bool,
+    /// Whether to add the context fields of the current thread.
+    context_stack: bool,
+    /// Whether to only write the last value of duplicate record keys.
+    dedup_fields: bool,
+    /// Whether to skip fields with empty values.
+    skip_empty_values: bool,
+    /// The maximum number of record key-values to write, if any.
+    max_fields: Option<usize>,
+    /// The optional standard fields to write.
+    standard_fields: StandardFields,
+    /// The most verbose level of records to write source location fields for.
+    source_location_level: LevelFilter,
+    /// What to do with record keys which escape to standard field names.
+    reserved_field_policy: ReservedFieldPolicy,
+    /// The number of key-values skipped because of their reserved names.
+    skipped_reserved_fields: AtomicU64,
+    /// The escaped name of the field for fingerprints of messages, if any.
+    fingerprint_field: Option<String>,
+    /// Whether to flatten structured values into separate fields.
+    #[cfg(feature = "flatten_structured")]
+    flatten_structured: bool,
+    /// Whether to write the elements of sequences as repeated fields.
+    #[cfg(feature = "flatten_structured")]
+    repeated_sequences: bool,
+}
+
+impl RecordFormat {
+    /// The field name for `name`, escaped as configured.
+    fn escaped<'a>(&self, name: &'a str) -> FieldName<'a> {
+        if self.long_key_hashing {
+            FieldName::WriteEscapedHashed(name)
+        } else {
+            FieldName::WriteEscaped(name)
+        }
+    }
+
+    /// The name of the field for the target of records, if we write it.
+    fn target_field(&self) -> Option<&String> {
+        self.target_field
+            .as_ref()
+            .filter(|_| self.standard_fields.contains(StandardFields::TARGET))
+    }
+
+    /// Whether to write the optional standard `field`.
+    fn writes(&self, field: StandardFields) -> bool {
+        self.standard_fields.contains(field)
+    }
+
+    /// Whether to write the source location `field` for a record at `level`.
+    fn writes_location(&self, field: StandardFields, level: Level) -> bool {
+        self.writes(field) && level <= self.source_location_level
+    }
+
+    /// Whether we write the standard field `name` for every record.
+    fn is_reserved(&self, name: &[u8]) -> bool {
+        use StandardFields as F;
+        match name {
+            b"PRIORITY" => true,
+            _ if name == self.message_field.as_bytes() => true,
+            b"SYSLOG_PID" => self.writes(F::SYSLOG_PID),
+            b"SYSLOG_IDENTIFIER" => !self.syslog_identifier.is_empty(),
+            b"CODE_FILE" => self.writes(F::CODE_FILE),
+            b"CODE_LINE" => self.writes(F::CODE_LINE),
+            b"CODE_MODULE" => self.writes(F::CODE_MODULE),
+            _ => [self.target_field(), self.target_as_field.as_ref()]
+                .into_iter()
+                .flatten()
+                .any(|target| target.as_bytes() == name),
+        }
+    }
+
+    /// The field name for the record key `key`, escaped into `buffer`.
+    ///
+    /// Apply the reserved field policy if `key` escapes to a standard field,
+    /// and return `None` if we should skip `key`.
+    fn key_field_name<'b>(
+        &self,
+        key: &str,
+        buffer: &'b mut [u8; RESERVED_FIELD_PREFIX.len() + 64],
+    ) -> Option<&'b str> {
+        if key == CODE_FUNC {
+            return Some("CODE_FUNC");
+        }
+        let prefix_len = RESERVED_FIELD_PREFIX.len();
+        let mut name = SliceBuffer::new(&mut buffer[prefix_len..]);
+        put_field_name(&mut name, self.escaped(key));
+        // Escaped names have at most 64 bytes, so they always fit.
+        let name_len = name.finish().unwrap().len();
+        let range = if !self.is_reserved(&buffer[prefix_len..prefix_len + name_len]) {
+            prefix_len..prefix_len + name_len
+        } else {
+            match self.reserved_field_policy {
+                ReservedFieldPolicy::Allow => prefix_len..prefix_len + name_len,
+                ReservedFieldPolicy::Skip => {
+                    self.skipped_reserved_fields.fetch_add(1, Ordering::Relaxed);
+                    return None;
+                }
+                ReservedFieldPolicy::Rename => {
+                    buffer[..prefix_len].copy_from_slice(RESERVED_FIELD_PREFIX.as_bytes());
+                    0..(prefix_len + name_len).min(64)
+                }
+            }
+        };
+        // Escaping leaves only ASCII characters in the name
+        Some(std::str::from_utf8(&buffer[range]).unwrap())
+    }
+}
+
+impl Default for RecordFormat {
+    fn default() -> Self {
+        Self {
+            syslog_identifier: String::new(),
+            message_prefix: String::new(),
+            message_field: "MESSAGE".to_string(),
+            error_chain: false,
+            target_field: Some("TARGET".to_string()),
+            target_as_field: None,
+            long_key_hashing: false,
+            context_stack: false,
+            dedup_fields: false,
+            skip_empty_values: false,
+            max_fields: None,
+            standard_fields: StandardFields::all(),
+            source_location_level: LevelFilter::Trace,
+            reserved_field_policy: ReservedFieldPolicy::default(),
+            skipped_reserved_fields: AtomicU64::new(0),
+            fingerprint_field: None,
+            #[cfg(feature = "flatten_structured")]
+            flatten_structured: false,
+            #[cfg(feature = "flatten_structured")]
+            repeated_sequences: false,
+        }
+    }
+}
+
+/// The prefix for record keys which escape to standard field names.
+///
+/// See [`ReservedFieldPolicy::Rename`].
+const RESERVED_FIELD_PREFIX: &str = "FIELD_";
+
+/// A guess for the size of a formatted message, when we can't know it upfront.
+const MESSAGE_SIZE_GUESS: usize = 128;
+
+/// A guess for the size of a key-value field of a record.
+const KEY_VALUE_SIZE_GUESS: usize = 64;
+
+/// Estimate the size of the payload of `record`.
+///
+/// Sum the exact sizes of all standard fields, and guess the sizes of the
+/// message if it needs formatting, and of key-values, because we only know
+/// their sizes after formatting them.
+fn estimate_payload_size(format: &RecordFormat, record: &Record) -> usize {
+    use StandardFields as F;
+    // PRIORITY, and the name and the length tag of the message field
+    let mut size = 11 + format.message_field.len() + 10 + format.message_prefix.len();
+    size += record.args().as_str().map_or(MESSAGE_SIZE_GUESS, str::len);
+    if let Some(field) = &format.fingerprint_field {
+        size += fingerprint::fingerprint_field_size(field.len());
+    }
+    if format.writes(F::SYSLOG_PID) {
+        // SYSLOG_PID with the largest possible PID
+        size += 22;
+    }
+    if !format.syslog_identifier.is_empty() {
+        size += 19 + format.syslog_identifier.len();
+    }
+    if format.writes_location(F::CODE_FILE, record.level()) {
+        size += record.file().map_or(0, |file| 11 + file.len());
+    }
+    if format.writes_location(F::CODE_MODULE, record.level()) {
+        size += record.module_path().map_or(0, |module| 13 + module.len());
+    }
+    if format.writes_location(F::CODE_LINE, record.level()) {
+        // CODE_LINE with the largest possible line
+        size += record.line().map_or(0, |_| 21);
+    }
+    for name in [format.target_field(), format.target_as_field.as_ref()]
+        .into_iter()
+        .flatten()
+    {
+        size += name.len() + 2 + record.target().len();
+    }
+    size + record.key_values().count() * KEY_VALUE_SIZE_GUESS
+}
+
+/// Create the payload for `record`.
+///
+/// Reserve `extra_capacity` bytes in the payload for fields the caller adds.
+fn record_payload(format: &RecordFormat, record: &Record, extra_capacity: usize) -> Vec<u8> {
+    let mut buffer = Vec::with_capacity(estimate_payload_size(format, record) + extra_capacity);
+    put_record_fields(&mut buffer, format, record);
+    buffer
 }
 
-fn record_payload(syslog_identifier: &str, record: &Record) -> Vec<u8> {
+/// Write all fields of `record` to `buffer`.
+///
+/// This function only allocates to format the sources of error values (see
+/// [`JournalLog::with_error_chain`]), and to deduplicate key-values (see
+/// [`JournalLog::with_dedup_fields`]).
+fn put_record_fields<B: FieldBuffer + ?Sized>(
+    buffer: &mut B,
+    format: &RecordFormat,
+    record: &Record,
+) {
     use FieldName::*;
-    let mut buffer = Vec::with_capacity(1024);
+    use StandardFields as F;
     // Write standard fields. Numeric fields can't contain new lines so we
     // write them directly, everything else goes through the put functions
     // for property mangling and length-encoding
-    let priority = match record.level() {
-        Level::Error => b"3",
-        Level::Warn => b"4",
-        Level::Info => b"5",
-        Level::Debug => b"6",
-        Level::Trace => b"7",
-    };
-    put_field_bytes(&mut buffer, WellFormed("PRIORITY"), priority);
-    put_field_length_encoded(&mut buffer, WellFormed("MESSAGE"), record.args());
+    put_field_bytes(buffer, WellFormed("PRIORITY"), record_priority(record));
+    if format.message_prefix.is_empty() {
+        put_field_length_encoded(buffer, WellFormed(&format.message_field), record.args());
+    } else {
+        let message = format_args!("{}{}", format.message_prefix, record.args());
+        put_field_length_encoded(buffer, WellFormed(&format.message_field), &message);
+    }
+    if let Some(field) = &format.fingerprint_field {
+        let fingerprint = fingerprint::message_fingerprint(record.args());
+        put_field_length_encoded(
+            buffer,
+            WellFormed(field),
+            &format_args!("{:016x}", fingerprint),
+        );
+    }
     // Syslog compatibility fields
-    writeln!(&mut buffer, "SYSLOG_PID={}", std::process::id()).unwrap();
+    if format.writes(F::SYSLOG_PID) {
+        put_field_u64(
+            buffer,
+            WellFormed("SYSLOG_PID"),
+            u64::from(std::process::id()),
+        );
+    }
+    if !format.syslog_identifier.is_empty() {
+        put_field_bytes(
+            buffer,
+            WellFormed("SYSLOG_IDENTIFIER"),
+            format.syslog_identifier.as_bytes(),
+        );
+    }
+    let level = record.level();
+    if let Some(file) = record
+        .file()
+        .filter(|_| format.writes_location(F::CODE_FILE, level))
+    {
+        put_field_bytes(buffer, WellFormed("CODE_FILE"), file.as_bytes());
+    }
+    if let Some(module) = record
+        .module_path()
+        .filter(|_| format.writes_location(F::CODE_MODULE, level))
+    {
+        put_field_bytes(buffer, WellFormed("CODE_MODULE"), module.as_bytes());
+    }
+    if let Some(line) = record
+        .line()
+        .filter(|_| format.writes_location(F::CODE_LINE, level))
+    {
+        put_field_u64(buffer, WellFormed("CODE_LINE"), u64::from(line));
+    }
+    // The log macros always set a target, so only records built by hand, e.g.
+    // by log_event, have no target; an empty field would tell nothing.
+    let target = record.target();
+    if !target.is_empty() {
+        if let Some(target_field) = format.target_field() {
+            put_field_bytes(buffer, WellFormed(target_field), target.as_bytes());
+        }
+        if let Some(target_as_field) = &format.target_as_field {
+            if format.target_field() != Some(target_as_field) {
+                put_field_bytes(buffer, WellFormed(target_as_field), target.as_bytes());
+            }
+        }
+    }
+    if format.context_stack {
+        context::for_each_context_field(|key, value| {
+            if !(value.is_empty() && format.skip_empty_values) {
+                put_field_bytes(buffer, format.escaped(key), value.as_bytes());
+            }
+        });
+    }
+    // Put all structured values of the record
+    let mut write = WriteKeyValues::new(buffer, format);
+    if format.dedup_fields {
+        let mut collect = CollectKeyValues(Vec::new());
+        record.key_values().visit(&mut collect).unwrap();
+        let pairs = &collect.0;
+        // Compare escaped field names, because distinct keys like "request"
+        // and "REQUEST" end up in the same journal field.
+        let names = pairs
+            .iter()
+            .map(|(key, _)| {
+                let mut name = Vec::new();
+                put_field_name(&mut name, format.escaped(key.as_str()));
+                name
+            })
+            .collect::<Vec<_>>();
+        for (index, (key, value)) in pairs.iter().enumerate() {
+            // Skip all but the last value of each field
+            if !names[index + 1..].contains(&names[index]) {
+                write.visit_pair(key.clone(), value.to_value()).unwrap();
+            }
+        }
+    } else {
+        record.key_values().visit(&mut write).unwrap();
+    }
+    write.finish();
+}
+
+/// The message ID of panics.
+///
+/// Match all panics logged with [`JournalLog::install_panic_hook`] with
+/// `journalctl MESSAGE_ID=a19f150c554c47ffb8e04ea885ab82dc`.
+pub const PANIC_MESSAGE_ID: MessageId = MessageId::from_u128(0xa19f150c554c47ffb8e04ea885ab82dc);
+
+/// Create the journal fields for a panic.
+///
+/// See [`JournalLog::install_panic_hook`].
+fn panic_payload(
+    syslog_identifier: &str,
+    message: &str,
+    location: Option<&std::panic::Location<'_>>,
+    thread_name: Option<&str>,
+    backtrace: Option<&str>,
+) -> Vec<u8> {
+    use FieldName::*;
+    let mut buffer = Vec::with_capacity(1024);
+    // crit
+    put_field_bytes(&mut buffer, WellFormed("PRIORITY"), b"2");
+    let full_message = match location {
+        Some(location) => format!("panicked at {}: {}", location, message),
+        None => format!("panicked: {}", message),
+    };
+    put_field_length_encoded(&mut buffer, WellFormed("MESSAGE"), full_message.as_bytes());
+    put_field_bytes(
+        &mut buffer,
+        WellFormed("MESSAGE_ID"),
+        PANIC_MESSAGE_ID.to_string().as_bytes(),
+    );
+    put_field_length_encoded(&mut buffer, WellFormed("PANIC_MESSAGE"), message.as_bytes());
+    put_field_u64(
+        &mut buffer,
+        WellFormed("SYSLOG_PID"),
+        u64::from(std::process::id()),
+    );
     if !syslog_identifier.is_empty() {
         put_field_bytes(
             &mut buffer,
@@ -205,33 +1129,104 @@ fn record_payload(syslog_identifier: &str, record: &Record) -> Vec<u8> {
             syslog_identifier.as_bytes(),
         );
     }
-    if let Some(file) = record.file() {
-        put_field_bytes(&mut buffer, WellFormed("CODE_FILE"), file.as_bytes());
-    }
-    if let Some(module) = record.module_path() {
-        put_field_bytes(&mut buffer, WellFormed("CODE_MODULE"), module.as_bytes());
-    }
-    if let Some(line) = record.line() {
-        writeln!(&mut buffer, "CODE_LINE={}", line).unwrap();
+    if let Some(location) = location {
+        put_field_bytes(
+            &mut buffer,
+            WellFormed("CODE_FILE"),
+            location.file().as_bytes(),
+        );
+        put_field_u64(
+            &mut buffer,
+            WellFormed("CODE_LINE"),
+            u64::from(location.line()),
+        );
     }
     put_field_bytes(
         &mut buffer,
-        WellFormed("TARGET"),
-        record.target().as_bytes(),
+        WellFormed("THREAD_NAME"),
+        thread_name.unwrap_or("<unnamed>").as_bytes(),
     );
-    // Put all structured values of the record
-    record
-        .key_values()
-        .visit(&mut WriteKeyValues(&mut buffer))
-        .unwrap();
+    if let Some(backtrace) = backtrace {
+        put_field_length_encoded(&mut buffer, WellFormed("BACKTRACE"), backtrace.as_bytes());
+    }
     buffer
 }
 
 impl JournalLog {
     /// Create a journal log instance with a default syslog identifier.
+    ///
+    /// See [`default_identifier`] for the identifier.
     pub fn new() -> std::io::Result<Self> {
         let logger = Self::empty()?;
-        Ok(logger.with_syslog_identifier(current_exe_identifier().unwrap_or_default()))
+        Ok(logger.with_syslog_identifier(default_identifier()))
+    }
+
+    /// Create a journal log instance with the name of a package as syslog
+    /// identifier.
+    ///
+    /// Like [`Self::new`], but use `package_name` as syslog identifier if it's
+    /// neither `None` nor empty, and fall back to the default identifier (see
+    /// [`default_identifier`]) otherwise.  Use the [`journal_log!`] macro to call this function with
+    /// the name of the package of the caller, which gives stable identifiers
+    /// even for test and example executables whose names Cargo decorates with
+    /// a hash.
+    pub fn new_with_package_identifier(package_name: Option<&str>) -> std::io::Result<Self> {
+        let logger = Self::empty()?;
+        let identifier = match package_name {
+            Some(name) if !name.is_empty() => name.to_string(),
+            _ => default_identifier(),
+        };
+        Ok(logger.with_syslog_identifier(identifier))
+    }
+
+    /// Create a journal log instance with a default syslog identifier, even if
+    /// journald is not reachable.
+    ///
+    /// Unlike [`Self::new`] this function does not fail if journald's socket
+    /// does not exist or nothing listens on it, e.g. in a container where the
+    /// socket is mounted only after the process started.  Instead the logger
+    /// discards all records until journald becomes reachable, and then starts
+    /// logging to the journal.
+    ///
+    /// # Errors
+    ///
+    /// Return an error if the logger fails to create a socket.
+    pub fn new_lenient() -> std::io::Result<Self> {
+        Self::new_with_startup_probe(false)
+    }
+
+    /// Create a journal log instance with a default syslog identifier, and
+    /// check whether journald is reachable if `probe` is `true`.
+    ///
+    /// With `probe` [`Self::new`], otherwise [`Self::new_lenient`].  Use this
+    /// function to decide at runtime whether to check, e.g. to skip the check
+    /// and its system call in tight init paths and only surface failures when
+    /// sending the first record:
+    ///
+    /// ```rust
+    /// use systemd_journal_logger::JournalLog;
+    ///
+    /// let probe = std::env::var_os("MY_APP_FAST_START").is_none();
+    /// let logger = JournalLog::new_with_startup_probe(probe).unwrap();
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Return an error if the logger fails to create a socket, or if `probe`
+    /// is `true` and journald is not reachable.
+    pub fn new_with_startup_probe(probe: bool) -> std::io::Result<Self> {
+        Self::connect(Path::new(SYSTEM_JOURNAL_SOCKET), probe)
+    }
+
+    /// Create a journal log instance with a default syslog identifier for the
+    /// journald socket at `path`, and check whether journald listens if `probe`.
+    fn connect(path: &Path, probe: bool) -> std::io::Result<Self> {
+        let client = if probe {
+            JournalClient::connect(path)?
+        } else {
+            JournalClient::connect_unchecked(path)?
+        };
+        Ok(Self::from_client(client).with_syslog_identifier(default_identifier()))
     }
 
     /// Create an empty journal log instance, with no extra fields and no syslog
@@ -240,36 +1235,337 @@ impl JournalLog {
     /// See [`Self::with_syslog_identifier`] and [`Self::with_extra_fields`] to
     /// set either.  It's recommended to at least set the syslog identifier.
     pub fn empty() -> std::io::Result<Self> {
-        Ok(Self {
-            client: JournalClient::new()?,
-            extra_fields: Vec::new(),
-            syslog_identifier: String::new(),
-        })
+        Ok(Self::from_client(JournalClient::system()?))
     }
 
-    /// Install this logger globally.
+    /// Create a journal log instance for the journald socket at `path`.
     ///
-    /// See [`log::set_boxed_logger`].
-    pub fn install(self) -> Result<(), SetLoggerError> {
-        log::set_boxed_logger(Box::new(self))
+    /// Like [`Self::new`], but send records to the journald socket at `path`
+    /// instead of the socket of the system journal (see
+    /// [`SYSTEM_JOURNAL_SOCKET`]), e.g. to the socket of a separate journald
+    /// instance in tests.  See "System, user, and namespace journals" in the
+    /// crate documentation.
+    ///
+    /// # Errors
+    ///
+    /// Return an error if the logger fails to create a socket, or if journald
+    /// does not listen at `path`.
+    pub fn new_with_socket_path<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        Self::connect(path.as_ref(), true)
     }
 
-    /// Add an extra field to be added to every log entry.
+    /// Create a journal log instance for the journal namespace `namespace`.
     ///
-    /// `name` is the name of a custom field, and `value` its value.  Fields are
-    /// appended to every log entry, in order they were added to the logger.
+    /// Like [`Self::new_with_socket_path`] with the socket of the journald
+    /// instance for `namespace`, i.e. `/run/systemd/journal.<namespace>/socket`.
+    /// Services with `LogNamespace=` in their unit already log to their
+    /// namespace; use this method to log to a namespace from other processes.
+    /// Use `journalctl --namespace=<namespace>` to read the journal of the
+    /// namespace.
     ///
-    /// ## Restrictions on field names
+    /// # Errors
     ///
-    /// `name` should be a valid journal file name, i.e. it must only contain
-    /// ASCII uppercase alphanumeric characters and the underscore, and must
-    /// start with an ASCII uppercase letter.
+    /// Return an [`std::io::ErrorKind::InvalidInput`] error if `namespace` is
+    /// not a valid namespace, i.e. if it is empty, `.` or `..`, longer than
+    /// 222 bytes, or has other characters than ASCII letters, digits, and
+    /// `:-_.@`, like journald itself requires.
     ///
-    /// Invalid keys in `extra_fields` are escaped according to the rules
-    /// documented in [`JournalLog`].
+    /// Return an error if the logger fails to create a socket, or if journald
+    /// does not listen for `namespace`, e.g. if `systemd-journald@<namespace>`
+    /// is not running.
+    pub fn new_for_namespace(namespace: &str) -> std::io::Result<Self> {
+        Self::new_with_socket_path(client::namespace_socket(namespace)?)
+    }
+
+    /// Create a journal log instance for a datagram `socket` connected to
+    /// journald.
     ///
-    /// It is not recommended that `name` is any of the standard fields already
-    /// added by this logger (see [`JournalLog`]); though journald supports
+    /// Like [`Self::new`], but send records through `socket` instead of
+    /// opening a new socket and sending to the path of journald's socket,
+    /// e.g. if the socket is inherited from a parent process or passed in with
+    /// socket activation, and this process can't open journald's socket by
+    /// path itself:
+    ///
+    /// ```rust
+    /// use std::os::fd::OwnedFd;
+    /// use std::os::unix::net::UnixDatagram;
+    /// use systemd_journal_logger::{JournalLog, SYSTEM_JOURNAL_SOCKET};
+    ///
+    /// let socket = UnixDatagram::unbound().unwrap();
+    /// socket.connect(SYSTEM_JOURNAL_SOCKET).unwrap();
+    /// let logger = JournalLog::new_with_socket_fd(OwnedFd::from(socket)).unwrap();
+    /// ```
+    ///
+    /// `socket` must be a Unix datagram socket connected to journald's
+    /// socket; the logger sends records without address.
+    ///
+    /// # Errors
+    ///
+    /// Return an error if journald does not listen on the peer of `socket`,
+    /// or if `socket` is not connected.
+    pub fn new_with_socket_fd(socket: OwnedFd) -> std::io::Result<Self> {
+        let logger = Self::from_client(JournalClient::from_connected_socket(socket)?);
+        Ok(logger.with_syslog_identifier(default_identifier()))
+    }
+
+    /// Create a journal log instance which sends payloads through `transport`.
+    ///
+    /// Like [`Self::new`], but send all records through `transport` instead
+    /// of journald's socket, e.g. to capture records in memory in unit tests:
+    ///
+    /// ```rust
+    /// # #[cfg(feature = "testing")]
+    /// # {
+    /// use std::sync::Arc;
+    /// use log::Log;
+    /// use systemd_journal_logger::JournalLog;
+    /// use systemd_journal_logger::testing::InMemoryClient;
+    ///
+    /// let client = Arc::new(InMemoryClient::new());
+    /// let logger = JournalLog::new_with_transport(client.clone()).unwrap();
+    /// logger.log(&log::Record::builder().args(format_args!("Hello")).build());
+    /// assert_eq!(client.entries()[0]["MESSAGE"], "Hello");
+    /// # }
+    /// ```
+    ///
+    /// Socket settings of this logger, e.g. [`Self::with_send_buffer_size`],
+    /// have no effect on `transport`, and the logger does not check whether
+    /// journald is reachable.  A stream backend (see
+    /// [`Self::with_stream_backend`]) takes precedence over `transport`.
+    ///
+    /// # Errors
+    ///
+    /// Return an error if the logger fails to create a socket.
+    pub fn new_with_transport<T: JournalTransport + 'static>(
+        transport: Arc<T>,
+    ) -> std::io::Result<Self> {
+        let logger = Self::from_client(JournalClient::connect_unchecked(Path::new(
+            SYSTEM_JOURNAL_SOCKET,
+        ))?);
+        Ok(logger
+            .with_transport(transport)
+            .with_syslog_identifier(default_identifier()))
+    }
+
+    /// Create a journal log instance which shares the socket of this logger.
+    ///
+    /// Like [`Self::new`], i.e. create a logger with a default syslog
+    /// identifier and no extra fields, but instead of opening a new socket and
+    /// checking whether journald listens, send records through the socket of
+    /// this logger.  The new logger also sends large payloads like this logger
+    /// (see [`Self::with_large_payload_strategy`]), and through the transport
+    /// of this logger, if any (see [`Self::new_with_transport`]).  All other
+    /// configuration is independent of this logger.
+    ///
+    /// Use this method to create multiple loggers with different fields, e.g.
+    /// one for audit events and one for application logs, without a socket and
+    /// a startup probe for each.  Sending through the same socket from
+    /// multiple loggers and threads is safe, because every record is a single
+    /// datagram.
+    ///
+    /// ```rust
+    /// use systemd_journal_logger::JournalLog;
+    ///
+    /// let app = JournalLog::new().unwrap();
+    /// let audit = app
+    ///     .new_sharing_socket()
+    ///     .with_extra_fields(vec![("AUDIT", "1")]);
+    /// ```
+    pub fn new_sharing_socket(&self) -> Self {
+        let logger = Self::from_client(self.client.clone());
+        logger.shared.transport.set(self.shared.transport.get());
+        logger.with_syslog_identifier(default_identifier())
+    }
+
+    fn from_client(client: JournalClient) -> Self {
+        Self {
+            client,
+            stream: None,
+            background: None,
+            shutdown_timeout: Duration::from_secs(1),
+            format: RecordFormat::default(),
+            sequence_field: None,
+            sequence: AtomicU64::new(1),
+            shared: Arc::default(),
+            sampling: None,
+            drop_summary: None,
+            clock: None,
+            catalog: Catalog::default(),
+            max_level_hint: false,
+            check_max_level: AtomicBool::new(false),
+            #[cfg(feature = "env_filter")]
+            filter: None,
+            record_filter: None,
+        }
+    }
+
+    /// Install this logger globally.
+    ///
+    /// See [`log::set_boxed_logger`].  Note that [`log`] discards all records
+    /// until you raise the global max level with [`log::set_max_level`]; see
+    /// [`Self::install_with_level`] to do both at once, and
+    /// [`Self::with_max_level_hint`] to warn if the max level is still off.
+    pub fn install(mut self) -> Result<(), SetLoggerError> {
+        self.check_max_level = AtomicBool::new(self.max_level_hint);
+        log::set_boxed_logger(Box::new(self))
+    }
+
+    /// Install this logger globally, and set the global max level to `level`.
+    ///
+    /// Like [`Self::install`] followed by [`log::set_max_level`]:
+    ///
+    /// ```rust
+    /// use log::LevelFilter;
+    /// use systemd_journal_logger::JournalLog;
+    ///
+    /// JournalLog::new()
+    ///     .unwrap()
+    ///     .install_with_level(LevelFilter::Info)
+    ///     .unwrap();
+    /// log::info!("Logged to the journal");
+    /// ```
+    ///
+    /// Only set the max level if installing the logger succeeded.
+    pub fn install_with_level(self, level: LevelFilter) -> Result<(), SetLoggerError> {
+        self.install()?;
+        log::set_max_level(level);
+        Ok(())
+    }
+
+    /// Capture all records logged on the current thread, for tests.
+    ///
+    /// Make every [`JournalLog`] send records logged on the current thread to
+    /// memory instead of journald, until the returned guard is dropped.  Use
+    /// the guard to inspect the captured entries:
+    ///
+    /// ```rust
+    /// use systemd_journal_logger::JournalLog;
+    ///
+    /// let capture = JournalLog::capture();
+    /// log::warn!(target: "my_test", "Disk almost full");
+    ///
+    /// let entries = capture.finish();
+    /// assert_eq!(entries.len(), 1);
+    /// assert_eq!(entries[0]["MESSAGE"], "Disk almost full");
+    /// assert_eq!(entries[0]["TARGET"], "my_test");
+    /// ```
+    ///
+    /// If no logger is installed yet, install a [`JournalLog`] which discards
+    /// all records logged outside of a capture, and set the global max level
+    /// to [`LevelFilter::Trace`].  Otherwise only capture records if the
+    /// installed logger is a [`JournalLog`], and only those it's enabled for.
+    ///
+    /// Captures only apply to the current thread, so tests running in
+    /// parallel do not see records of each other.  Captures nest: a capture
+    /// started while another one is active takes all records until it ends.
+    ///
+    /// Captured entries have the fields of the logger which captured them,
+    /// but bypass its background sender and stream backend, if any.
+    #[cfg(feature = "testing")]
+    pub fn capture() -> testing::CaptureGuard {
+        testing::CaptureGuard::start()
+    }
+
+    /// Install this logger globally, and return a handle to control it.
+    ///
+    /// Like [`Self::install`], but return a handle to shut down the background
+    /// sender of this logger (see [`Self::with_background_sender`]) before the
+    /// process exits, to make sure that records logged right before exit
+    /// reach journald, and to pause and resume the logger.
+    pub fn install_with_handle(self) -> Result<JournalLogHandle, SetLoggerError> {
+        let handle = JournalLogHandle {
+            background: self.background.clone(),
+            shutdown_timeout: self.shutdown_timeout,
+            shared: self.shared.clone(),
+        };
+        self.install()?;
+        Ok(handle)
+    }
+
+    /// Install a panic hook which logs panics to the journal.
+    ///
+    /// Chain a panic hook (see [`std::panic::set_hook`]) which sends an entry
+    /// for every panic to journald, and then calls the previous hook, i.e. by
+    /// default the hook of the standard library which prints the panic to
+    /// standard error.
+    ///
+    /// The entry has priority `2` (crit), a message with the location and the
+    /// message of the panic in `MESSAGE`, the message ID [`PANIC_MESSAGE_ID`]
+    /// in `MESSAGE_ID`, only the panic message in `PANIC_MESSAGE`, the
+    /// location of the panic in `CODE_FILE` and `CODE_LINE`, and the name of
+    /// the panicking thread in `THREAD_NAME`.  Use the message ID to find all
+    /// panics in the journal.  If backtraces are enabled with
+    /// `$RUST_BACKTRACE` (see [`std::backtrace`]) the entry also has a
+    /// `BACKTRACE` field.  It also has the syslog identifier of this logger as
+    /// configured when calling this method, and the current extra fields of
+    /// this logger (see [`Self::set_extra_field`]).
+    ///
+    /// The hook sends entries directly to journald's socket, even if this
+    /// logger uses a background sender or a stream backend, because the
+    /// process may abort right after the hook returns.
+    ///
+    /// ```rust
+    /// use systemd_journal_logger::JournalLog;
+    ///
+    /// let logger = JournalLog::new().unwrap();
+    /// logger.install_panic_hook();
+    /// logger.install().unwrap();
+    /// ```
+    pub fn install_panic_hook(&self) {
+        let transport = self.transport();
+        let syslog_identifier = self.format.syslog_identifier.clone();
+        let shared = self.shared.clone();
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            let payload = info.payload();
+            let message = match payload.downcast_ref::<&str>() {
+                Some(message) => *message,
+                None => match payload.downcast_ref::<String>() {
+                    Some(message) => message.as_str(),
+                    None => "Box<dyn Any>",
+                },
+            };
+            let backtrace = std::backtrace::Backtrace::capture();
+            let backtrace = match backtrace.status() {
+                std::backtrace::BacktraceStatus::Captured => Some(backtrace.to_string()),
+                _ => None,
+            };
+            let fields = panic_payload(
+                &syslog_identifier,
+                message,
+                info.location(),
+                std::thread::current().name(),
+                backtrace.as_deref(),
+            );
+            // Do not wait for the extra fields: the panic may come from a
+            // thread which currently updates them.
+            let extra_fields = shared
+                .extra_fields
+                .try_read()
+                .map_or_else(|_| Arc::default(), |fields| fields.payload.clone());
+            let _ = transport
+                .send_payload_vectored(&[IoSlice::new(&fields), IoSlice::new(&extra_fields)]);
+            previous(info);
+        }));
+    }
+
+    /// Add an extra field to be added to every log entry.
+    ///
+    /// `name` is the name of a custom field, and `value` its value.  Fields are
+    /// appended to every log entry, in order they were added to the logger.
+    ///
+    /// ## Restrictions on field names
+    ///
+    /// `name` should be a valid journal file name, i.e. it must only contain
+    /// ASCII uppercase alphanumeric characters and the underscore, and must
+    /// start with an ASCII uppercase letter.
+    ///
+    /// Invalid keys in `extra_fields` are escaped according to the rules
+    /// documented in [`JournalLog`]; use [`Self::try_add_extra_field`] to
+    /// reject invalid keys instead.
+    ///
+    /// It is not recommended that `name` is any of the standard fields already
+    /// added by this logger (see [`JournalLog`]); though journald supports
     /// multiple values for a field, journald clients may not handle unexpected
     /// multi-value fields properly and perhaps only show the first value.
     /// Specifically, even `journalctl` will only shouw the first `MESSAGE` value
@@ -278,27 +1574,208 @@ impl JournalLog {
     /// ## Restrictions on values
     ///
     /// There are no restrictions on the value.
-    pub fn add_extra_field<K: AsRef<str>, V: AsRef<[u8]>>(mut self, name: K, value: V) -> Self {
+    ///
+    /// If the logger skips empty values (see [`Self::with_skip_empty_values`])
+    /// and `value` is empty, do not add the field.  Likewise, do not add the
+    /// field if it exceeds the size limit for extra fields (see
+    /// [`Self::with_max_extra_fields_bytes`]).
+    pub fn add_extra_field<K: AsRef<str>, V: AsRef<[u8]>>(self, name: K, value: V) -> Self {
+        if value.as_ref().is_empty() && self.format.skip_empty_values {
+            return self;
+        }
+        let mut extra_fields = self.shared.extra_fields_mut();
+        let start = extra_fields.payload.len();
         put_field_bytes(
-            &mut self.extra_fields,
-            FieldName::WriteEscaped(name.as_ref()),
+            extra_fields.payload_mut(),
+            self.format.escaped(name.as_ref()),
             value.as_ref(),
         );
+        extra_fields.limit(start);
+        drop(extra_fields);
+        self
+    }
+
+    /// Limit the total size of extra fields to `max_bytes`.
+    ///
+    /// The logger appends all extra fields to every record, so large extra
+    /// fields, e.g. from a misconfiguration, make every record large.  With a
+    /// limit, [`Self::add_extra_field`], [`Self::try_add_extra_field`],
+    /// [`Self::add_raw_fields`] and all methods based on these silently drop
+    /// fields which would make the extra fields exceed `max_bytes`, in their
+    /// encoded size in the native protocol:
+    ///
+    /// ```rust
+    /// use systemd_journal_logger::JournalLog;
+    ///
+    /// let logger = JournalLog::new()
+    ///     .unwrap()
+    ///     .with_max_extra_fields_bytes(4096)
+    ///     .with_extra_fields(vec![("FOO", "bar"), ("HUGE", &"x".repeat(8192))]);
+    /// ```
+    ///
+    /// The limit only applies to fields added afterwards, so set it before
+    /// adding extra fields.  Unlimited by default.
+    pub fn with_max_extra_fields_bytes(self, max_bytes: usize) -> Self {
+        self.shared.extra_fields_mut().max_bytes = Some(max_bytes);
         self
     }
 
+    /// Add an extra field to be added to every log entry, if `name` is valid.
+    ///
+    /// Like [`Self::add_extra_field`], but instead of escaping an invalid
+    /// `name` return an error which describes why `name` is not a valid
+    /// journal field name.  Use this method to catch mistakes in field names,
+    /// e.g. from configuration, early on.
+    ///
+    /// Unlike [`Self::add_extra_field`] this method also rejects names which
+    /// start with an underscore, because journald reserves these for trusted
+    /// fields and ignores them in log entries.
+    ///
+    /// ```rust
+    /// use systemd_journal_logger::{InvalidFieldNameReason, JournalLog};
+    ///
+    /// let error = JournalLog::new()
+    ///     .unwrap()
+    ///     .try_add_extra_field("Hallöchen", "Welt")
+    ///     .err()
+    ///     .unwrap();
+    /// assert_eq!(error.name(), "Hallöchen");
+    /// assert_eq!(error.reason(), InvalidFieldNameReason::InvalidCharacter('a'));
+    /// ```
+    pub fn try_add_extra_field<K: AsRef<str>, V: AsRef<[u8]>>(
+        self,
+        name: K,
+        value: V,
+    ) -> Result<Self, InvalidFieldName> {
+        validate_field_name(name.as_ref())?;
+        if value.as_ref().is_empty() && self.format.skip_empty_values {
+            return Ok(self);
+        }
+        let mut extra_fields = self.shared.extra_fields_mut();
+        let start = extra_fields.payload.len();
+        put_field_bytes(
+            extra_fields.payload_mut(),
+            FieldName::WellFormed(name.as_ref()),
+            value.as_ref(),
+        );
+        extra_fields.limit(start);
+        drop(extra_fields);
+        Ok(self)
+    }
+
+    /// Add pre-formatted fields to be added to every log entry.
+    ///
+    /// `fields` must contain well-formed fields in the [native journal
+    /// protocol][1], i.e. each field is either `NAME=value` followed by a
+    /// newline, or `NAME` followed by a newline, the length of the value as
+    /// 64-bit little endian integer, the value, and a newline.  All field names
+    /// must be valid journal field names (see [`Self::try_add_extra_field`]).
+    ///
+    /// The logger appends `fields` verbatim to every log entry, without
+    /// escaping or re-encoding.
+    ///
+    /// ```rust
+    /// use systemd_journal_logger::JournalLog;
+    ///
+    /// let logger = JournalLog::new()
+    ///     .unwrap()
+    ///     .add_raw_fields(b"FOO=bar\nBAZ=qux\n")
+    ///     .unwrap();
+    /// ```
+    ///
+    /// [1]: https://systemd.io/JOURNAL_NATIVE_PROTOCOL/
+    ///
+    /// # Errors
+    ///
+    /// Return an error describing the first malformed field if `fields` is not
+    /// well-formed.
+    pub fn add_raw_fields(self, fields: &[u8]) -> Result<Self, InvalidRawFields> {
+        validate_payload(fields)?;
+        let mut extra_fields = self.shared.extra_fields_mut();
+        let start = extra_fields.payload.len();
+        extra_fields.payload_mut().extend_from_slice(fields);
+        extra_fields.limit(start);
+        drop(extra_fields);
+        Ok(self)
+    }
+
+    /// The extra fields of this logger.
+    ///
+    /// Return the names and values of all extra fields, in the order the
+    /// logger adds them to every log entry, with names escaped as the logger
+    /// writes them (see [`Self::add_extra_field`]), e.g. to log the effective
+    /// configuration of the logger at startup:
+    ///
+    /// ```rust
+    /// use systemd_journal_logger::JournalLog;
+    ///
+    /// let logger = JournalLog::new()
+    ///     .unwrap()
+    ///     .with_extra_fields(vec![("deployment", "blue")]);
+    /// assert_eq!(
+    ///     logger.extra_fields(),
+    ///     vec![("DEPLOYMENT".to_string(), b"blue".to_vec())]
+    /// );
+    /// ```
+    ///
+    /// Fields dropped by [`Self::with_max_extra_fields_bytes`] or
+    /// [`Self::with_skip_empty_values`] do not appear.
+    pub fn extra_fields(&self) -> Vec<(String, Vec<u8>)> {
+        payload_fields(&self.shared.extra_fields().payload)
+    }
+
+    /// Set the extra field `name` to `value`, while logging.
+    ///
+    /// Replace the value of the extra field `name`, or add the field if this
+    /// logger has no such extra field yet.  Unlike [`Self::add_extra_field`]
+    /// this method does not consume the logger, so use it to update context
+    /// which changes at runtime, e.g. a session ID.  To update the extra
+    /// fields of an installed logger, use [`JournalLogHandle::set_extra_field`]:
+    ///
+    /// ```rust
+    /// use systemd_journal_logger::JournalLog;
+    ///
+    /// let logger = JournalLog::new()
+    ///     .unwrap()
+    ///     .with_extra_fields(vec![("SESSION_ID", "1"), ("VERSION", "1.2.3")]);
+    /// logger.set_extra_field("SESSION_ID", "2").unwrap();
+    /// assert_eq!(
+    ///     logger.extra_fields(),
+    ///     vec![
+    ///         ("SESSION_ID".to_string(), b"2".to_vec()),
+    ///         ("VERSION".to_string(), b"1.2.3".to_vec()),
+    ///     ]
+    /// );
+    /// ```
+    ///
+    /// The field keeps its position among the extra fields; if the logger has
+    /// multiple extra fields named `name`, keep only the first.  If the new
+    /// value exceeds the size limit for extra fields (see
+    /// [`Self::with_max_extra_fields_bytes`]), remove the field.
+    ///
+    /// Like [`Self::try_add_extra_field`] reject invalid names instead of
+    /// escaping them, and return an error which describes why `name` is not a
+    /// valid journal field name.
+    pub fn set_extra_field<K: AsRef<str>, V: AsRef<[u8]>>(
+        &self,
+        name: K,
+        value: V,
+    ) -> Result<(), InvalidFieldName> {
+        self.shared.set_extra_field(name.as_ref(), value.as_ref())
+    }
+
     /// Set extra fields to be added to every log entry.
     ///
     /// Remove all previously added fields.
     ///
     /// See [`Self::add_extra_field`] for details.
-    pub fn with_extra_fields<I, K, V>(mut self, extra_fields: I) -> Self
+    pub fn with_extra_fields<I, K, V>(self, extra_fields: I) -> Self
     where
         I: IntoIterator<Item = (K, V)>,
         K: AsRef<str>,
         V: AsRef<[u8]>,
     {
-        self.extra_fields.clear();
+        self.shared.extra_fields_mut().payload_mut().clear();
         let mut logger = self;
         for (name, value) in extra_fields {
             logger = logger.add_extra_field(name, value);
@@ -306,64 +1783,2780 @@ impl JournalLog {
         logger
     }
 
+    /// Set extra fields to be added to every log entry, sorted by name.
+    ///
+    /// Like [`Self::with_extra_fields`], but add fields in the order of their
+    /// escaped names, and fields with the same escaped name in the order of
+    /// their values, instead of the order of `extra_fields`.  Use this method
+    /// to get the same payload regardless of the iteration order of
+    /// `extra_fields`, e.g. for a [`HashMap`](std::collections::HashMap):
+    ///
+    /// ```rust
+    /// use std::collections::HashMap;
+    /// use systemd_journal_logger::JournalLog;
+    ///
+    /// let fields = HashMap::from([("VERSION", "1.2.3"), ("SERVICE", "web")]);
+    /// let logger = JournalLog::new()
+    ///     .unwrap()
+    ///     .with_extra_fields_sorted(fields);
+    /// ```
+    pub fn with_extra_fields_sorted<I, K, V>(self, extra_fields: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: AsRef<[u8]>,
+    {
+        let mut fields = extra_fields
+            .into_iter()
+            .map(|(name, value)| {
+                let mut escaped = Vec::new();
+                put_field_name(&mut escaped, self.format.escaped(name.as_ref()));
+                (escaped, name, value)
+            })
+            .collect::<Vec<_>>();
+        fields.sort_by(|(a, _, a_value), (b, _, b_value)| {
+            a.cmp(b)
+                .then_with(|| a_value.as_ref().cmp(b_value.as_ref()))
+        });
+        self.with_extra_fields(fields.into_iter().map(|(_, name, value)| (name, value)))
+    }
+
+    /// Add extra fields from environment variables.
+    ///
+    /// `fields` maps names of extra fields to names of environment variables.
+    /// For every environment variable which is set, add its current value as
+    /// extra field of the given name (see [`Self::add_extra_field`]); skip all
+    /// environment variables which are not set.
+    ///
+    /// Unlike [`Self::with_extra_fields`] this method retains all previously
+    /// added fields.
+    ///
+    /// ```rust
+    /// use systemd_journal_logger::JournalLog;
+    ///
+    /// let logger = JournalLog::new()
+    ///     .unwrap()
+    ///     .with_extra_fields_from_env(&[("DEPLOYMENT", "MY_APP_DEPLOYMENT")]);
+    /// ```
+    pub fn with_extra_fields_from_env(self, fields: &[(&str, &str)]) -> Self {
+        let mut logger = self;
+        for (name, variable) in fields {
+            if let Some(value) = std::env::var_os(variable) {
+                logger = logger.add_extra_field(name, value.as_bytes());
+            }
+        }
+        logger
+    }
+
+    /// Add the invocation ID of the current service as extra field, if `enabled`.
+    ///
+    /// systemd sets `$INVOCATION_ID` to a unique ID for every start of a
+    /// service.  If `enabled` and `$INVOCATION_ID` is set, add its value in the
+    /// extra field `INVOCATION_ID` to correlate all log entries from a single
+    /// service start.
+    ///
+    /// See [`Self::with_extra_fields_from_env`].
+    pub fn with_invocation_id(self, enabled: bool) -> Self {
+        if enabled {
+            self.with_extra_fields_from_env(&[("INVOCATION_ID", "INVOCATION_ID")])
+        } else {
+            self
+        }
+    }
+
+    /// Add the boot ID of the system as extra field `name`.
+    ///
+    /// Read the boot ID from `/proc/sys/kernel/random/boot_id` once, and add
+    /// it in the same format as journald's trusted `_BOOT_ID` field, i.e. as
+    /// 32 hex digits without dashes.  Use this if your log pipeline does not
+    /// export trusted fields.
+    ///
+    /// If the boot ID is not available do not add any field.
+    ///
+    /// ```rust
+    /// use systemd_journal_logger::JournalLog;
+    ///
+    /// let logger = JournalLog::new().unwrap().with_boot_id_field("BOOT_ID");
+    /// ```
+    pub fn with_boot_id_field<K: AsRef<str>>(self, name: K) -> Self {
+        match std::fs::read_to_string("/proc/sys/kernel/random/boot_id") {
+            Ok(boot_id) => self.add_extra_field(name, boot_id.trim().replace('-', "")),
+            Err(_) => self,
+        }
+    }
+
+    /// Add the machine ID of the system as extra field `name`.
+    ///
+    /// Read the machine ID from `/etc/machine-id` once, and add it like
+    /// journald's trusted `_MACHINE_ID` field.  Use this if your log pipeline
+    /// does not export trusted fields.
+    ///
+    /// If the machine ID is not available do not add any field.
+    pub fn with_machine_id_field<K: AsRef<str>>(self, name: K) -> Self {
+        match std::fs::read_to_string("/etc/machine-id") {
+            Ok(machine_id) if !machine_id.trim().is_empty() => {
+                self.add_extra_field(name, machine_id.trim())
+            }
+            _ => self,
+        }
+    }
+
+    /// Add the effective user and group ID of this process as extra fields, if `enabled`.
+    ///
+    /// Read the effective user and group ID once, and add them in the extra
+    /// fields `EUID` and `EGID`.  Unlike journald's trusted `_UID` and `_GID`
+    /// fields these fields record the credentials as seen by the application,
+    /// e.g. to cross-check against privilege transitions in audit logs.
+    ///
+    /// ```rust
+    /// use systemd_journal_logger::JournalLog;
+    ///
+    /// let logger = JournalLog::new().unwrap().with_credentials_fields(true);
+    /// ```
+    ///
+    /// The fields keep the credentials at the time of this call; if the
+    /// process changes its credentials later, e.g. when dropping privileges,
+    /// create a new logger afterwards.
+    pub fn with_credentials_fields(self, enabled: bool) -> Self {
+        if enabled {
+            self.add_extra_field("EUID", sys::geteuid().to_string())
+                .add_extra_field("EGID", sys::getegid().to_string())
+        } else {
+            self
+        }
+    }
+
+    /// Add the container `name` in the extra field `CONTAINER_NAME`.
+    ///
+    /// Use this to tell which container a log entry came from in the journal
+    /// of the host.  See [`Self::with_detected_container_name`] to detect the
+    /// name automatically.
+    pub fn with_container_name(self, name: String) -> Self {
+        self.add_extra_field("CONTAINER_NAME", name)
+    }
+
+    /// Add the unit `name` in the extra field `UNIT`.
+    ///
+    /// journald adds the trusted `_SYSTEMD_UNIT` field only to entries from
+    /// processes which systemd started as part of a unit.  Use this method to
+    /// emulate it for processes started otherwise, e.g. by another process
+    /// manager, and query the journal with `journalctl UNIT=my-app.service`:
+    ///
+    /// ```rust
+    /// use systemd_journal_logger::JournalLog;
+    ///
+    /// let logger = JournalLog::new()
+    ///     .unwrap()
+    ///     .with_unit("my-app.service".to_string())
+    ///     .unwrap();
+    /// ```
+    ///
+    /// Note that `UNIT` is an ordinary field: the process sets it, journald
+    /// does not verify it, and `journalctl --unit` does not match it, because
+    /// it only matches trusted fields like `_SYSTEMD_UNIT`.
+    ///
+    /// # Errors
+    ///
+    /// Return an error if `name` is not a valid unit name, i.e. if it lacks a
+    /// unit type suffix such as `.service`, contains invalid characters, or
+    /// is longer than 255 bytes (see `systemd.unit(5)`).
+    pub fn with_unit(self, name: String) -> Result<Self, InvalidUnitName> {
+        let name = unit_name::validate_unit_name(name)?;
+        Ok(self.add_extra_field("UNIT", name))
+    }
+
+    /// Add the name of the logging facade in the extra field `LOGGER`.
+    ///
+    /// Use this to tell which facade produced a journal entry if a process
+    /// sends records from multiple facades to the journal, e.g. `LOGGER=log`
+    /// for this logger and `LOGGER=tracing` for a `tracing` subscriber:
+    ///
+    /// ```rust
+    /// use systemd_journal_logger::JournalLog;
+    ///
+    /// let logger = JournalLog::new().unwrap().with_logger_tag("log".to_string());
+    /// ```
+    ///
+    /// Like all extra fields [`Self::with_extra_fields`] removes this field
+    /// again, so add it afterwards.
+    pub fn with_logger_tag(self, tag: String) -> Self {
+        self.add_extra_field("LOGGER", tag)
+    }
+
+    /// Add the name of the container of this process in the extra field `CONTAINER_NAME`.
+    ///
+    /// Detect the name on a best-effort basis: Use the name in
+    /// `/run/.containerenv` if podman provides it, and otherwise the hostname,
+    /// if `/run/.containerenv` or `/.dockerenv` exist or `$container` is set.
+    /// If the process does not appear to run in a container, or if the name is
+    /// not available, do not add any field.
+    ///
+    /// See [`Self::with_container_name`].
+    pub fn with_detected_container_name(self) -> Self {
+        match container::detect_container_name() {
+            Some(name) => self.with_container_name(name),
+            None => self,
+        }
+    }
+
     /// Set the given syslog identifier for this logger.
     ///
     /// The logger writes this string in the `SYSLOG_IDENTIFIER` field, which
     /// can be filtered for with `journalctl -t`.
     ///
-    /// Use [`current_exe_identifier()`] to obtain the standard identifier for
-    /// the current executable.
+    /// Use [`default_identifier()`] to obtain the standard identifier for the
+    /// current process, or see [`Self::with_syslog_identifier_from`].
+    ///
+    /// The logger also names the memfds of large payloads (see
+    /// [`Self::with_large_payload_strategy`]) after the identifier, to
+    /// attribute them to this logger in `/proc/<pid>/fd`.
+    ///
+    /// This method accepts any identifier; see
+    /// [`Self::try_with_syslog_identifier`] to reject unreasonable identifiers.
     pub fn with_syslog_identifier(mut self, identifier: String) -> Self {
-        self.syslog_identifier = identifier;
+        self.client.set_memfd_name(&identifier);
+        self.format.syslog_identifier = identifier;
         self
     }
 
-    /// Get the complete journal payload for `record`, including extra fields
-    /// from this logger.
-    fn record_payload(&self, record: &Record) -> Vec<u8> {
-        let mut payload = record_payload(&self.syslog_identifier, record);
-        payload.extend_from_slice(&self.extra_fields);
-        payload
+    /// Set the given syslog identifier for this logger, if it is valid.
+    ///
+    /// Like [`Self::with_syslog_identifier`], but return an error if
+    /// `identifier` is longer than [`MAX_SYSLOG_IDENTIFIER_LEN`] bytes, because
+    /// the logger writes the identifier to every record, or if it contains
+    /// control characters such as newlines, which syslog and most tools do not
+    /// expect in identifiers.
+    ///
+    /// ```rust
+    /// use systemd_journal_logger::{InvalidSyslogIdentifierReason, JournalLog};
+    ///
+    /// let error = JournalLog::new()
+    ///     .unwrap()
+    ///     .try_with_syslog_identifier("my\nservice".to_string())
+    ///     .err()
+    ///     .unwrap();
+    /// assert_eq!(error.reason(), InvalidSyslogIdentifierReason::ControlCharacter('\n'));
+    /// ```
+    ///
+    /// Use [`sanitize_syslog_identifier`] to truncate the identifier and
+    /// replace control characters instead of failing.
+    ///
+    /// # Errors
+    ///
+    /// Return an error which describes why `identifier` is not valid.
+    pub fn try_with_syslog_identifier(
+        self,
+        identifier: String,
+    ) -> Result<Self, InvalidSyslogIdentifier> {
+        let identifier = syslog_identifier::validate_syslog_identifier(identifier)?;
+        Ok(self.with_syslog_identifier(identifier))
     }
 
-    /// Send a single log record to the journal.
+    /// Set the syslog identifier for this logger from `source`.
     ///
-    /// Extract all fields (standard and custom) from `record` (`see [`JournalLog`]),
-    /// append all `extra_fields` given to this logger, and send the result to
-    /// journald.
-    pub fn journal_send(&self, record: &Record) -> std::io::Result<()> {
-        let _ = self.client.send_payload(&self.record_payload(record))?;
-        Ok(())
+    /// Use [`IdentifierSource::Argv0`] for multi-call binaries, where the name
+    /// the process was invoked with is the meaningful identity:
+    ///
+    /// ```rust
+    /// use systemd_journal_logger::{IdentifierSource, JournalLog};
+    ///
+    /// let logger = JournalLog::new()
+    ///     .unwrap()
+    ///     .with_syslog_identifier_from(IdentifierSource::Argv0);
+    /// ```
+    ///
+    /// If `source` has no identifier, fall back to the current executable, and
+    /// keep the current identifier if that fails too.
+    pub fn with_syslog_identifier_from(self, source: IdentifierSource) -> Self {
+        match source.identifier().or_else(current_exe_identifier) {
+            Some(identifier) => self.with_syslog_identifier(identifier),
+            None => self,
+        }
     }
-}
 
-/// The [`Log`] interface for [`JournalLog`].
-impl Log for JournalLog {
-    /// Whether this logger is enabled.
+    /// Set the name of the field for the target of records.
     ///
-    /// Always returns `true`.
-    fn enabled(&self, _metadata: &Metadata) -> bool {
-        true
+    /// By default the logger writes the target of each record (see
+    /// [`log::Record::target()`]) to the `TARGET` field.  Use this method to
+    /// write the target to a field of a different `name`, or pass `None` to
+    /// omit the target entirely.
+    ///
+    /// # Errors
+    ///
+    /// Return an error if `name` is not a valid journal field name (see
+    /// [`Self::try_add_extra_field`]).
+    pub fn with_target_field_name(
+        mut self,
+        name: Option<String>,
+    ) -> Result<Self, InvalidFieldName> {
+        if let Some(name) = &name {
+            validate_field_name(name)?;
+        }
+        self.format.target_field = name;
+        Ok(self)
     }
 
-    /// Send the given `record` to the systemd journal.
+    /// Set the name of the field for the message of records.
+    ///
+    /// By default the logger writes the message of each record (see
+    /// [`log::Record::args()`]) to the standard `MESSAGE` field.  Use this
+    /// method to write the message to a field of a different `name` instead,
+    /// e.g. for a pipeline which ingests the journal and expects the message
+    /// in another field:
+    ///
+    /// ```rust
+    /// use systemd_journal_logger::JournalLog;
+    ///
+    /// let logger = JournalLog::new()
+    ///     .unwrap()
+    ///     .with_message_field_name("EVENT_TEXT".to_string())
+    ///     .unwrap();
+    /// ```
+    ///
+    /// **Use with care:** entries of records then have no `MESSAGE` field, so
+    /// `journalctl` shows them as empty lines in its default output, and
+    /// most other consumers of the journal won't find their message at all.
+    ///
+    /// This only applies to log records.  Other entries, e.g. of the panic
+    /// hook (see [`Self::install_panic_hook`]) or of [`Self::send_fields`],
+    /// still have `MESSAGE`, and so does the stream backend (see
+    /// [`Self::with_stream_backend`]).
     ///
     /// # Errors
     ///
-    /// Ignore any errors which occur when sending `record` to journald because
-    /// we cannot reasonably handle them at this place.
+    /// Return an error if `name` is not a valid journal field name (see
+    /// [`Self::try_add_extra_field`]).
+    pub fn with_message_field_name(mut self, name: String) -> Result<Self, InvalidFieldName> {
+        validate_field_name(&name)?;
+        self.format.message_field = name;
+        Ok(self)
+    }
+
+    /// Set the optional standard fields to write.
     ///
-    /// See [`JournalLog::journal_send`] for a function which returns any error
-    /// which might have occurred while sending the `record` to the journal.
-    fn log(&self, record: &Record) {
-        // We can't really handle errors here, so simply discard them.
-        // The alternative would be to panic, but a failed logging call should
-        // not bring the entire process down.
-        let _ = self.journal_send(record);
+    /// By default the logger writes all standard fields (see "Standard
+    /// fields" in [`JournalLog`]).  Use this method to omit some of them, to
+    /// reduce the size of journal entries:
+    ///
+    /// ```rust
+    /// use systemd_journal_logger::{JournalLog, StandardFields};
+    ///
+    /// let logger = JournalLog::new()
+    ///     .unwrap()
+    ///     .with_standard_fields(StandardFields::all() - StandardFields::CODE_MODULE);
+    /// ```
+    ///
+    /// The logger always writes `PRIORITY` and `MESSAGE`, and
+    /// `SYSLOG_IDENTIFIER` if set.
+    pub fn with_standard_fields(mut self, fields: StandardFields) -> Self {
+        self.format.standard_fields = fields;
+        self
     }
 
-    /// Flush log records.
+    /// Only write source locations of records at `level` or more severe.
+    ///
+    /// By default the logger writes `CODE_FILE`, `CODE_LINE`, and
+    /// `CODE_MODULE` for records of all levels.  Source locations help most
+    /// with problems, so use this method to omit them from more verbose
+    /// records, and reduce the size of their entries:
+    ///
+    /// ```rust
+    /// use log::Level;
+    /// use systemd_journal_logger::JournalLog;
+    ///
+    /// // Only write source locations of errors and warnings
+    /// let logger = JournalLog::new()
+    ///     .unwrap()
+    ///     .with_source_location_min_level(Level::Warn);
+    /// ```
+    ///
+    /// Source location fields omitted with [`Self::with_standard_fields`]
+    /// stay omitted for all levels.
+    pub fn with_source_location_min_level(mut self, level: Level) -> Self {
+        self.format.source_location_level = level.to_level_filter();
+        self
+    }
+
+    /// Prepend `prefix` to the message of every record.
+    ///
+    /// Write `prefix` right before the formatted message of every record into
+    /// the `MESSAGE` field, e.g. to tag all messages of a component of a
+    /// service which cannot log with a distinct target:
+    ///
+    /// ```rust
+    /// use systemd_journal_logger::JournalLog;
+    ///
+    /// let logger = JournalLog::new()
+    ///     .unwrap()
+    ///     .with_message_prefix("[scheduler] ".to_string());
+    /// ```
     ///
-    /// A no-op for journal logging.
-    fn flush(&self) {}
+    /// The prefix also applies to messages written to a stream backend (see
+    /// [`Self::with_stream_backend`]), but not to [`Self::send_fields`] and
+    /// [`Self::send_binary_message`], which send messages as given.
+    pub fn with_message_prefix(mut self, prefix: String) -> Self {
+        self.format.message_prefix = prefix;
+        self
+    }
+
+    /// Write a fingerprint of the message of every record to the field `name`.
+    ///
+    /// The fingerprint is the 64-bit FNV-1a hash of the formatted message of a
+    /// record (see [`log::Record::args()`]), without the message prefix (see
+    /// [`Self::with_message_prefix`]), as 16 lowercase hex digits.  The hash
+    /// is stable across processes and versions, so the fingerprint groups
+    /// entries with identical messages, regardless of their timestamps and
+    /// other fields:
+    ///
+    /// ```rust
+    /// use systemd_journal_logger::JournalLog;
+    ///
+    /// let logger = JournalLog::new()
+    ///     .unwrap()
+    ///     .with_message_fingerprint("MSG_HASH");
+    /// ```
+    ///
+    /// Hashing messages costs time for every record, so the logger writes no
+    /// fingerprints by default.
+    ///
+    /// Invalid characters in `name` are escaped according to the rules
+    /// documented in [`JournalLog`].
+    pub fn with_message_fingerprint<K: AsRef<str>>(mut self, name: K) -> Self {
+        self.format.fingerprint_field = Some(escape_field_name(name.as_ref()));
+        self
+    }
+
+    /// Also write the target of records to the field `name`.
+    ///
+    /// In addition to the target field (see [`Self::with_target_field_name`])
+    /// write the target of each record to the field `name`, e.g. to filter
+    /// log entries of different components of a service with `journalctl
+    /// COMPONENT=foo` if each component logs with its own target.  If `name`
+    /// is the name of the target field the logger writes the target only once.
+    ///
+    /// Invalid characters in `name` are escaped according to the rules
+    /// documented in [`JournalLog`].
+    pub fn with_target_as_field<K: AsRef<str>>(mut self, name: K) -> Self {
+        self.format.target_as_field = Some(escape_field_name(name.as_ref()));
+        self
+    }
+
+    /// Whether to hash long field names instead of truncating them.
+    ///
+    /// journald limits field names to 64 bytes, so the logger truncates longer
+    /// names when escaping them (see [`JournalLog`]).  Distinct long names can
+    /// end up as the same field this way.  If `enabled` the logger instead
+    /// replaces the tail of escaped names longer than 64 bytes with an
+    /// underscore and six hex digits of a hash of the original name, to keep
+    /// distinct names apart.
+    ///
+    /// This setting applies to key-values of records, and to extra fields added
+    /// after calling this method.  Disabled by default.
+    pub fn with_long_key_hashing(mut self, enabled: bool) -> Self {
+        self.format.long_key_hashing = enabled;
+        self
+    }
+
+    /// Whether to write only the last value of keys which occur multiple times in a record.
+    ///
+    /// A record can have multiple values for the same key, e.g. if a wrapping
+    /// logger adds its own key-values to the key-values of a record, like the
+    /// `custom_fields.rs` example does.  By default the logger writes a field
+    /// for every value, and the journal entry ends up with multiple values
+    /// for the same field.  If `enabled` the logger only writes the last value
+    /// of each key, so that values added by wrappers override the values of
+    /// the original record.
+    ///
+    /// Keys are compared by their escaped field names, so keys which only
+    /// differ in case, e.g. `request` and `REQUEST`, count as the same key.
+    ///
+    /// This setting requires buffering all key-values of a record before
+    /// writing them.  Disabled by default.
+    pub fn with_dedup_fields(mut self, enabled: bool) -> Self {
+        self.format.dedup_fields = enabled;
+        self
+    }
+
+    /// Whether to skip fields with empty values.
+    ///
+    /// By default the logger writes fields with empty values like any other
+    /// field, e.g. `FOO=` for a key-value `foo = ""`.  If `enabled` the logger
+    /// omits such fields instead, to keep entries of config-driven field sets
+    /// tidy in `journalctl --output=verbose`:
+    ///
+    /// ```rust
+    /// use systemd_journal_logger::JournalLog;
+    ///
+    /// let region = std::env::var("REGION").unwrap_or_default();
+    /// let logger = JournalLog::new()
+    ///     .unwrap()
+    ///     .with_skip_empty_values(true)
+    ///     .add_extra_field("REGION", region);
+    /// ```
+    ///
+    /// This setting applies to key-values of records, to context fields (see
+    /// [`Self::with_context_stack`]), and to extra fields added after calling
+    /// this method.  The logger always writes standard fields, even if empty,
+    /// e.g. an empty `MESSAGE`.  Disabled by default.
+    pub fn with_skip_empty_values(mut self, enabled: bool) -> Self {
+        self.format.skip_empty_values = enabled;
+        self
+    }
+
+    /// Whether to warn about a global max level of `Off` once installed.
+    ///
+    /// [`log`] discards all records while the global max level is
+    /// [`LevelFilter::Off`], which is the default, so a logger installed
+    /// without a call to [`log::set_max_level`] silently logs nothing.  If
+    /// `enabled` an installed logger points out this mistake: it checks the
+    /// global max level on the first call to [`Log::log`] or [`Log::flush`],
+    /// and if it's off sends a single entry with priority `4` (warning) which
+    /// says so.  As [`log`] doesn't call the logger at all while the max
+    /// level is off, the logger usually only notices when flushed, e.g. with
+    /// `log::logger().flush()` before the process exits.
+    ///
+    /// Disabled by default, because some processes turn logging off on
+    /// purpose.
+    pub fn with_max_level_hint(mut self, enabled: bool) -> Self {
+        self.max_level_hint = enabled;
+        self
+    }
+
+    /// Write at most `max_fields` key-values of each record.
+    ///
+    /// Drop all further key-values of a record, and add a `FIELDS_TRUNCATED`
+    /// field with the number of dropped key-values instead, to protect this
+    /// process and journald from huge payloads of records with excessive
+    /// key-values.  The limit only applies to key-values of records, and not
+    /// to standard fields and extra fields of this logger.
+    ///
+    /// Unlimited by default.
+    pub fn with_max_fields(mut self, max_fields: usize) -> Self {
+        self.format.max_fields = Some(max_fields);
+        self
+    }
+
+    /// Whether to flatten structured values into separate fields.
+    ///
+    /// By default the logger formats every value of a record key-value into a
+    /// single field with [`std::fmt::Display`].  If `enabled` the logger
+    /// instead writes a separate field for every child of structured values
+    /// captured with serde (see [`log::kv::Value::from_serde`]), recursively,
+    /// and joins the names of nested fields with `_`:
+    ///
+    /// ```rust
+    /// use log::kv::Value;
+    /// use serde::Serialize;
+    /// use systemd_journal_logger::JournalLog;
+    ///
+    /// #[derive(Serialize)]
+    /// struct User {
+    ///     id: u64,
+    ///     name: &'static str,
+    /// }
+    ///
+    /// let logger = JournalLog::new().unwrap().with_flatten_structured(true);
+    /// log::set_boxed_logger(Box::new(logger)).unwrap();
+    /// log::set_max_level(log::LevelFilter::Info);
+    ///
+    /// let user = User { id: 5, name: "x" };
+    /// // Writes the fields USER_ID=5 and USER_NAME=x
+    /// log::info!(user = Value::from_serde(&user); "User logged in");
+    /// ```
+    ///
+    /// Fields of sequences use the index of elements as name, and fields of
+    /// maps the key of entries; the logger omits entries of maps whose keys
+    /// are neither strings nor numbers, and values nested deeper than eight
+    /// levels.  Flattened fields bypass the reserved field policy (see
+    /// [`Self::with_reserved_field_policy`]) and the field limit (see
+    /// [`Self::with_max_fields`]), which only apply to the key-value itself.
+    ///
+    /// Disabled by default.  Requires the `flatten_structured` feature.
+    #[cfg(feature = "flatten_structured")]
+    pub fn with_flatten_structured(mut self, enabled: bool) -> Self {
+        self.format.flatten_structured = enabled;
+        self
+    }
+
+    /// Whether to write every element of sequences as a separate field.
+    ///
+    /// If `enabled` the logger writes a field with the name of the key for
+    /// every element of a sequence captured with serde (see
+    /// [`log::kv::Value::from_serde`]), instead of a single field with the
+    /// whole sequence, so that journald can match entries by single elements:
+    ///
+    /// ```rust
+    /// use log::kv::Value;
+    /// use systemd_journal_logger::JournalLog;
+    ///
+    /// let logger = JournalLog::new().unwrap().with_repeated_sequences(true);
+    /// log::set_boxed_logger(Box::new(logger)).unwrap();
+    /// log::set_max_level(log::LevelFilter::Info);
+    ///
+    /// let tags = vec!["billing", "retry"];
+    /// // Writes the fields TAG=billing and TAG=retry
+    /// log::info!(tag = Value::from_serde(&tags); "Payment failed");
+    /// ```
+    ///
+    /// The logger flattens structured elements (see
+    /// [`Self::with_flatten_structured`]).  Every element counts towards the
+    /// field limit (see [`Self::with_max_fields`]); the logger drops elements
+    /// beyond the limit and counts them in `FIELDS_TRUNCATED`.
+    ///
+    /// Disabled by default.  Requires the `flatten_structured` feature.
+    #[cfg(feature = "flatten_structured")]
+    pub fn with_repeated_sequences(mut self, enabled: bool) -> Self {
+        self.format.repeated_sequences = enabled;
+        self
+    }
+
+    /// Set what to do with record keys which escape to standard field names.
+    ///
+    /// By default the logger writes record keys which escape to the name of a
+    /// standard field the logger writes itself as is, so that the journal
+    /// entry gets a second value for the standard field.  With
+    /// [`ReservedFieldPolicy::Rename`] the logger writes keys like `message`
+    /// or `code_line` to `FIELD_MESSAGE` or `FIELD_CODE_LINE` respectively
+    /// instead (see [`ReservedFieldPolicy`]).  Reserved names are
+    /// `MESSAGE`, `PRIORITY`, `SYSLOG_IDENTIFIER` if this logger has a syslog
+    /// identifier, the target fields of this logger, and all standard fields
+    /// this logger writes (see [`Self::with_standard_fields`]).
+    ///
+    /// With [`ReservedFieldPolicy::Skip`] see
+    /// [`Self::skipped_reserved_fields`] for the number of skipped key-values.
+    pub fn with_reserved_field_policy(mut self, policy: ReservedFieldPolicy) -> Self {
+        self.format.reserved_field_policy = policy;
+        self
+    }
+
+    /// The number of key-values this logger skipped because of their names.
+    ///
+    /// See [`Self::with_reserved_field_policy`].
+    pub fn skipped_reserved_fields(&self) -> u64 {
+        self.format.skipped_reserved_fields.load(Ordering::Relaxed)
+    }
+
+    /// Whether to add fields for the sources of error values.
+    ///
+    /// If `enabled` add a field for every error in the [`source`] chain of
+    /// each error value in the key-values of a record, i.e. for a value
+    /// captured with `:err` as in `log::error!(error:err; "Failed")`.  The
+    /// fields for the sources of an error in the key `error` are named
+    /// `ERROR_SOURCE_0`, `ERROR_SOURCE_1`, etc., in order of the source chain.
+    ///
+    /// Disabled by default.
+    ///
+    /// [`source`]: std::error::Error::source
+    pub fn with_error_chain(mut self, enabled: bool) -> Self {
+        self.format.error_chain = enabled;
+        self
+    }
+
+    /// Whether to add the context fields of the current thread to every record.
+    ///
+    /// If `enabled` add all fields pushed with [`push_context`] and not yet
+    /// popped with [`pop_context`] on the thread which logs a record to the
+    /// journal entry of the record, e.g. to add a request ID to all records
+    /// logged while handling a request.
+    ///
+    /// ```rust
+    /// use systemd_journal_logger::{pop_context, push_context, JournalLog};
+    ///
+    /// JournalLog::new().unwrap().with_context_stack(true).install().unwrap();
+    /// log::set_max_level(log::LevelFilter::Info);
+    ///
+    /// push_context("REQUEST_ID", "42");
+    /// log::info!("This entry has a REQUEST_ID field");
+    /// pop_context();
+    /// ```
+    ///
+    /// Disabled by default.
+    pub fn with_context_stack(mut self, enabled: bool) -> Self {
+        self.format.context_stack = enabled;
+        self
+    }
+
+    /// Add timestamp fields with the time from `clock` to every log entry.
+    ///
+    /// Call `clock` for every record the logger formats, and add the
+    /// `SOURCE_REALTIME_TIMESTAMP` and `SYSLOG_TIMESTAMP` fields with the
+    /// returned time, like [`Self::send_with_timestamp`] does.  Without a
+    /// clock the logger adds no timestamp fields, and journald uses the time
+    /// it received each record.
+    ///
+    /// Use [`SystemTime::now`] to record the time the process logged each
+    /// record, or a fixed clock in tests, to check the exact payload of a
+    /// record:
+    ///
+    /// ```rust
+    /// use std::time::{Duration, UNIX_EPOCH};
+    /// use log::{Level, Record};
+    /// use systemd_journal_logger::JournalLog;
+    ///
+    /// let logger = JournalLog::empty()
+    ///     .unwrap()
+    ///     .with_clock(|| UNIX_EPOCH + Duration::from_secs(1_792_144_282));
+    /// let payload = logger.format_record(
+    ///     &Record::builder()
+    ///         .level(Level::Info)
+    ///         .args(format_args!("Hello"))
+    ///         .build(),
+    /// );
+    /// assert!(payload.ends_with(
+    ///     b"SOURCE_REALTIME_TIMESTAMP=1792144282000000\nSYSLOG_TIMESTAMP=Oct 16 09:51:22\n"
+    /// ));
+    /// ```
+    ///
+    /// The logger omits timestamp fields if `clock` returns a time before the
+    /// Unix epoch.
+    pub fn with_clock<F>(mut self, clock: F) -> Self
+    where
+        F: Fn() -> SystemTime + Send + Sync + 'static,
+    {
+        self.clock = Some(Box::new(clock));
+        self
+    }
+
+    /// Add a sequence number in the field `name` to every log entry.
+    ///
+    /// The logger numbers all log entries it formats consecutively, starting at
+    /// 1, and writes the number of each entry in the field `name`.  Use this
+    /// field to reconstruct the exact order of log entries from this logger,
+    /// even if their timestamps collide.
+    ///
+    /// Invalid characters in `name` are escaped according to the rules
+    /// documented in [`JournalLog`].
+    pub fn with_sequence_field<K: AsRef<str>>(mut self, name: K) -> Self {
+        self.sequence_field = Some(escape_field_name(name.as_ref()));
+        self
+    }
+
+    /// Create a journal log instance filtered by `$RUST_LOG`.
+    ///
+    /// Like [`Self::new`], but filter records with the directives in the
+    /// `$RUST_LOG` environment variable (see [`Self::with_filter`]), like
+    /// `env_logger` does.  If `$RUST_LOG` is not set only log errors.
+    ///
+    /// Use [`Self::max_level`] to set the global max level accordingly:
+    ///
+    /// ```rust
+    /// use systemd_journal_logger::JournalLog;
+    ///
+    /// let logger = JournalLog::from_env().unwrap();
+    /// log::set_max_level(logger.max_level());
+    /// logger.install().unwrap();
+    /// ```
+    ///
+    /// Requires the `env_filter` feature.
+    #[cfg(feature = "env_filter")]
+    pub fn from_env() -> std::io::Result<Self> {
+        let spec = std::env::var("RUST_LOG").unwrap_or_default();
+        Ok(Self::new()?.with_filter(&spec))
+    }
+
+    /// Filter records by target and level according to `spec`.
+    ///
+    /// `spec` uses the syntax of `$RUST_LOG` for `env_logger`: A
+    /// comma-separated list of directives, where each directive is either a
+    /// level like `info`, which applies to all targets without a more specific
+    /// directive, or `target=level`, which applies to `target` and all modules
+    /// beneath, e.g. `info,mycrate=debug,noisy=off`.  A target without level
+    /// enables all levels for the target.  The most specific directive for a
+    /// target wins; without a level directive the logger only logs errors.
+    /// The logger ignores directives with invalid levels.
+    ///
+    /// The filter applies to [`Log::enabled`] and [`Log::log`], but not to
+    /// [`Self::journal_send`].
+    ///
+    /// Requires the `env_filter` feature.
+    #[cfg(feature = "env_filter")]
+    pub fn with_filter(mut self, spec: &str) -> Self {
+        self.filter = Some(filter::Filter::parse(spec));
+        self
+    }
+
+    /// Filter records with `filter`.
+    ///
+    /// Consult `filter` in [`Log::log`] with every complete record, and
+    /// discard the record if `filter` rejects it.  Also consult `filter` in
+    /// [`Log::enabled`] with only the metadata of a record, for callers which
+    /// explicitly check whether the logger is enabled; see [`RecordFilter`]
+    /// for details and the costs of either call.  The logger applies `filter`
+    /// after the filter of `JournalLog::with_filter` with the `env_filter`
+    /// feature, and before pausing and sampling.
+    ///
+    /// ```rust
+    /// use log::{Metadata, Record};
+    /// use systemd_journal_logger::JournalLog;
+    ///
+    /// let logger = JournalLog::new().unwrap().with_record_filter(Box::new(
+    ///     |metadata: &Metadata, _: Option<&Record>| !metadata.target().starts_with("hyper"),
+    /// ));
+    /// ```
+    ///
+    /// Replace any previous record filter.  Like all filters `filter` applies
+    /// neither to [`Self::journal_send`] nor to [`Self::max_level`].
+    pub fn with_record_filter(mut self, filter: Box<dyn RecordFilter>) -> Self {
+        self.record_filter = Some(filter);
+        self
+    }
+
+    /// Whether the record filter of this logger accepts `metadata` and
+    /// `record`, if any.
+    fn record_filter_accepts(&self, metadata: &Metadata, record: Option<&Record>) -> bool {
+        self.record_filter
+            .as_ref()
+            .map_or(true, |filter| filter.should_log(metadata, record))
+    }
+
+    /// The most verbose level this logger logs.
+    ///
+    /// Consider the filter of this logger, if any (see
+    /// `JournalLog::with_filter` with the `env_filter` feature).  Pass the result to
+    /// [`log::set_max_level`] to skip records this logger discards anyway.
+    pub fn max_level(&self) -> LevelFilter {
+        #[cfg(feature = "env_filter")]
+        if let Some(filter) = &self.filter {
+            return filter.max_level();
+        }
+        LevelFilter::Trace
+    }
+
+    /// Whether the filter of this logger accepts records with `metadata`.
+    #[cfg_attr(not(feature = "env_filter"), allow(unused_variables))]
+    fn filter_enabled(&self, metadata: &Metadata) -> bool {
+        #[cfg(feature = "env_filter")]
+        if let Some(filter) = &self.filter {
+            return filter.enabled(metadata);
+        }
+        true
+    }
+
+    /// Only log a random sample of records at or below `level`.
+    ///
+    /// Log only about the given `rate` of all records at `level` or less
+    /// severe levels, e.g. `with_sampling(Level::Trace, 0.01)` logs only about
+    /// one percent of all trace records.  Always log records at more severe
+    /// levels.  A rate of 1 or more logs all records, a rate of 0 or less none.
+    ///
+    /// [`Log::log`] decides whether to log a record before formatting it, so
+    /// discarded records cost next to nothing.  See
+    /// [`Self::sampled_out_records`] for the number of discarded records.
+    /// [`Self::journal_send`] always sends records, regardless of sampling.
+    pub fn with_sampling(mut self, level: Level, rate: f64) -> Self {
+        self.sampling = Some(Sampling::new(level, rate));
+        self
+    }
+
+    /// Periodically log a summary of dropped records.
+    ///
+    /// Log a journal entry with the number of records dropped since the last
+    /// summary, at most once every `interval`, and only if the logger dropped
+    /// any records since the last summary.  The entry has the message ID
+    /// [`DROP_SUMMARY_MESSAGE_ID`] and the priority `4` (warning), and has
+    /// the following fields with the numbers of dropped records by reason:
+    ///
+    /// - `DROPPED_SAMPLED`: Records discarded by sampling (see
+    ///   [`Self::with_sampling`]).
+    /// - `DROPPED_WOULD_BLOCK`: Records dropped because sending them would
+    ///   block (see [`Self::dropped_records`]).
+    /// - `DROPPED_PAUSED`: Records dropped while the logger was paused (see
+    ///   [`Self::pause`]).
+    ///
+    /// Use `journalctl MESSAGE_ID=854e5a3fc8224016a717f79e10e2e348` to find
+    /// all summaries:
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use log::Level;
+    /// use systemd_journal_logger::JournalLog;
+    ///
+    /// let logger = JournalLog::new()
+    ///     .unwrap()
+    ///     .with_sampling(Level::Debug, 0.1)
+    ///     .with_drop_summary_interval(Duration::from_secs(60));
+    /// ```
+    ///
+    /// The logger checks for dropped records on a separate thread, which it
+    /// starts with the first record it handles (see [`Self::log_record`]), so
+    /// that it logs summaries even if it drops every record.  Like the panic
+    /// hook (see [`Self::install_panic_hook`]) the thread sends summaries
+    /// directly to journald's socket, or through the transport of this logger
+    /// (see [`Self::with_transport`]), with the syslog identifier and extra
+    /// fields of this logger.  The thread terminates when this logger is
+    /// dropped.
+    pub fn with_drop_summary_interval(mut self, interval: Duration) -> Self {
+        self.drop_summary = Some(DropSummaryThread::new(interval));
+        self
+    }
+
+    /// Use `catalog` to look up message IDs for [`Self::log_catalog`].
+    ///
+    /// Replace the previous catalog of this logger, if any.
+    pub fn with_catalog(mut self, catalog: Catalog) -> Self {
+        self.catalog = catalog;
+        self
+    }
+
+    /// Start the thread which summarizes dropped records, if not yet started.
+    ///
+    /// See [`Self::with_drop_summary_interval`].
+    fn start_drop_summary(&self) {
+        let summary = match &self.drop_summary {
+            Some(summary) if summary.should_start() => summary,
+            _ => return,
+        };
+        let shared = self.shared.clone();
+        let background = self.background.clone();
+        let counts = move || shared.drop_counts(background.as_deref());
+        let transport = self.transport();
+        let syslog_identifier = self.format.syslog_identifier.clone();
+        let shared = self.shared.clone();
+        let send = move |dropped, elapsed| {
+            let fields = drop_summary::payload(&syslog_identifier, dropped, elapsed);
+            let extra_fields = shared.extra_fields_snapshot();
+            // If journald is too slow for the summary there's nothing we can do.
+            let _ = transport
+                .send_payload_vectored(&[IoSlice::new(&fields), IoSlice::new(&extra_fields)]);
+        };
+        // Without a thread we can't summarize dropped records, but we can
+        // still log.
+        let _ = summary.spawn(counts, send);
+    }
+
+    /// The number of records discarded by sampling.
+    ///
+    /// See [`Self::with_sampling`].
+    pub fn sampled_out_records(&self) -> u64 {
+        self.shared.sampled_out.load(Ordering::Relaxed)
+    }
+
+    /// Pause this logger.
+    ///
+    /// While paused [`Log::log`] drops all records, and counts them in
+    /// [`Self::paused_records`].  Use this to cheaply disable the logger during
+    /// critical sections without uninstalling it.  See
+    /// [`Self::install_with_handle`] to pause an installed logger.
+    pub fn pause(&self) {
+        self.shared.pause();
+    }
+
+    /// Resume this logger after [`Self::pause`].
+    pub fn resume(&self) {
+        self.shared.resume();
+    }
+
+    /// Whether this logger is paused.
+    pub fn is_paused(&self) -> bool {
+        self.shared.is_paused()
+    }
+
+    /// The number of records dropped while this logger was paused.
+    pub fn paused_records(&self) -> u64 {
+        self.shared.paused_records.load(Ordering::Relaxed)
+    }
+
+    /// Set how to send records too large for a single datagram.
+    ///
+    /// journald limits the size of datagrams; the logger passes larger records
+    /// as file descriptor instead.  By default the logger uses a sealed memfd
+    /// for this purpose, and falls back to a temporary file in `/dev/shm` if
+    /// the kernel doesn't support memfds or sealing, or a seccomp profile
+    /// denies either.  See [`LargePayloadStrategy`] for all options.
+    pub fn with_large_payload_strategy(mut self, strategy: LargePayloadStrategy) -> Self {
+        self.client.set_large_payload_strategy(strategy);
+        self
+    }
+
+    /// Send records larger than `threshold` bytes directly as file descriptor.
+    ///
+    /// By default the logger first tries to send every record as datagram, and
+    /// only passes a record as file descriptor (see
+    /// [`Self::with_large_payload_strategy`]) if the record is too large for a
+    /// datagram.  With a threshold the logger passes records larger than the
+    /// threshold as file descriptor right away, to avoid a failed system call
+    /// for every record which is known to be too large for a datagram.
+    pub fn with_large_payload_threshold(mut self, threshold: usize) -> Self {
+        self.client.set_large_payload_threshold(Some(threshold));
+        self
+    }
+
+    /// Whether to send the credentials of this process with every record.
+    ///
+    /// journald takes the trusted fields `_PID`, `_UID`, and `_GID` of entries
+    /// from the credentials of the socket.  Usually the kernel fills these in
+    /// by itself, but in some setups with user or PID namespaces journald
+    /// records the wrong identity.  If `enabled` the logger explicitly sends
+    /// the PID and the real user and group IDs of this process as
+    /// `SCM_CREDENTIALS` with every record.
+    ///
+    /// Disabled by default; only enable this if journald records wrong
+    /// credentials for this process.
+    pub fn with_send_credentials(mut self, enabled: bool) -> Self {
+        self.client.set_send_credentials(enabled);
+        self
+    }
+
+    /// Request a send buffer of `size` bytes for the socket to journald.
+    ///
+    /// The send buffer holds records until journald receives them; if it is
+    /// full, sending a record blocks until journald catches up.  Increase the
+    /// send buffer to absorb large bursts of records without blocking.
+    ///
+    /// The kernel doubles the requested size for bookkeeping overhead, and
+    /// clamps it to the `net.core.wmem_max` sysctl.  Use
+    /// [`Self::send_buffer_size`] to get the actual size of the send buffer,
+    /// e.g. to detect whether the kernel clamped the requested size:
+    ///
+    /// ```rust
+    /// use systemd_journal_logger::JournalLog;
+    ///
+    /// let logger = JournalLog::new()
+    ///     .unwrap()
+    ///     .with_send_buffer_size(1024 * 1024)
+    ///     .unwrap();
+    /// if logger.send_buffer_size().unwrap() < 2 * 1024 * 1024 {
+    ///     eprintln!("Send buffer clamped, consider raising net.core.wmem_max");
+    /// }
+    /// ```
+    ///
+    /// Loggers which share the socket of this logger (see
+    /// [`Self::new_sharing_socket`]) share the send buffer as well.
+    ///
+    /// # Errors
+    ///
+    /// Return an error if setting `SO_SNDBUF` on the socket fails.
+    pub fn with_send_buffer_size(self, size: usize) -> std::io::Result<Self> {
+        self.client.set_send_buffer_size(size)?;
+        Ok(self)
+    }
+
+    /// The actual size of the send buffer of the socket to journald, in bytes.
+    ///
+    /// See [`Self::with_send_buffer_size`].
+    pub fn send_buffer_size(&self) -> std::io::Result<usize> {
+        self.client.send_buffer_size()
+    }
+
+    /// Whether to drop records instead of blocking if journald is slow.
+    ///
+    /// If `nonblocking` set the socket to journald to non-blocking mode: If
+    /// the send buffer of the socket is full (see
+    /// [`Self::with_send_buffer_size`]), sending a record fails with
+    /// [`std::io::ErrorKind::WouldBlock`] right away, and the logger drops the
+    /// record and counts it (see [`Self::dropped_records`]).  Use this mode
+    /// to guarantee that logging never blocks, at the expense of losing
+    /// records in bursts:
+    ///
+    /// ```rust
+    /// use systemd_journal_logger::JournalLog;
+    ///
+    /// let logger = JournalLog::new().unwrap().with_nonblocking(true).unwrap();
+    /// ```
+    ///
+    /// Loggers which share the socket of this logger (see
+    /// [`Self::new_sharing_socket`]) share the mode as well.  With a
+    /// background sender (see [`Self::with_background_sender`]) the
+    /// background thread drops records instead of blocking.
+    ///
+    /// # Errors
+    ///
+    /// Return an error if setting `O_NONBLOCK` on the socket fails.
+    pub fn with_nonblocking(self, nonblocking: bool) -> std::io::Result<Self> {
+        self.client.set_nonblocking(nonblocking)?;
+        Ok(self)
+    }
+
+    /// Fail sends to journald which block for longer than `timeout`.
+    ///
+    /// If the send buffer of the socket is full (see
+    /// [`Self::with_send_buffer_size`]), e.g. because journald is wedged,
+    /// sending a record blocks until journald catches up, by default
+    /// indefinitely.  With a send timeout sending a record fails with
+    /// [`std::io::ErrorKind::WouldBlock`] after `timeout` instead, and the
+    /// logger drops the record and counts it (see [`Self::dropped_records`]).  Use a timeout
+    /// in latency-sensitive services, which should rather lose records than
+    /// stall.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use systemd_journal_logger::JournalLog;
+    ///
+    /// let logger = JournalLog::new()
+    ///     .unwrap()
+    ///     .with_send_timeout(Duration::from_millis(50))
+    ///     .unwrap();
+    /// ```
+    ///
+    /// Loggers which share the socket of this logger (see
+    /// [`Self::new_sharing_socket`]) share the send timeout as well.
+    ///
+    /// # Errors
+    ///
+    /// Return an [`std::io::ErrorKind::InvalidInput`] error if `timeout` is
+    /// zero, with either backend; to send without blocking use
+    /// [`Self::with_nonblocking`] instead.  Return an error if setting
+    /// `SO_SNDTIMEO` on the socket fails.
+    pub fn with_send_timeout(self, timeout: Duration) -> std::io::Result<Self> {
+        self.client.set_send_timeout(Some(timeout))?;
+        Ok(self)
+    }
+
+    /// The send timeout of the socket to journald, if any.
+    ///
+    /// See [`Self::with_send_timeout`].
+    pub fn send_timeout(&self) -> std::io::Result<Option<Duration>> {
+        self.client.send_timeout()
+    }
+
+    /// Write records to a stream connected to the journal instead of journald's socket.
+    ///
+    /// Use this backend if journald captures the output of this process, but
+    /// journald's socket is not available, e.g. in a container.  Create this
+    /// logger with [`Self::new_lenient`] in this case, because journald's
+    /// socket is not required.
+    ///
+    /// Note that the stream protocol loses all fields except the message and
+    /// the priority; see [`StreamBackend`] for details.
+    ///
+    /// ```rust
+    /// use systemd_journal_logger::{JournalLog, StreamBackend};
+    ///
+    /// let logger = JournalLog::new_lenient()
+    ///     .unwrap()
+    ///     .with_stream_backend(StreamBackend::stderr().unwrap());
+    /// ```
+    pub fn with_stream_backend(mut self, backend: StreamBackend) -> Self {
+        self.stream = Some(backend);
+        self
+    }
+
+    /// Send payloads through `transport` instead of journald's socket.
+    ///
+    /// Replace the transport of this logger, i.e. journald's socket or a
+    /// previous transport, with `transport`.  See [`JournalTransport`] for how
+    /// to implement a transport, and [`Self::new_with_transport`] for
+    /// caveats.
+    ///
+    /// The new transport also applies to the background sender (see
+    /// [`Self::with_background_sender`]), the panic hook (see
+    /// [`Self::install_panic_hook`]), and summaries of dropped records (see
+    /// [`Self::with_drop_summary_interval`]) of this logger, even if set up
+    /// before this call.
+    pub fn with_transport<T: JournalTransport + 'static>(self, transport: Arc<T>) -> Self {
+        self.shared.transport.set(Some(transport));
+        self
+    }
+
+    /// Send records to journald from a dedicated background thread.
+    ///
+    /// Spawn a thread which sends all records to journald, and make this logger
+    /// queue records for this thread instead of sending them directly.  The
+    /// queue holds at most `queue_capacity` records, but at least one; if it's
+    /// full the logger drops records instead of blocking, and counts them in
+    /// [`Self::dropped_records`].  [`Log::flush`] blocks until the thread sent
+    /// all records queued so far.
+    ///
+    /// This decouples the latency of logging calls from journald latency, at
+    /// the expense of losing records under load.
+    ///
+    /// If this logger already uses a background sender, replace it with a new
+    /// one; the old thread terminates after sending all records queued for it.
+    ///
+    /// The background thread sends records the way this logger is configured to
+    /// at the time of this call, so configure sending (e.g. with
+    /// [`Self::with_large_payload_strategy`]) before calling this method.  Only
+    /// a new transport applies to the thread as well (see
+    /// [`Self::with_transport`]).
+    ///
+    /// # Errors
+    ///
+    /// Return an error if the background thread could not be spawned.
+    pub fn with_background_sender(mut self, queue_capacity: usize) -> std::io::Result<Self> {
+        let transport = self.transport();
+        self.background = Some(Arc::new(BackgroundSender::spawn(
+            transport,
+            queue_capacity,
+        )?));
+        Ok(self)
+    }
+
+    /// Set how long to wait for the background sender on shutdown.
+    ///
+    /// See [`Self::shutdown`].  Defaults to one second.
+    pub fn with_shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    /// Shut down the background sender of this logger.
+    ///
+    /// Wait until the background thread sent all queued records and
+    /// terminated, but at most for the configured shutdown timeout (see
+    /// [`Self::with_shutdown_timeout`]).  Afterwards the logger drops all
+    /// records.
+    ///
+    /// Return `true` if the background thread terminated in time, and `false`
+    /// otherwise.  Always return `true` if this logger does not use a
+    /// background sender.
+    pub fn shutdown(&self) -> bool {
+        shutdown_background(self.background.as_deref(), self.shutdown_timeout)
+    }
+
+    /// Flush and close this logger.
+    ///
+    /// Flush all records (see [`Log::flush`]), shut down the background
+    /// sender (see [`Self::shutdown`]), and then drop this logger to close its
+    /// socket right away, e.g. to avoid leaking file descriptors in tests
+    /// which create many loggers.  Return whether the background thread
+    /// terminated in time, like [`Self::shutdown`].
+    ///
+    /// The socket stays open as long as other loggers share it (see
+    /// [`Self::new_sharing_socket`]), or a panic hook of this logger is
+    /// installed (see [`Self::install_panic_hook`]), because both hold a
+    /// reference to the socket.
+    ///
+    /// Note that [`log`] never drops the global logger, and does not permit to
+    /// replace it either, so this method only applies to loggers which are not
+    /// installed.  For an installed logger use [`JournalLogHandle::shutdown`]
+    /// to send all queued records; its socket stays open until the process
+    /// exits.
+    pub fn close(self) -> bool {
+        self.flush();
+        self.shutdown()
+    }
+
+    /// The number of records dropped because sending them would block.
+    ///
+    /// Count records dropped because the background queue was full (see
+    /// [`Self::with_background_sender`]), and records dropped because the
+    /// send buffer of the socket was full, with a non-blocking socket (see
+    /// [`Self::with_nonblocking`]) or after the send timeout (see
+    /// [`Self::with_send_timeout`]).
+    ///
+    /// Only count records logged with [`Log::log`] or [`Self::log_record`];
+    /// [`Self::journal_send`] and related methods return the error instead.
+    pub fn dropped_records(&self) -> u64 {
+        self.shared.dropped_records(self.background.as_deref())
+    }
+
+    /// The number of records sent as file descriptor instead of a datagram.
+    ///
+    /// Count records which were too large for a datagram, or larger than the
+    /// threshold for large payloads (see [`Self::with_large_payload_threshold`]),
+    /// and which the logger thus passed to journald as file descriptor (see
+    /// [`Self::with_large_payload_strategy`]).  Only count records sent to
+    /// journald's socket, and not records sent through a custom transport.
+    pub fn large_payload_records(&self) -> u64 {
+        self.client.large_payloads()
+    }
+
+    /// Format the complete journal payload for `record`, without sending it.
+    ///
+    /// Return the exact bytes [`Self::journal_send`] would send to journald
+    /// for `record`, i.e. all standard and custom fields of `record` (see
+    /// [`JournalLog`]) followed by the extra fields of this logger, in the
+    /// [native journal protocol][1].
+    ///
+    /// Use this to inspect or test the fields this logger emits without a
+    /// round trip through journald.
+    ///
+    /// If this logger adds a sequence number (see [`Self::with_sequence_field`])
+    /// every call to this method takes the next sequence number.
+    ///
+    /// [1]: https://systemd.io/JOURNAL_NATIVE_PROTOCOL/
+    pub fn format_record(&self, record: &Record) -> Vec<u8> {
+        let extra_fields = self.extra_fields_for(record);
+        let mut payload = self.format_record_fields(record, None, extra_fields.len());
+        payload.extend_from_slice(&extra_fields);
+        payload
+    }
+
+    /// Write `record` as entry in the journal export format to `writer`.
+    ///
+    /// Write the same fields as [`Self::format_record`], followed by an empty
+    /// line which terminates the entry in the [journal export format][1].  Use
+    /// this method to write records to a file or a stream, e.g. for
+    /// `systemd-journal-remote`, or to inspect entries offline.
+    ///
+    /// Like [`Self::journal_send`] this method ignores filters, pausing and
+    /// sampling of this logger.
+    ///
+    /// ```rust
+    /// use log::{Level, Record};
+    /// use systemd_journal_logger::JournalLog;
+    ///
+    /// let logger = JournalLog::empty().unwrap();
+    /// let mut export = Vec::new();
+    /// logger.write_export(
+    ///     &mut export,
+    ///     &Record::builder()
+    ///         .level(Level::Info)
+    ///         .args(format_args!("Exported"))
+    ///         .build(),
+    /// ).unwrap();
+    /// assert!(export.ends_with(b"\n\n"));
+    /// ```
+    ///
+    /// [1]: https://systemd.io/JOURNAL_EXPORT_FORMATS/
+    pub fn write_export<W: Write>(&self, writer: &mut W, record: &Record) -> std::io::Result<()> {
+        let extra_fields = self.extra_fields_for(record);
+        writer.write_all(&self.format_record_fields(record, None, 0))?;
+        writer.write_all(&extra_fields)?;
+        writer.write_all(b"\n")
+    }
+
+    /// Format the complete journal payload for `record` into `buffer`.
+    ///
+    /// Like [`Self::format_record`], but write the payload to the given
+    /// `buffer` instead of allocating a new one, and return the number of
+    /// bytes written.  Return an [`Overflow`] error if `buffer` is too small
+    /// for the payload; the error tells the required size of the buffer.
+    /// Only a payload which fits into `buffer` takes the next sequence number
+    /// (see [`Self::with_sequence_field`]).
+    ///
+    /// ```rust
+    /// use log::{Level, Record};
+    /// use systemd_journal_logger::JournalLog;
+    ///
+    /// let logger = JournalLog::empty().unwrap();
+    /// let mut buffer = [0; 1024];
+    /// let record = Record::builder()
+    ///     .level(Level::Info)
+    ///     .args(format_args!("Hello"))
+    ///     .build();
+    /// let length = logger.format_record_into(&mut buffer, &record).unwrap();
+    /// assert_eq!(&buffer[..length], logger.format_record(&record).as_slice());
+    ///
+    /// let error = logger.format_record_into(&mut buffer[..8], &record).unwrap_err();
+    /// assert_eq!(error.required(), length);
+    /// ```
+    ///
+    /// Formatting does not allocate, unless this logger adds the sources of
+    /// error values (see [`Self::with_error_chain`]), or deduplicates
+    /// key-values (see [`Self::with_dedup_fields`]).
+    pub fn format_record_into(
+        &self,
+        buffer: &mut [u8],
+        record: &Record,
+    ) -> Result<usize, Overflow> {
+        self.put_fields_into(buffer, record, &self.extra_fields_for(record))
+    }
+
+    /// Format all fields of `record`, without the extra fields of this logger.
+    ///
+    /// Add timestamp fields for `timestamp`, or for the time of the event of
+    /// `record`, or for the time of the clock of this logger, if any.  Reserve
+    /// `extra_capacity` bytes for fields the caller adds.
+    fn format_record_fields(
+        &self,
+        record: &Record,
+        timestamp: Option<SystemTime>,
+        extra_capacity: usize,
+    ) -> Vec<u8> {
+        let timestamp = timestamp.or_else(|| record_timestamp(record));
+        let sequence_capacity = self
+            .sequence_field
+            .as_ref()
+            .map_or(0, |name| name.len() + 22);
+        let timestamp_capacity = if timestamp.is_some() || self.clock.is_some() {
+            timestamp::TIMESTAMP_FIELDS_SIZE
+        } else {
+            0
+        };
+        let mut payload = record_payload(
+            &self.format,
+            record,
+            sequence_capacity + timestamp_capacity + extra_capacity,
+        );
+        let sequence = self
+            .sequence_field
+            .as_ref()
+            .map(|_| self.sequence.fetch_add(1, Ordering::Relaxed));
+        self.put_logger_fields(&mut payload, sequence, timestamp);
+        payload
+    }
+
+    /// Write all fields of `record` followed by `trailer` to `buffer`, and
+    /// return the number of bytes written.
+    ///
+    /// Take the next sequence number only if all fields fit into `buffer`, so
+    /// that an [`Overflow`] leaves no gap in the sequence numbers of entries.
+    fn put_fields_into(
+        &self,
+        buffer: &mut [u8],
+        record: &Record,
+        trailer: &[u8],
+    ) -> Result<usize, Overflow> {
+        let timestamp = record_timestamp(record);
+        loop {
+            let sequence = self
+                .sequence_field
+                .as_ref()
+                .map(|_| self.sequence.load(Ordering::Relaxed));
+            let mut fields = SliceBuffer::new(&mut *buffer);
+            put_record_fields(&mut fields, &self.format, record);
+            self.put_logger_fields(&mut fields, sequence, timestamp);
+            fields.put(trailer);
+            let length = fields.finish()?.len();
+            match sequence {
+                // Another thread took this number meanwhile, so format again
+                // with the next one.
+                Some(sequence)
+                    if self
+                        .sequence
+                        .compare_exchange(
+                            sequence,
+                            sequence + 1,
+                            Ordering::Relaxed,
+                            Ordering::Relaxed,
+                        )
+                        .is_err() => {}
+                _ => return Ok(length),
+            }
+        }
+    }
+
+    /// Write the fields this logger adds to the fields of a record.
+    ///
+    /// Write the `sequence` number if this logger adds one, and timestamp
+    /// fields for `timestamp`, or for the time of the clock of this logger.
+    fn put_logger_fields<B: FieldBuffer + ?Sized>(
+        &self,
+        buffer: &mut B,
+        sequence: Option<u64>,
+        timestamp: Option<SystemTime>,
+    ) {
+        if let (Some(name), Some(sequence)) = (&self.sequence_field, sequence) {
+            put_field_u64(buffer, FieldName::WellFormed(name), sequence);
+        }
+        if let Some(timestamp) = timestamp.or_else(|| self.clock.as_ref().map(|clock| clock())) {
+            // Omit timestamps before the epoch; journald uses the time it
+            // received the record instead.
+            let _ = timestamp::put_timestamp_fields(buffer, timestamp);
+        }
+    }
+
+    /// Send a single log record to the journal.
+    ///
+    /// Extract all fields (standard and custom) from `record` (`see [`JournalLog`]),
+    /// append all `extra_fields` given to this logger, and send the result to
+    /// journald.
+    ///
+    /// With a stream backend (see [`Self::with_stream_backend`]) write the
+    /// message of the record to the stream instead.
+    ///
+    /// With a background sender (see [`Self::with_background_sender`]) queue
+    /// the record instead, and return a [`std::io::ErrorKind::WouldBlock`]
+    /// error if the queue is full and the record was dropped.
+    pub fn journal_send(&self, record: &Record) -> std::io::Result<()> {
+        #[cfg(feature = "testing")]
+        if let Some(capture) = testing::current_capture() {
+            let extra_fields = self.extra_fields_for(record);
+            let fields = self.format_record_fields(record, None, extra_fields.len());
+            return capture
+                .send_payload_vectored(&[IoSlice::new(&fields), IoSlice::new(&extra_fields)])
+                .map(|_| ());
+        }
+        if let Some(stream) = &self.stream {
+            return self.send_to_stream(stream, record);
+        }
+        let extra_fields = self.extra_fields_for(record);
+        let fields =
+            self.format_record_fields(record, None, self.queued_extra_capacity(&extra_fields));
+        self.send_with_extra_fields(fields, &extra_fields)
+    }
+
+    /// Send a single log record to the journal, using `buffer` for its payload.
+    ///
+    /// Like [`Self::journal_send`], but format the fields of `record` into
+    /// `buffer` (see [`Self::format_record_into`]), and send them from
+    /// `buffer` together with the extra fields of this logger.  Use this
+    /// method with a preallocated buffer to send records without allocating.
+    ///
+    /// ```rust
+    /// use log::{Level, Record};
+    /// use systemd_journal_logger::JournalLog;
+    ///
+    /// let logger = JournalLog::new().unwrap();
+    /// let mut buffer = [0; 4096];
+    /// logger.journal_send_with_buffer(
+    ///     &mut buffer,
+    ///     &Record::builder()
+    ///         .level(Level::Info)
+    ///         .args(format_args!("Sent without allocation"))
+    ///         .build(),
+    /// ).unwrap();
+    /// ```
+    ///
+    /// A background sender (see [`Self::with_background_sender`]) still
+    /// copies the payload to queue it, and a stream backend (see
+    /// [`Self::with_stream_backend`]) formats the message as usual.
+    ///
+    /// # Errors
+    ///
+    /// Return an [`std::io::ErrorKind::InvalidInput`] error with the
+    /// [`Overflow`] if `buffer` is too small for the fields of `record`.  See
+    /// [`Self::journal_send`] for other errors.
+    pub fn journal_send_with_buffer(
+        &self,
+        buffer: &mut [u8],
+        record: &Record,
+    ) -> std::io::Result<()> {
+        #[cfg(feature = "testing")]
+        if let Some(capture) = testing::current_capture() {
+            let length = self.put_record_fields_into(buffer, record)?;
+            let extra_fields = self.extra_fields_for(record);
+            return capture
+                .send_payload_vectored(&[
+                    IoSlice::new(&buffer[..length]),
+                    IoSlice::new(&extra_fields),
+                ])
+                .map(|_| ());
+        }
+        if let Some(stream) = &self.stream {
+            return self.send_to_stream(stream, record);
+        }
+        let length = self.put_record_fields_into(buffer, record)?;
+        let fields = &buffer[..length];
+        let extra_fields = self.extra_fields_for(record);
+        match &self.background {
+            Some(_) => self.send_with_extra_fields(fields.to_vec(), &extra_fields),
+            None => {
+                let _ = self
+                    .send_payload_vectored(&[IoSlice::new(fields), IoSlice::new(&extra_fields)])?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Format the fields of `record` into `buffer` for sending.
+    ///
+    /// Return the length of the fields, or an
+    /// [`std::io::ErrorKind::InvalidInput`] error if `buffer` is too small.
+    fn put_record_fields_into(&self, buffer: &mut [u8], record: &Record) -> std::io::Result<usize> {
+        self.put_fields_into(buffer, record, &[])
+            .map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidInput, error))
+    }
+
+    /// Send a single log record with a known event time to the journal.
+    ///
+    /// Like [`Self::journal_send`], but add the `SOURCE_REALTIME_TIMESTAMP`
+    /// field with the microseconds since the epoch of `timestamp`, and a
+    /// `SYSLOG_TIMESTAMP` field in RFC 3164 format, in UTC.  journald still
+    /// stores entries in the order it received them, with the time of receipt
+    /// in `__REALTIME_TIMESTAMP`; `SOURCE_REALTIME_TIMESTAMP` records when the
+    /// event happened, for tools which order entries by event time.
+    ///
+    /// Use this method to replay buffered events with their original time.
+    /// `timestamp` takes precedence over the reserved key
+    /// `source_realtime_timestamp` (see [`JournalLog`]) and over the clock of
+    /// this logger (see [`Self::with_clock`]).
+    ///
+    /// ```rust
+    /// use std::time::{Duration, SystemTime};
+    /// use log::{Level, Record};
+    /// use systemd_journal_logger::JournalLog;
+    ///
+    /// let logger = JournalLog::new().unwrap();
+    /// let happened_at = SystemTime::now() - Duration::from_secs(60);
+    /// logger.send_with_timestamp(
+    ///     happened_at,
+    ///     &Record::builder()
+    ///         .level(Level::Info)
+    ///         .args(format_args!("Replayed event"))
+    ///         .build(),
+    /// ).unwrap();
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Return an [`std::io::ErrorKind::InvalidInput`] error if `timestamp` is
+    /// before the Unix epoch, and an [`std::io::ErrorKind::Unsupported`] error
+    /// with a stream backend (see [`Self::with_stream_backend`]), because the
+    /// stream protocol does not support fields.  See [`Self::journal_send`] for
+    /// other errors.
+    pub fn send_with_timestamp(
+        &self,
+        timestamp: SystemTime,
+        record: &Record,
+    ) -> std::io::Result<()> {
+        if self.stream.is_some() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "stream backend does not support fields",
+            ));
+        }
+        timestamp::since_epoch(timestamp)?;
+        let extra_fields = self.extra_fields_for(record);
+        let fields = self.format_record_fields(
+            record,
+            Some(timestamp),
+            self.queued_extra_capacity(&extra_fields),
+        );
+        self.send_with_extra_fields(fields, &extra_fields)
+    }
+
+    /// Log the given `record` with this logger.
+    ///
+    /// Like [`Log::log`], i.e. discard `record` if the filter of this logger
+    /// rejects it (see [`Self::max_level`]), if the record filter rejects it
+    /// (see [`Self::with_record_filter`]), if this logger is paused, or if
+    /// sampling drops it, but return any error which occurs when sending
+    /// `record` (see [`Self::journal_send`]).
+    ///
+    /// Start the thread which summarizes dropped records with the first
+    /// record, if any (see [`Self::with_drop_summary_interval`]).
+    ///
+    /// Use this method to send specific records to the journal with a logger
+    /// which is not installed globally, see "Without a global logger" in the
+    /// crate documentation.
+    pub fn log_record(&self, record: &Record) -> std::io::Result<()> {
+        self.start_drop_summary();
+        if !self.filter_enabled(record.metadata())
+            || !self.record_filter_accepts(record.metadata(), Some(record))
+        {
+            return Ok(());
+        }
+        if self.shared.is_paused() {
+            self.shared.paused_records.fetch_add(1, Ordering::Relaxed);
+            return Ok(());
+        }
+        if let Some(sampling) = &self.sampling {
+            if !sampling.keep(record.level()) {
+                self.shared.sampled_out.fetch_add(1, Ordering::Relaxed);
+                return Ok(());
+            }
+        }
+        let result = self.journal_send(record);
+        if let Err(error) = &result {
+            // The background sender counts records dropped from its queue
+            if error.kind() == std::io::ErrorKind::WouldBlock && self.background.is_none() {
+                self.shared.would_block.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        result
+    }
+
+    /// Send a journal entry with the given `fields`.
+    ///
+    /// Escape the name of each field according to the rules documented in
+    /// [`JournalLog`], append all `extra_fields` given to this logger, and send
+    /// the result to journald as a single entry.  Unlike [`Self::journal_send`]
+    /// this method does not add any other standard field, except for a
+    /// `PRIORITY` of `6` (info) and an empty `MESSAGE` if `fields` lacks
+    /// either.
+    ///
+    /// Use this method to send events which do not fit log records, like
+    /// `sd_journal_send` does:
+    ///
+    /// ```rust
+    /// use systemd_journal_logger::JournalLog;
+    ///
+    /// let logger = JournalLog::new().unwrap();
+    /// logger.send_fields(&[
+    ///     ("MESSAGE", b"User logged in"),
+    ///     ("AUDIT_USER", b"jdoe"),
+    /// ]).unwrap();
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Return an [`std::io::ErrorKind::Unsupported`] error with a stream
+    /// backend (see [`Self::with_stream_backend`]), because the stream protocol
+    /// does not support fields.  See [`Self::journal_send`] for other errors.
+    pub fn send_fields(&self, fields: &[(&str, &[u8])]) -> std::io::Result<()> {
+        if self.stream.is_some() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "stream backend does not support fields",
+            ));
+        }
+        let mut payload = Vec::with_capacity(1024);
+        let mut has_priority = false;
+        let mut has_message = false;
+        for (name, value) in fields {
+            let name = escape_field_name(name);
+            has_priority |= name == "PRIORITY";
+            has_message |= name == "MESSAGE";
+            put_field_length_encoded(&mut payload, FieldName::WellFormed(&name), *value);
+        }
+        if !has_priority {
+            put_field_bytes(&mut payload, FieldName::WellFormed("PRIORITY"), b"6");
+        }
+        if !has_message {
+            put_field_bytes(&mut payload, FieldName::WellFormed("MESSAGE"), b"");
+        }
+        self.send_with_extra_fields(payload, &self.shared.extra_fields_snapshot())
+    }
+
+    /// Send a journal entry with a binary `message`.
+    ///
+    /// Send `message` as is in the `MESSAGE` field, with the priority of
+    /// `level` and the syslog identifier of this logger, followed by `fields`
+    /// and all extra fields of this logger.  Escape the name of each field in
+    /// `fields` like [`Self::send_fields`] does.
+    ///
+    /// Unlike records, whose messages are always text, this method stores
+    /// arbitrary bytes losslessly, e.g. frames of binary protocols;
+    /// `journalctl --output=export` reproduces them exactly:
+    ///
+    /// ```rust
+    /// use log::Level;
+    /// use systemd_journal_logger::JournalLog;
+    ///
+    /// let logger = JournalLog::new().unwrap();
+    /// logger
+    ///     .send_binary_message(Level::Debug, &[0x02, 0xff, 0x00, 0x0a], &[("PROTOCOL", b"modbus")])
+    ///     .unwrap();
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Return an [`std::io::ErrorKind::Unsupported`] error with a stream
+    /// backend (see [`Self::with_stream_backend`]), because the stream protocol
+    /// does not support binary messages.  See [`Self::journal_send`] for other
+    /// errors.
+    pub fn send_binary_message(
+        &self,
+        level: Level,
+        message: &[u8],
+        fields: &[(&str, &[u8])],
+    ) -> std::io::Result<()> {
+        if self.stream.is_some() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "stream backend does not support binary messages",
+            ));
+        }
+        let mut payload = Vec::with_capacity(message.len() + 1024);
+        put_field_bytes(
+            &mut payload,
+            FieldName::WellFormed("PRIORITY"),
+            priority(level),
+        );
+        put_field_length_encoded(&mut payload, FieldName::WellFormed("MESSAGE"), message);
+        if !self.format.syslog_identifier.is_empty() {
+            put_field_bytes(
+                &mut payload,
+                FieldName::WellFormed("SYSLOG_IDENTIFIER"),
+                self.format.syslog_identifier.as_bytes(),
+            );
+        }
+        for (name, value) in fields {
+            put_field_bytes(&mut payload, FieldName::WriteEscaped(name), value);
+        }
+        self.send_with_extra_fields(payload, &self.shared.extra_fields_snapshot())
+    }
+
+    /// The extra fields to add to `record`.
+    ///
+    /// Return no extra fields if `record` asks to skip them, see
+    /// [`SKIP_EXTRA_FIELDS`].
+    ///
+    /// Return a snapshot, so that formatting and sending `record` holds no
+    /// lock, and sees the same extra fields throughout, even if another
+    /// thread updates them meanwhile.
+    fn extra_fields_for(&self, record: &Record) -> RecordExtraFields {
+        if skips_extra_fields(record) {
+            RecordExtraFields(None)
+        } else {
+            RecordExtraFields(Some(self.shared.extra_fields_snapshot()))
+        }
+    }
+
+    /// The capacity to reserve for `extra_fields` in payloads to send.
+    ///
+    /// Only a background sender appends extra fields to payloads; otherwise we
+    /// send extra fields separately.
+    fn queued_extra_capacity(&self, extra_fields: &[u8]) -> usize {
+        if self.background.is_some() {
+            extra_fields.len()
+        } else {
+            0
+        }
+    }
+
+    /// Send `fields` followed by `extra_fields` to journald.
+    fn send_with_extra_fields(&self, fields: Vec<u8>, extra_fields: &[u8]) -> std::io::Result<()> {
+        debug_assert_eq!(validate_payload(&fields), Ok(()));
+        debug_assert_eq!(validate_payload(extra_fields), Ok(()));
+        match &self.background {
+            Some(background) => {
+                let mut payload = fields;
+                payload.extend_from_slice(extra_fields);
+                background.try_send(payload)
+            }
+            None => {
+                // Send the record fields and our extra fields as separate
+                // slices, to avoid copying extra fields for every record.
+                let _ = self
+                    .send_payload_vectored(&[IoSlice::new(&fields), IoSlice::new(extra_fields)])?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Write `record` to `stream`, with the message prefix of this logger.
+    fn send_to_stream(&self, stream: &StreamBackend, record: &Record) -> std::io::Result<()> {
+        if self.format.message_prefix.is_empty() {
+            stream.send(record)
+        } else {
+            let prefix = &self.format.message_prefix;
+            stream.send(
+                &record
+                    .to_builder()
+                    .args(format_args!("{}{}", prefix, record.args()))
+                    .build(),
+            )
+        }
+    }
+
+    /// Send the concatenation of `slices` through the transport of this logger.
+    fn send_payload_vectored(&self, slices: &[IoSlice<'_>]) -> std::io::Result<usize> {
+        match self.shared.transport.get() {
+            Some(transport) => transport.send_payload_vectored(slices),
+            None => self.client.send_payload_vectored(slices),
+        }
+    }
+
+    /// Warn once if the global max level is off, see
+    /// [`Self::with_max_level_hint`].
+    fn check_max_level(&self) {
+        if self.check_max_level.load(Ordering::Relaxed)
+            && self.check_max_level.swap(false, Ordering::Relaxed)
+            && log::max_level() == LevelFilter::Off
+        {
+            let _ = self.journal_send(
+                &Record::builder()
+                    .level(Level::Warn)
+                    .target(module_path!())
+                    .args(format_args!(
+                        "Journal logger installed, but the max level is Off; \
+                         call log::set_max_level to log records"
+                    ))
+                    .build(),
+            );
+        }
+    }
+
+    /// The transport of this logger, for sending from other threads.
+    ///
+    /// Send through the current transport of this logger (see
+    /// [`Self::with_transport`]), or through the client of this logger as
+    /// configured at the time of this call.
+    fn transport(&self) -> Arc<dyn JournalTransport> {
+        Arc::new(SlotOrClient {
+            slot: self.shared.transport.clone(),
+            client: self.client.clone(),
+        })
+    }
+
+    /// Send an event with the given message `id` to the journal.
+    ///
+    /// Send a record with the given `level` and message `args`, and add a
+    /// `MESSAGE_ID` field with `id`, to link the journal entry to the
+    /// journal message catalog (see [`MessageId`]).
+    ///
+    /// ```rust
+    /// use log::Level;
+    /// use systemd_journal_logger::{JournalLog, MessageId};
+    ///
+    /// const STARTED: MessageId = MessageId::from_u128(0x39f53479d3a045ac8e11786248231fbf);
+    ///
+    /// let logger = JournalLog::new().unwrap();
+    /// logger.log_event(STARTED, Level::Info, format_args!("Service started")).unwrap();
+    /// ```
+    ///
+    /// See [`Self::journal_send`] for errors.
+    pub fn log_event(
+        &self,
+        id: MessageId,
+        level: Level,
+        args: Arguments<'_>,
+    ) -> std::io::Result<()> {
+        let kvs = [("MESSAGE_ID", id.to_value())];
+        self.journal_send(
+            &Record::builder()
+                .level(level)
+                .args(args)
+                .key_values(&kvs)
+                .build(),
+        )
+    }
+
+    /// Send an I/O `error` with its errno to the journal.
+    ///
+    /// Send a record with the given `level` and the message `context: error`,
+    /// and add an `ERRNO` field with the raw OS error of `error`, if any, like
+    /// `sd_journal_print` does for `%m`:
+    ///
+    /// ```rust
+    /// use log::Level;
+    /// use systemd_journal_logger::JournalLog;
+    ///
+    /// let logger = JournalLog::new().unwrap();
+    /// if let Err(error) = std::fs::read("/etc/does-not-exist") {
+    ///     logger.log_io_error(Level::Warn, "Failed to read config", &error).unwrap();
+    /// }
+    /// ```
+    ///
+    /// See [`Self::journal_send`] for errors.
+    pub fn log_io_error(
+        &self,
+        level: Level,
+        context: &str,
+        error: &std::io::Error,
+    ) -> std::io::Result<()> {
+        let errno = error.raw_os_error();
+        let kvs = [("ERRNO", errno.to_value())];
+        let kvs = if errno.is_some() { &kvs[..] } else { &[] };
+        self.journal_send(
+            &Record::builder()
+                .level(level)
+                .args(format_args!("{}: {}", context, error))
+                .key_values(&kvs)
+                .build(),
+        )
+    }
+
+    /// Send the catalog `event` to the journal.
+    ///
+    /// Look up the message ID of `event` in the catalog of this logger (see
+    /// [`Self::with_catalog`]), and send the event like [`Self::log_event`].
+    ///
+    /// ```rust
+    /// use log::Level;
+    /// use systemd_journal_logger::{Catalog, JournalLog, MessageId};
+    ///
+    /// let catalog = Catalog::new([(
+    ///     "started",
+    ///     MessageId::from_u128(0x39f53479d3a045ac8e11786248231fbf),
+    /// )]);
+    /// catalog.require(&["started"]).unwrap();
+    /// let logger = JournalLog::new().unwrap().with_catalog(catalog);
+    /// logger.log_catalog("started", Level::Info, format_args!("Service started")).unwrap();
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Return an [`std::io::ErrorKind::InvalidInput`] error with the
+    /// [`UnknownCatalogEvent`] if the catalog does not know `event`; use
+    /// [`Catalog::require`] to check events at startup.  See
+    /// [`Self::journal_send`] for other errors.
+    pub fn log_catalog(
+        &self,
+        event: &str,
+        level: Level,
+        args: Arguments<'_>,
+    ) -> std::io::Result<()> {
+        let id = self
+            .catalog
+            .lookup(event)
+            .map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidInput, error))?;
+        self.log_event(id, level, args)
+    }
+}
+
+/// The [`Log`] interface for [`JournalLog`].
+impl Log for JournalLog {
+    /// Whether this logger is enabled.
+    ///
+    /// Returns `true`, unless the filter of this logger rejects `metadata` (see
+    /// `JournalLog::with_filter` with the `env_filter` feature), or the record
+    /// filter of this logger rejects `metadata` (see
+    /// [`JournalLog::with_record_filter`]).  Records above the static max
+    /// level features of [`log`] never reach this logger, see "Static max
+    /// level" in the crate documentation.
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filter_enabled(metadata) && self.record_filter_accepts(metadata, None)
+    }
+
+    /// Send the given `record` to the systemd journal.
+    ///
+    /// # Errors
+    ///
+    /// Ignore any errors which occur when sending `record` to journald because
+    /// we cannot reasonably handle them at this place.
+    ///
+    /// See [`JournalLog::journal_send`] for a function which returns any error
+    /// which might have occurred while sending the `record` to the journal.
+    fn log(&self, record: &Record) {
+        self.check_max_level();
+        // We can't really handle errors here, so simply discard them.
+        // The alternative would be to panic, but a failed logging call should
+        // not bring the entire process down.
+        let _ = self.log_record(record);
+    }
+
+    /// Flush log records.
+    ///
+    /// With a background sender wait until all queued records were sent.
+    /// Otherwise wait at most [`FLUSH_TIMEOUT`] until the socket to journald
+    /// is writable, i.e. until the kernel handed previously sent records to
+    /// journald, on a best-effort basis.
+    ///
+    /// Use [`JournalLog::shutdown`] to reliably send all queued records before
+    /// the process exits.
+    fn flush(&self) {
+        self.check_max_level();
+        match &self.background {
+            Some(background) => background.flush(),
+            None => {
+                let _ = self.client.wait_writable(FLUSH_TIMEOUT);
+            }
+        }
+    }
+}
+
+impl Drop for JournalLog {
+    /// Shut down the background sender, if any.
+    ///
+    /// See [`JournalLog::shutdown`].
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+fn shutdown_background(background: Option<&BackgroundSender>, timeout: Duration) -> bool {
+    background.map_or(true, |background| background.shutdown(timeout))
+}
+
+/// A handle to shut down an installed [`JournalLog`].
+///
+/// See [`JournalLog::install_with_handle`].
+#[derive(Clone)]
+pub struct JournalLogHandle {
+    background: Option<Arc<BackgroundSender>>,
+    shutdown_timeout: Duration,
+    shared: Arc<Shared>,
+}
+
+impl JournalLogHandle {
+    /// Shut down the background sender of the installed logger.
+    ///
+    /// See [`JournalLog::shutdown`].
+    pub fn shutdown(self) -> bool {
+        shutdown_background(self.background.as_deref(), self.shutdown_timeout)
+    }
+
+    /// Pause the installed logger.
+    ///
+    /// See [`JournalLog::pause`].
+    pub fn pause(&self) {
+        self.shared.pause();
+    }
+
+    /// Resume the installed logger.
+    ///
+    /// See [`JournalLog::resume`].
+    pub fn resume(&self) {
+        self.shared.resume();
+    }
+
+    /// Whether the installed logger is paused.
+    pub fn is_paused(&self) -> bool {
+        self.shared.is_paused()
+    }
+
+    /// The number of records dropped while the installed logger was paused.
+    pub fn paused_records(&self) -> u64 {
+        self.shared.paused_records.load(Ordering::Relaxed)
+    }
+
+    /// Set the extra field `name` of the installed logger to `value`.
+    ///
+    /// See [`JournalLog::set_extra_field`].
+    pub fn set_extra_field<K: AsRef<str>, V: AsRef<[u8]>>(
+        &self,
+        name: K,
+        value: V,
+    ) -> Result<(), InvalidFieldName> {
+        self.shared.set_extra_field(name.as_ref(), value.as_ref())
+    }
+}
+
+/// State shared between a [`JournalLog`] and its handles.
+#[derive(Default)]
+struct Shared {
+    /// Whether the logger is paused.
+    paused: AtomicBool,
+    /// The number of records dropped while paused.
+    paused_records: AtomicU64,
+    /// The number of records discarded by sampling.
+    sampled_out: AtomicU64,
+    /// The number of records dropped because sending them would block.
+    would_block: AtomicU64,
+    /// The extra fields of the logger.
+    extra_fields: RwLock<ExtraFields>,
+    /// The custom transport of the logger, if any.
+    transport: Arc<TransportSlot>,
+}
+
+impl Shared {
+    fn pause(&self) {
+        self.paused.store(true, Ordering::Relaxed);
+    }
+
+    fn resume(&self) {
+        self.paused.store(false, Ordering::Relaxed);
+    }
+
+    fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// The number of records dropped because sending them would block,
+    /// including records dropped by `background`.
+    fn dropped_records(&self, background: Option<&BackgroundSender>) -> u64 {
+        self.would_block.load(Ordering::Relaxed) + background.map_or(0, BackgroundSender::dropped)
+    }
+
+    /// The total numbers of dropped records so far.
+    fn drop_counts(&self, background: Option<&BackgroundSender>) -> DropCounts {
+        DropCounts {
+            sampled: self.sampled_out.load(Ordering::Relaxed),
+            would_block: self.dropped_records(background),
+            paused: self.paused_records.load(Ordering::Relaxed),
+        }
+    }
+
+    fn extra_fields(&self) -> RwLockReadGuard<'_, ExtraFields> {
+        self.extra_fields
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// A snapshot of the current extra fields.
+    ///
+    /// Take the lock only to clone the snapshot, so that callers can send the
+    /// snapshot without blocking writers.
+    fn extra_fields_snapshot(&self) -> Arc<Vec<u8>> {
+        self.extra_fields().payload.clone()
+    }
+
+    fn extra_fields_mut(&self) -> RwLockWriteGuard<'_, ExtraFields> {
+        self.extra_fields
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn set_extra_field(&self, name: &str, value: &[u8]) -> Result<(), InvalidFieldName> {
+        validate_field_name(name)?;
+        let mut extra_fields = self.extra_fields_mut();
+        let field = replace_field(extra_fields.payload_mut(), name, value);
+        if extra_fields.exceeds_limit() {
+            extra_fields.payload_mut().drain(field);
+        }
+        Ok(())
+    }
+}
+
+/// Preformatted extra fields to be appended to every log message.
+#[derive(Default)]
+struct ExtraFields {
+    /// The fields, in the native journal protocol.
+    ///
+    /// Shared with snapshots of the fields; writers copy the fields if a
+    /// snapshot still refers to them.
+    payload: Arc<Vec<u8>>,
+    /// The maximum size of the fields in bytes, if any.
+    max_bytes: Option<usize>,
+}
+
+impl ExtraFields {
+    fn exceeds_limit(&self) -> bool {
+        self.max_bytes.map_or(false, |max| max < self.payload.len())
+    }
+
+    /// The fields, for modification.
+    fn payload_mut(&mut self) -> &mut Vec<u8> {
+        Arc::make_mut(&mut self.payload)
+    }
+
+    /// Drop fields from `start` onwards if they exceed the size limit.
+    fn limit(&mut self, start: usize) {
+        if self.exceeds_limit() {
+            self.payload_mut().truncate(start);
+        }
+    }
+}
+
+/// A snapshot of the extra fields to append to a record, if any.
+struct RecordExtraFields(Option<Arc<Vec<u8>>>);
+
+impl std::ops::Deref for RecordExtraFields {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.0.as_deref().map_or(&[], Vec::as_slice)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use similar_asserts::assert_eq;
+
+    #[derive(Debug)]
+    struct ChainedError(&'static str, Option<Box<ChainedError>>);
+
+    impl std::fmt::Display for ChainedError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str(self.0)
+        }
+    }
+
+    impl std::error::Error for ChainedError {
+        fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+            self.1
+                .as_deref()
+                .map(|e| e as &(dyn std::error::Error + 'static))
+        }
+    }
+
+    #[test]
+    fn record_payload_error_chain() {
+        let error = ChainedError(
+            "outer",
+            Some(Box::new(ChainedError(
+                "middle",
+                Some(Box::new(ChainedError("inner", None))),
+            ))),
+        );
+        let kvs = [("error", Value::from_dyn_error(&error))];
+        let record = Record::builder()
+            .args(format_args!("Failed"))
+            .key_values(&kvs)
+            .build();
+
+        let format = RecordFormat {
+            error_chain: true,
+            ..RecordFormat::default()
+        };
+        let payload = record_payload(&format, &record, 0);
+        let fields = b"ERROR\n\x05\0\0\0\0\0\0\0outer\n\
+            ERROR_SOURCE_0\n\x06\0\0\0\0\0\0\0middle\n\
+            ERROR_SOURCE_1\n\x05\0\0\0\0\0\0\0inner\n";
+        assert_eq!(&payload[payload.len() - fields.len()..], fields);
+
+        let payload = record_payload(&RecordFormat::default(), &record, 0);
+        let fields = b"ERROR\n\x05\0\0\0\0\0\0\0outer\n";
+        assert_eq!(&payload[payload.len() - fields.len()..], fields);
+    }
+
+    #[test]
+    fn record_payload_dedup_fields() {
+        let kvs = [("request", "outer"), ("spam", "eggs")];
+        let more_kvs = [("request", "inner")];
+        let sources: [&dyn log::kv::Source; 2] = [&kvs, &more_kvs];
+        let record = Record::builder()
+            .target("test")
+            .args(format_args!("Hello"))
+            .key_values(&sources)
+            .build();
+
+        let format = RecordFormat {
+            dedup_fields: true,
+            ..RecordFormat::default()
+        };
+        let payload = record_payload(&format, &record, 0);
+        let fields = b"TARGET=test\n\
+            SPAM\n\x04\0\0\0\0\0\0\0eggs\n\
+            REQUEST\n\x05\0\0\0\0\0\0\0inner\n";
+        assert_eq!(&payload[payload.len() - fields.len()..], fields);
+
+        let payload = record_payload(&RecordFormat::default(), &record, 0);
+        let fields = b"REQUEST\n\x05\0\0\0\0\0\0\0outer\n\
+            SPAM\n\x04\0\0\0\0\0\0\0eggs\n\
+            REQUEST\n\x05\0\0\0\0\0\0\0inner\n";
+        assert_eq!(&payload[payload.len() - fields.len()..], fields);
+    }
+
+    #[test]
+    fn record_payload_dedup_fields_by_escaped_name() {
+        let kvs = [("request", "lower"), ("spam", "eggs"), ("REQUEST", "upper")];
+        let record = Record::builder()
+            .target("test")
+            .args(format_args!("Hello"))
+            .key_values(&kvs)
+            .build();
+
+        let format = RecordFormat {
+            dedup_fields: true,
+            ..RecordFormat::default()
+        };
+        let payload = record_payload(&format, &record, 0);
+        let fields = b"TARGET=test\n\
+            SPAM\n\x04\0\0\0\0\0\0\0eggs\n\
+            REQUEST\n\x05\0\0\0\0\0\0\0upper\n";
+        assert_eq!(&payload[payload.len() - fields.len()..], fields);
+    }
+
+    #[test]
+    fn panic_payload() {
+        let location = std::panic::Location::caller();
+        let payload =
+            super::panic_payload("foo", "oops", Some(location), Some("main"), Some("stack\n"));
+        let message = format!("panicked at {}: oops", location);
+        let mut expected = b"PRIORITY=2\nMESSAGE\n".to_vec();
+        expected.extend_from_slice(&(message.len() as u64).to_le_bytes());
+        expected.extend_from_slice(
+            format!(
+                "{}\nMESSAGE_ID=a19f150c554c47ffb8e04ea885ab82dc\n\
+                PANIC_MESSAGE\n\x04\0\0\0\0\0\0\0oops\nSYSLOG_PID={}\nSYSLOG_IDENTIFIER=foo\nCODE_FILE={}\nCODE_LINE={}\nTHREAD_NAME=main\n",
+                message,
+                std::process::id(),
+                location.file(),
+                location.line()
+            )
+            .as_bytes(),
+        );
+        expected.extend_from_slice(b"BACKTRACE\n\x06\0\0\0\0\0\0\0stack\n\n");
+        assert_eq!(payload, expected);
+
+        let payload = super::panic_payload("", "oops", None, None, None);
+        let fields = b"THREAD_NAME=<unnamed>\n";
+        assert!(payload.ends_with(fields));
+        assert!(payload.starts_with(b"PRIORITY=2\nMESSAGE\n\x0e\0\0\0\0\0\0\0panicked: oops\n"));
+    }
+
+    #[test]
+    fn fallback_identifier() {
+        let exe = || Some("exe".to_string());
+        let argv0 = || Some("argv0".to_string());
+        assert_eq!(super::fallback_identifier(exe, argv0), "exe");
+        assert_eq!(super::fallback_identifier(|| None, argv0), "argv0");
+        assert_eq!(super::fallback_identifier(|| None, || None), "unknown");
+        assert_eq!(default_identifier(), current_exe_identifier().unwrap());
+    }
+
+    #[test]
+    fn estimate_payload_size() {
+        let record = Record::builder()
+            .level(Level::Info)
+            .target("estimate_payload_size")
+            .file(Some(file!()))
+            .module_path(Some(module_path!()))
+            .line(Some(line!()))
+            .args(format_args!("A message without arguments"))
+            .build();
+        let format = RecordFormat {
+            syslog_identifier: "foo".to_string(),
+            target_as_field: Some("COMPONENT".to_string()),
+            ..RecordFormat::default()
+        };
+        let payload = record_payload(&format, &record, 0);
+        let estimate = super::estimate_payload_size(&format, &record);
+        assert!(
+            payload.len() <= estimate,
+            "{} > {}",
+            payload.len(),
+            estimate
+        );
+        // Without key-values and with a static message the estimate is tight
+        assert!(
+            estimate - payload.len() < 32,
+            "{} vs {}",
+            payload.len(),
+            estimate
+        );
+        // Vec may allocate more than requested
+        assert!(
+            estimate <= payload.capacity(),
+            "{} < {}",
+            payload.capacity(),
+            estimate
+        );
+    }
+
+    #[test]
+    fn record_payload_code_func() {
+        let kvs = [("code_func", "handle_request"), ("code_function", "other")];
+        let record = Record::builder()
+            .args(format_args!("Hello"))
+            .key_values(&kvs)
+            .build();
+        let payload = record_payload(&RecordFormat::default(), &record, 0);
+        let fields = b"CODE_FUNC\n\x0e\0\0\0\0\0\0\0handle_request\n\
+            CODE_FUNCTION\n\x05\0\0\0\0\0\0\0other\n";
+        assert_eq!(&payload[payload.len() - fields.len()..], fields);
+    }
+
+    #[test]
+    fn record_payload_standard_fields() {
+        let record = Record::builder()
+            .args(format_args!("Hello"))
+            .target("foo")
+            .file(Some("foo.rs"))
+            .line(Some(42))
+            .module_path(Some("foo::bar"))
+            .build();
+        let format = RecordFormat {
+            standard_fields: StandardFields::CODE_LINE,
+            target_as_field: Some("TARGET".to_string()),
+            ..RecordFormat::default()
+        };
+        assert_eq!(
+            record_payload(&format, &record, 0),
+            b"PRIORITY=5\nMESSAGE\n\x05\0\0\0\0\0\0\0Hello\nCODE_LINE=42\nTARGET=foo\n"
+        );
+        let format = RecordFormat {
+            standard_fields: StandardFields::empty(),
+            ..RecordFormat::default()
+        };
+        assert_eq!(
+            record_payload(&format, &record, 0),
+            b"PRIORITY=5\nMESSAGE\n\x05\0\0\0\0\0\0\0Hello\n"
+        );
+    }
+
+    #[test]
+    fn record_payload_message_prefix() {
+        let record = Record::builder().args(format_args!("Hello")).build();
+        let format = RecordFormat {
+            message_prefix: "[foo] ".to_string(),
+            standard_fields: StandardFields::empty(),
+            target_field: None,
+            ..RecordFormat::default()
+        };
+        let payload = record_payload(&format, &record, 0);
+        assert_eq!(
+            payload,
+            b"PRIORITY=5\nMESSAGE\n\x0b\0\0\0\0\0\0\0[foo] Hello\n"
+        );
+        // Account for the prefix in the size estimate
+        assert_eq!(
+            super::estimate_payload_size(&format, &record),
+            payload.len()
+        );
+    }
+
+    #[test]
+    fn record_payload_skip_empty_values() {
+        let kvs = [("empty", ""), ("foo", "bar"), ("also_empty", "")];
+        let record = Record::builder()
+            .args(format_args!(""))
+            .key_values(&kvs)
+            .build();
+        let format = RecordFormat {
+            skip_empty_values: true,
+            standard_fields: StandardFields::empty(),
+            target_field: None,
+            ..RecordFormat::default()
+        };
+        assert_eq!(
+            record_payload(&format, &record, 0),
+            b"PRIORITY=5\nMESSAGE\n\0\0\0\0\0\0\0\0\nFOO\n\x03\0\0\0\0\0\0\0bar\n"
+        );
+
+        let mut buffer = [0; 64];
+        let mut slice = SliceBuffer::new(&mut buffer);
+        put_record_fields(&mut slice, &format, &record);
+        assert_eq!(slice.finish().unwrap(), record_payload(&format, &record, 0));
+    }
+
+    #[test]
+    fn record_payload_severity() {
+        let format = RecordFormat {
+            standard_fields: StandardFields::empty(),
+            target_field: None,
+            ..RecordFormat::default()
+        };
+        for (severity, expected) in [
+            ("CRITICAL", &b"PRIORITY=2\nMESSAGE\n\0\0\0\0\0\0\0\0\n"[..]),
+            ("notice", b"PRIORITY=5\nMESSAGE\n\0\0\0\0\0\0\0\0\n"),
+            ("Informational", b"PRIORITY=6\nMESSAGE\n\0\0\0\0\0\0\0\0\n"),
+            (
+                "bogus",
+                b"PRIORITY=3\nMESSAGE\n\0\0\0\0\0\0\0\0\nSEVERITY\n\x05\0\0\0\0\0\0\0bogus\n",
+            ),
+        ] {
+            let kvs = [("severity", severity)];
+            let record = Record::builder()
+                .level(Level::Error)
+                .args(format_args!(""))
+                .key_values(&kvs)
+                .build();
+            assert_eq!(
+                record_payload(&format, &record, 0),
+                expected,
+                "{}",
+                severity
+            );
+        }
+    }
+
+    #[test]
+    fn record_payload_object_fields() {
+        let kvs = [
+            ("object_pid", "42"),
+            ("object_systemd_unit", "worker@3.service"),
+            ("Object-Systemd-User-Unit", "app.service"),
+        ];
+        let record = Record::builder()
+            .args(format_args!(""))
+            .key_values(&kvs)
+            .build();
+        let format = RecordFormat {
+            standard_fields: StandardFields::empty(),
+            target_field: None,
+            ..RecordFormat::default()
+        };
+        assert_eq!(
+            record_payload(&format, &record, 0),
+            &b"PRIORITY=5\nMESSAGE\n\0\0\0\0\0\0\0\0\n\
+            OBJECT_PID\n\x02\0\0\0\0\0\0\x0042\n\
+            OBJECT_SYSTEMD_UNIT\n\x10\0\0\0\0\0\0\0worker@3.service\n\
+            OBJECT_SYSTEMD_USER_UNIT\n\x0b\0\0\0\0\0\0\0app.service\n"[..]
+        );
+    }
+
+    #[test]
+    fn record_payload_message_field() {
+        let kvs = [("event_text", "kv"), ("message", "also kv")];
+        let record = Record::builder()
+            .args(format_args!("Hello"))
+            .key_values(&kvs)
+            .build();
+        let format = RecordFormat {
+            message_field: "EVENT_TEXT".to_string(),
+            standard_fields: StandardFields::empty(),
+            target_field: None,
+            reserved_field_policy: ReservedFieldPolicy::Rename,
+            ..RecordFormat::default()
+        };
+        assert_eq!(
+            record_payload(&format, &record, 0),
+            &b"PRIORITY=5\nEVENT_TEXT\n\x05\0\0\0\0\0\0\0Hello\n\
+            FIELD_EVENT_TEXT\n\x02\0\0\0\0\0\0\0kv\n\
+            MESSAGE\n\x07\0\0\0\0\0\0\0also kv\n"[..]
+        );
+    }
+
+    #[test]
+    fn record_payload_message_fingerprint() {
+        let format = RecordFormat {
+            fingerprint_field: Some("MSG_HASH".to_string()),
+            message_prefix: "[prefix] ".to_string(),
+            standard_fields: StandardFields::empty(),
+            target_field: None,
+            ..RecordFormat::default()
+        };
+        let record = Record::builder().args(format_args!("foobar")).build();
+        let payload = record_payload(&format, &record, 0);
+        assert_eq!(
+            payload,
+            &b"PRIORITY=5\nMESSAGE\n\x0f\0\0\0\0\0\0\0[prefix] foobar\n\
+            MSG_HASH\n\x10\0\0\0\0\0\0\x0085944171f73967e8\n"[..]
+        );
+        assert_eq!(
+            super::estimate_payload_size(&format, &record),
+            11 + 7 + 10 + 9 + 6 + 34
+        );
+    }
+
+    #[test]
+    fn record_payload_source_location_min_level() {
+        let format = RecordFormat {
+            standard_fields: StandardFields::CODE_LINE,
+            source_location_level: LevelFilter::Warn,
+            target_field: None,
+            ..RecordFormat::default()
+        };
+        for (level, expected) in [
+            (
+                Level::Error,
+                &b"PRIORITY=3\nMESSAGE\n\0\0\0\0\0\0\0\0\nCODE_LINE=42\n"[..],
+            ),
+            (
+                Level::Warn,
+                b"PRIORITY=4\nMESSAGE\n\0\0\0\0\0\0\0\0\nCODE_LINE=42\n",
+            ),
+            (Level::Info, b"PRIORITY=5\nMESSAGE\n\0\0\0\0\0\0\0\0\n"),
+        ] {
+            let record = Record::builder()
+                .level(level)
+                .args(format_args!(""))
+                .line(Some(42))
+                .build();
+            assert_eq!(record_payload(&format, &record, 0), expected, "{}", level);
+        }
+    }
+
+    #[test]
+    fn record_payload_with_location() {
+        let file = String::from("src/wrapped.rs");
+        let mut builder = Record::builder();
+        with_location(&mut builder, &file, 42, "wrapped")
+            .args(format_args!(""))
+            .target("t");
+        let format = RecordFormat {
+            standard_fields: StandardFields::CODE_FILE
+                | StandardFields::CODE_LINE
+                | StandardFields::CODE_MODULE,
+            target_field: None,
+            ..RecordFormat::default()
+        };
+        assert_eq!(
+            record_payload(&format, &builder.build(), 0),
+            &b"PRIORITY=5\nMESSAGE\n\0\0\0\0\0\0\0\0\n\
+            CODE_FILE=src/wrapped.rs\nCODE_MODULE=wrapped\nCODE_LINE=42\n"[..]
+        );
+    }
+
+    #[test]
+    fn record_payload_reserved_fields() {
+        let kvs = [("message", "kv"), ("code_line", "1"), ("Target", "kv")];
+        let record = Record::builder()
+            .target("test")
+            .args(format_args!("Hello"))
+            .key_values(&kvs)
+            .build();
+        // By default write reserved keys as is
+        let payload = record_payload(&RecordFormat::default(), &record, 0);
+        let fields = b"MESSAGE\n\x02\0\0\0\0\0\0\0kv\n\
+            CODE_LINE\n\x01\0\0\0\0\0\0\x001\n\
+            TARGET\n\x02\0\0\0\0\0\0\0kv\n";
+        assert_eq!(&payload[payload.len() - fields.len()..], fields);
+
+        let format = RecordFormat {
+            reserved_field_policy: ReservedFieldPolicy::Rename,
+            ..RecordFormat::default()
+        };
+        let payload = record_payload(&format, &record, 0);
+        let fields = b"FIELD_MESSAGE\n\x02\0\0\0\0\0\0\0kv\n\
+            FIELD_CODE_LINE\n\x01\0\0\0\0\0\0\x001\n\
+            FIELD_TARGET\n\x02\0\0\0\0\0\0\0kv\n";
+        assert_eq!(&payload[payload.len() - fields.len()..], fields);
+
+        let format = RecordFormat {
+            reserved_field_policy: ReservedFieldPolicy::Skip,
+            standard_fields: StandardFields::all() - StandardFields::CODE_LINE,
+            ..RecordFormat::default()
+        };
+        let payload = record_payload(&format, &record, 0);
+        assert!(payload.ends_with(b"TARGET=test\nCODE_LINE\n\x01\0\0\0\0\0\0\x001\n"));
+        assert_eq!(format.skipped_reserved_fields.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn record_payload_max_fields() {
+        let kvs = [("a", "1"), ("b", "2"), ("c", "3"), ("d", "4")];
+        let record = Record::builder()
+            .target("test")
+            .args(format_args!("Hello"))
+            .key_values(&kvs)
+            .build();
+        let format = RecordFormat {
+            max_fields: Some(2),
+            ..RecordFormat::default()
+        };
+        let payload = record_payload(&format, &record, 0);
+        let fields = b"TARGET=test\n\
+            A\n\x01\0\0\0\0\0\0\x001\n\
+            B\n\x01\0\0\0\0\0\0\x002\n\
+            FIELDS_TRUNCATED=2\n";
+        assert_eq!(&payload[payload.len() - fields.len()..], fields);
+
+        let format = RecordFormat {
+            max_fields: Some(4),
+            ..RecordFormat::default()
+        };
+        let payload = record_payload(&format, &record, 0);
+        assert!(payload.ends_with(b"D\n\x01\0\0\0\0\0\0\x004\n"));
+    }
+
+    #[test]
+    #[cfg(feature = "flatten_structured")]
+    fn record_payload_repeated_sequences() {
+        let tags = vec!["a", "b", "c"];
+        let kvs = [("tag", Value::from_serde(&tags)), ("d", Value::from("4"))];
+        let record = Record::builder()
+            .target("test")
+            .args(format_args!("Hello"))
+            .key_values(&kvs)
+            .build();
+        let format = RecordFormat {
+            repeated_sequences: true,
+            max_fields: Some(2),
+            ..RecordFormat::default()
+        };
+        let payload = record_payload(&format, &record, 0);
+        let fields = b"TARGET=test\n\
+            TAG\n\x01\0\0\0\0\0\0\0a\n\
+            TAG\n\x01\0\0\0\0\0\0\0b\n\
+            FIELDS_TRUNCATED=2\n";
+        assert_eq!(&payload[payload.len() - fields.len()..], fields);
+    }
+
+    #[test]
+    fn journal_log_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<JournalLog>();
+        assert_send_sync::<JournalLogHandle>();
+    }
+
+    #[test]
+    fn record_payload_context_stack() {
+        let record = Record::builder()
+            .target("test")
+            .args(format_args!("Hello"))
+            .build();
+        let format = RecordFormat {
+            context_stack: true,
+            ..RecordFormat::default()
+        };
+
+        push_context("request_id", "42");
+        push_context("TENANT", "acme");
+        let payload = record_payload(&format, &record, 0);
+        let fields = b"REQUEST_ID=42\nTENANT=acme\n";
+        assert_eq!(&payload[payload.len() - fields.len()..], fields);
+
+        let payload = record_payload(&RecordFormat::default(), &record, 0);
+        assert!(!payload.ends_with(fields));
+
+        assert_eq!(
+            pop_context(),
+            Some(("TENANT".to_string(), "acme".to_string()))
+        );
+        let payload = record_payload(&format, &record, 0);
+        assert!(payload.ends_with(b"TARGET=test\nREQUEST_ID=42\n"));
+        assert!(pop_context().is_some());
+        assert_eq!(pop_context(), None);
+    }
+
+    #[test]
+    fn record_payload_without_target() {
+        let record = Record::builder().args(format_args!("Hello")).build();
+        let format = RecordFormat {
+            target_as_field: Some("MODULE".to_string()),
+            ..RecordFormat::default()
+        };
+        let payload = record_payload(&format, &record, 0);
+        assert!(!payload.windows(7).any(|w| w == b"TARGET="));
+        assert!(!payload.windows(7).any(|w| w == b"MODULE="));
+    }
+
+    #[test]
+    fn connect_with_and_without_probe() {
+        let path = std::env::temp_dir().join(format!(
+            "systemd-journal-logger-probe-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let receiver = std::os::unix::net::UnixDatagram::bind(&path).unwrap();
+        receiver.set_nonblocking(true).unwrap();
+        let mut buffer = [0; 64];
+
+        JournalLog::connect(&path, false).unwrap();
+        let error = receiver.recv(&mut buffer).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::WouldBlock);
+
+        JournalLog::connect(&path, true).unwrap();
+        // The probe is an empty datagram
+        assert_eq!(receiver.recv(&mut buffer).unwrap(), 0);
+        std::fs::remove_file(&path).unwrap();
+    }
 }
@@ -0,0 +1,81 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Message IDs for the journal catalog.
+
+use std::fmt::{Display, Formatter};
+
+use log::kv::{ToValue, Value};
+
+/// A message ID for the `MESSAGE_ID` field.
+///
+/// Message IDs identify a type of event in the journal, and link journal
+/// entries to the [journal message catalog][1].  A message ID is a 128-bit ID,
+/// which formats as 32 lowercase hex digits, like `journalctl --new-id128`
+/// prints it.
+///
+/// Define message IDs as constants and use them with [`crate::JournalLog::log_event`],
+/// or as value of a `MESSAGE_ID` key on log records:
+///
+/// ```rust
+/// use systemd_journal_logger::MessageId;
+///
+/// const STARTED: MessageId = MessageId::from_u128(0x39f53479d3a045ac8e11786248231fbf);
+///
+/// assert_eq!(STARTED.to_string(), "39f53479d3a045ac8e11786248231fbf");
+/// log::info!(MESSAGE_ID = STARTED; "Service started");
+/// ```
+///
+/// [1]: https://www.freedesktop.org/wiki/Software/systemd/catalog/
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct MessageId(u128);
+
+impl MessageId {
+    /// Create a message ID from its numeric value.
+    ///
+    /// Write `id` as hex literal to use the same representation as the journal,
+    /// e.g. `MessageId::from_u128(0x39f53479d3a045ac8e11786248231fbf)`.
+    pub const fn from_u128(id: u128) -> Self {
+        Self(id)
+    }
+
+    /// Get the numeric value of this message ID.
+    pub const fn as_u128(self) -> u128 {
+        self.0
+    }
+}
+
+impl Display for MessageId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:032x}", self.0)
+    }
+}
+
+impl ToValue for MessageId {
+    fn to_value(&self) -> Value<'_> {
+        Value::from_display(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use similar_asserts::assert_eq;
+
+    #[test]
+    fn display() {
+        assert_eq!(
+            MessageId::from_u128(0x39f53479d3a045ac8e11786248231fbf).to_string(),
+            "39f53479d3a045ac8e11786248231fbf"
+        );
+        assert_eq!(
+            MessageId::from_u128(0x2a).to_string(),
+            "0000000000000000000000000000002a"
+        );
+    }
+}
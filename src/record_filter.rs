@@ -0,0 +1,79 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Pluggable filters for records.
+
+use log::{LevelFilter, Metadata, Record};
+
+/// Decide which records to log.
+///
+/// Implement this trait for arbitrary filter logic, e.g. to suppress records
+/// by their message or to sample records by their key-values, and pass the
+/// filter to [`crate::JournalLog::with_record_filter`]:
+///
+/// ```rust
+/// use log::{Metadata, Record};
+/// use systemd_journal_logger::{JournalLog, RecordFilter};
+///
+/// struct NoHealthChecks;
+///
+/// impl RecordFilter for NoHealthChecks {
+///     fn should_log(&self, _metadata: &Metadata, record: Option<&Record>) -> bool {
+///         record.map_or(true, |record| {
+///             !record.args().to_string().contains("GET /health")
+///         })
+///     }
+/// }
+///
+/// let logger = JournalLog::new()
+///     .unwrap()
+///     .with_record_filter(Box::new(NoHealthChecks));
+/// ```
+///
+/// The logger consults the filter in two places:
+///
+/// 1. In [`log::Log::log`] with the `metadata` and the complete record, right
+///    before the logger formats it, for every record.  The `log` macros call
+///    `log` directly, without asking `enabled` first, so this call must reject
+///    all records the filter rejects, by metadata as well as by the message or
+///    the key-values of the record.  Formatting the message, e.g. with
+///    `record.args().to_string()`, costs an allocation for every record.
+/// 2. In [`log::Log::enabled`] with only the `metadata` of a record, and
+///    `None` for the record, if a caller explicitly asks whether the logger is
+///    enabled, e.g. with `log::log_enabled!`, before doing expensive work for
+///    a record.
+///
+/// A filter should accept all records in the second call which it may accept
+/// in the first, because callers which check `enabled` skip records rejected
+/// in the second call altogether; in particular a filter which only looks at
+/// the metadata should decide the same in both calls.
+///
+/// Closures with the signature of [`Self::should_log`] are filters, and so is
+/// [`LevelFilter`], which accepts all records up to its level.
+pub trait RecordFilter: Send + Sync {
+    /// Whether to log the record with `metadata`.
+    ///
+    /// `record` is `None` in [`log::Log::enabled`], and the complete record
+    /// in [`log::Log::log`].
+    fn should_log(&self, metadata: &Metadata, record: Option<&Record>) -> bool;
+}
+
+impl<F> RecordFilter for F
+where
+    F: Fn(&Metadata, Option<&Record>) -> bool + Send + Sync,
+{
+    fn should_log(&self, metadata: &Metadata, record: Option<&Record>) -> bool {
+        self(metadata, record)
+    }
+}
+
+impl RecordFilter for LevelFilter {
+    fn should_log(&self, metadata: &Metadata, _record: Option<&Record>) -> bool {
+        metadata.level() <= *self
+    }
+}
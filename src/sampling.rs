@@ -0,0 +1,108 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Randomly sample verbose log records.
+
+use std::cell::Cell;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+use log::Level;
+
+thread_local! {
+    /// State of a xorshift generator for the current thread.
+    static RNG_STATE: Cell<u64> = Cell::new(seed());
+}
+
+/// Create a random non-zero seed for a xorshift generator.
+///
+/// Use the random keys of the standard library's hash maps, to avoid a
+/// dependency on a random number crate.
+fn seed() -> u64 {
+    RandomState::new().build_hasher().finish() | 1
+}
+
+/// Get a random number from a fast thread-local generator.
+///
+/// The generator is not cryptographically secure, but good enough for sampling.
+fn next_random() -> u64 {
+    RNG_STATE.with(|state| {
+        // xorshift64*, see https://en.wikipedia.org/wiki/Xorshift#xorshift*
+        let mut x = state.get();
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        state.set(x);
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    })
+}
+
+/// Sample records at or below a level.
+#[derive(Debug, Copy, Clone)]
+pub struct Sampling {
+    /// The most severe level to sample.
+    level: Level,
+    /// Keep records whose random number is below this threshold.
+    threshold: u64,
+}
+
+impl Sampling {
+    /// Keep about `rate` of all records at or below `level`.
+    ///
+    /// Clamp `rate` to the range from 0 to 1.
+    pub fn new(level: Level, rate: f64) -> Self {
+        let threshold = if 1.0 <= rate {
+            u64::MAX
+        } else if rate <= 0.0 || rate.is_nan() {
+            0
+        } else {
+            (rate * u64::MAX as f64) as u64
+        };
+        Self { level, threshold }
+    }
+
+    /// Whether to keep a record at `level`.
+    ///
+    /// Always keep records more severe than the sampling level.
+    pub fn keep(&self, level: Level) -> bool {
+        level < self.level || self.threshold == u64::MAX || next_random() < self.threshold
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keep_more_severe_levels() {
+        let sampling = Sampling::new(Level::Debug, 0.0);
+        for level in [Level::Error, Level::Warn, Level::Info] {
+            assert!(sampling.keep(level));
+        }
+        for level in [Level::Debug, Level::Trace] {
+            assert!(!sampling.keep(level));
+        }
+    }
+
+    #[test]
+    fn keep_all_or_none() {
+        let all = Sampling::new(Level::Trace, 1.0);
+        let none = Sampling::new(Level::Trace, -1.0);
+        for _ in 0..1000 {
+            assert!(all.keep(Level::Trace));
+            assert!(!none.keep(Level::Trace));
+        }
+    }
+
+    #[test]
+    fn keep_about_rate() {
+        let sampling = Sampling::new(Level::Trace, 0.25);
+        let kept = (0..10_000).filter(|_| sampling.keep(Level::Trace)).count();
+        assert!((2000..3000).contains(&kept), "kept {} records", kept);
+    }
+}
@@ -0,0 +1,125 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Sets of optional standard fields.
+
+use std::ops::{BitAnd, BitOr, BitOrAssign, Sub};
+
+/// A set of optional standard fields.
+///
+/// The logger always writes `PRIORITY` and `MESSAGE`; use this set with
+/// [`crate::JournalLog::with_standard_fields`] to select which other standard
+/// fields it writes.  Combine fields with `|`, and remove fields with `-`:
+///
+/// ```rust
+/// use systemd_journal_logger::StandardFields;
+///
+/// let fields = StandardFields::all() - StandardFields::CODE_MODULE - StandardFields::TARGET;
+/// assert!(fields.contains(StandardFields::CODE_FILE | StandardFields::CODE_LINE));
+/// assert!(!fields.contains(StandardFields::TARGET));
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct StandardFields(u8);
+
+impl StandardFields {
+    /// `SYSLOG_PID` with the PID of the process.
+    pub const SYSLOG_PID: Self = Self(1);
+    /// `CODE_FILE` with the file of the record, if present.
+    pub const CODE_FILE: Self = Self(1 << 1);
+    /// `CODE_LINE` with the line of the record, if present.
+    pub const CODE_LINE: Self = Self(1 << 2);
+    /// `CODE_MODULE` with the module path of the record, if present.
+    pub const CODE_MODULE: Self = Self(1 << 3);
+    /// The field with the target of the record, see
+    /// [`crate::JournalLog::with_target_field_name`].
+    pub const TARGET: Self = Self(1 << 4);
+
+    /// No optional standard fields.
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// All optional standard fields.
+    pub const fn all() -> Self {
+        Self(
+            Self::SYSLOG_PID.0
+                | Self::CODE_FILE.0
+                | Self::CODE_LINE.0
+                | Self::CODE_MODULE.0
+                | Self::TARGET.0,
+        )
+    }
+
+    /// Whether this set has no fields.
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Whether this set contains all fields of `other`.
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+/// All optional standard fields.
+impl Default for StandardFields {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+/// The fields in either set.
+impl BitOr for StandardFields {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for StandardFields {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+/// The fields in both sets.
+impl BitAnd for StandardFields {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self {
+        Self(self.0 & rhs.0)
+    }
+}
+
+/// The fields in the first set but not in the second.
+impl Sub for StandardFields {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        Self(self.0 & !rhs.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_operations() {
+        let fields = StandardFields::SYSLOG_PID | StandardFields::TARGET;
+        assert!(fields.contains(StandardFields::TARGET));
+        assert!(!fields.contains(StandardFields::CODE_FILE));
+        assert!(!fields.contains(StandardFields::TARGET | StandardFields::CODE_FILE));
+        assert_eq!(fields - StandardFields::TARGET, StandardFields::SYSLOG_PID);
+        assert_eq!(fields & StandardFields::TARGET, StandardFields::TARGET);
+        assert!(StandardFields::empty().is_empty());
+        assert!((StandardFields::all() - StandardFields::all()).is_empty());
+        assert_eq!(StandardFields::default(), StandardFields::all());
+    }
+}
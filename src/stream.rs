@@ -0,0 +1,139 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Write records to a stream connected to the journal.
+
+use std::fs::File;
+use std::io::prelude::*;
+use std::os::fd::{AsFd, OwnedFd};
+use std::sync::Mutex;
+
+use log::Record;
+
+use crate::{connected_to_journal, record_priority};
+
+/// Write records to a stream which journald captures.
+///
+/// journald captures the standard output and error streams of services, and
+/// parses a `<N>` prefix on every line as syslog priority of the line.  This
+/// backend writes the message of every record to a stream in this format, for
+/// environments which connect the output of a process to the journal, but do
+/// not provide journald's socket.
+///
+/// This backend only writes the message and the priority of each record; all
+/// other fields, including extra fields of the logger and key-values of
+/// records, are lost.  journald logs every line of a multiline message as a
+/// separate entry.
+///
+/// The `<N>` prefix only makes sense if journald reads the stream; for humans
+/// it is just noise.  [`Self::with_priority_prefix`] disables the prefix.
+///
+/// See [`crate::JournalLog::with_stream_backend`].
+pub struct StreamBackend {
+    stream: Mutex<File>,
+    /// Whether to prefix every line with the priority of the record.
+    priority_prefix: bool,
+}
+
+impl StreamBackend {
+    /// Write records to `fd`, with a priority prefix on every line.
+    pub fn new(fd: OwnedFd) -> Self {
+        Self {
+            stream: Mutex::new(fd.into()),
+            priority_prefix: true,
+        }
+    }
+
+    /// Write records to the standard error of this process.
+    ///
+    /// Only prefix lines with the priority if standard error is connected to
+    /// the journal (see [`crate::connected_to_journal`]), and write plain lines
+    /// otherwise, for human readers.
+    pub fn stderr() -> std::io::Result<Self> {
+        Ok(Self::new(std::io::stderr().as_fd().try_clone_to_owned()?)
+            .with_priority_prefix(connected_to_journal()))
+    }
+
+    /// Whether to prefix every line with the priority of its record.
+    ///
+    /// journald parses the prefix to classify the severity of every line, so
+    /// only disable the prefix if the stream is not connected to the journal.
+    pub fn with_priority_prefix(mut self, priority_prefix: bool) -> Self {
+        self.priority_prefix = priority_prefix;
+        self
+    }
+
+    /// Write `record` to the stream.
+    pub fn send(&self, record: &Record) -> std::io::Result<()> {
+        let buffer = format_stream_record(record, self.priority_prefix);
+        self.stream.lock().unwrap().write_all(&buffer)
+    }
+}
+
+/// Format `record` for the journal stream protocol.
+///
+/// If `priority_prefix` is true prefix every line of the message with the
+/// priority of `record`.
+fn format_stream_record(record: &Record, priority_prefix: bool) -> Vec<u8> {
+    let priority = record_priority(record);
+    let message = record.args().to_string();
+    let mut buffer = Vec::with_capacity(message.len() + 4);
+    // journald would drop the empty line after a trailing newline anyway
+    let message = message.strip_suffix('\n').unwrap_or(&message);
+    for line in message.split('\n') {
+        if priority_prefix {
+            buffer.push(b'<');
+            buffer.extend_from_slice(priority);
+            buffer.push(b'>');
+        }
+        buffer.extend_from_slice(line.as_bytes());
+        buffer.push(b'\n');
+    }
+    buffer
+}
+
+#[cfg(test)]
+mod tests {
+    use log::Level;
+    use similar_asserts::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn format_stream_record() {
+        let payload = super::format_stream_record(
+            &Record::builder()
+                .level(Level::Warn)
+                .args(format_args!("Hello {}", "world"))
+                .build(),
+            true,
+        );
+        assert_eq!(payload, b"<4>Hello world\n");
+
+        let payload = super::format_stream_record(
+            &Record::builder()
+                .level(Level::Error)
+                .args(format_args!("multiple\nlines\n"))
+                .build(),
+            true,
+        );
+        assert_eq!(payload, b"<3>multiple\n<3>lines\n");
+    }
+
+    #[test]
+    fn format_stream_record_without_priority_prefix() {
+        let payload = super::format_stream_record(
+            &Record::builder()
+                .level(Level::Error)
+                .args(format_args!("multiple\nlines\n"))
+                .build(),
+            false,
+        );
+        assert_eq!(payload, b"multiple\nlines\n");
+    }
+}
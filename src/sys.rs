@@ -0,0 +1,456 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! System calls, through `rustix` or, with the `libc-backend` feature, `libc`.
+//!
+//! Both backends provide the same functions, and report failures as
+//! [`std::io::Error`] with the raw `errno`, so the rest of the crate can
+//! compare errors against the `errno` constants of this module regardless of
+//! the backend.
+
+pub use imp::*;
+
+#[cfg(not(feature = "libc-backend"))]
+mod imp {
+    use std::ffi::CStr;
+    use std::fs::File;
+    use std::io::IoSlice;
+    use std::os::fd::BorrowedFd;
+    use std::os::unix::net::UnixDatagram;
+    use std::path::Path;
+    use std::time::Duration;
+
+    use rustix::event::{poll, PollFd, PollFlags};
+    use rustix::fs::{fcntl_add_seals, memfd_create, MemfdFlags, Mode, OFlags, SealFlags};
+    use rustix::io::Errno;
+    use rustix::net::sockopt::{get_socket_send_buffer_size, set_socket_send_buffer_size};
+    use rustix::net::{
+        sendmsg, sendmsg_unix, SendAncillaryBuffer, SendAncillaryMessage, SendFlags,
+        SocketAddrUnix, UCred,
+    };
+    use rustix::process::{getgid, getpid, getuid};
+
+    pub const EAGAIN: i32 = Errno::AGAIN.raw_os_error();
+    pub const EINVAL: i32 = Errno::INVAL.raw_os_error();
+    pub const EMSGSIZE: i32 = Errno::MSGSIZE.raw_os_error();
+    pub const ENOMEM: i32 = Errno::NOMEM.raw_os_error();
+    pub const ENOSPC: i32 = Errno::NOSPC.raw_os_error();
+    pub const ENOSYS: i32 = Errno::NOSYS.raw_os_error();
+    pub const EPERM: i32 = Errno::PERM.raw_os_error();
+
+    /// The address of a Unix socket.
+    #[derive(Clone)]
+    pub struct Address(SocketAddrUnix);
+
+    impl Address {
+        /// The address of the socket at `path`.
+        pub fn new(path: &Path) -> std::io::Result<Self> {
+            Ok(Self(SocketAddrUnix::new(path)?))
+        }
+    }
+
+    /// The credentials of a process.
+    #[derive(Clone, Copy)]
+    pub struct Credentials(UCred);
+
+    impl Credentials {
+        /// The PID and the real user and group IDs of the current process.
+        pub fn current() -> Self {
+            Self(UCred {
+                pid: getpid(),
+                uid: getuid(),
+                gid: getgid(),
+            })
+        }
+    }
+
+    /// Send the concatenation of `slices` to `address`, together with `fd`
+    /// and `credentials`, if any.
+    ///
+    /// If `address` is `None` send to the peer of the connected `socket`.
+    pub fn send(
+        socket: &UnixDatagram,
+        address: Option<&Address>,
+        slices: &[IoSlice<'_>],
+        fd: Option<BorrowedFd<'_>>,
+        credentials: Option<Credentials>,
+    ) -> std::io::Result<usize> {
+        let fds = match &fd {
+            Some(fd) => std::slice::from_ref(fd),
+            None => &[],
+        };
+        // We use a static buffer size here, because we don't need to account
+        // for arbitrary messages; we just need enough space for a single FD
+        // and credentials.  With a static buffer we get away without any
+        // additional heap allocations here.
+        let mut buffer = [0; rustix::cmsg_space!(ScmRights(1), ScmCredentials(1))];
+        let mut ancillary = SendAncillaryBuffer::new(&mut buffer);
+        // push returns false if the buffer is too small to add the new
+        // message; let's guard against this.
+        if !fds.is_empty() {
+            assert!(
+                ancillary.push(SendAncillaryMessage::ScmRights(fds)),
+                "Failed to push ScmRights message"
+            );
+        }
+        if let Some(credentials) = credentials {
+            assert!(
+                ancillary.push(SendAncillaryMessage::ScmCredentials(credentials.0)),
+                "Failed to push ScmCredentials message"
+            );
+        }
+        let sent = match address {
+            Some(address) => sendmsg_unix(
+                socket,
+                &address.0,
+                slices,
+                &mut ancillary,
+                SendFlags::NOSIGNAL,
+            )?,
+            None => sendmsg(socket, slices, &mut ancillary, SendFlags::NOSIGNAL)?,
+        };
+        Ok(sent)
+    }
+
+    /// Wait until `fd` is writable, for at most `timeout`.
+    pub fn wait_writable(fd: BorrowedFd<'_>, timeout: Duration) -> std::io::Result<bool> {
+        let mut fds = [PollFd::new(&fd, PollFlags::OUT)];
+        let timeout = i32::try_from(timeout.as_millis()).unwrap_or(i32::MAX);
+        loop {
+            match poll(&mut fds, timeout) {
+                Ok(ready) => return Ok(0 < ready),
+                Err(Errno::INTR) => continue,
+                Err(error) => return Err(error.into()),
+            }
+        }
+    }
+
+    /// Create a memfd with `name` which allows sealing.
+    pub fn memfd_sealable(name: &CStr) -> std::io::Result<File> {
+        Ok(memfd_create(name, MemfdFlags::ALLOW_SEALING | MemfdFlags::CLOEXEC)?.into())
+    }
+
+    /// Seal `file` against all modifications, and against further seals.
+    pub fn seal_fully(file: &File) -> std::io::Result<()> {
+        Ok(fcntl_add_seals(
+            file,
+            SealFlags::SEAL | SealFlags::SHRINK | SealFlags::WRITE | SealFlags::GROW,
+        )?)
+    }
+
+    /// Create an anonymous file in `/dev/shm`, readable and writable by the
+    /// current user only.
+    pub fn shm_tmpfile() -> std::io::Result<File> {
+        Ok(rustix::fs::open(
+            "/dev/shm",
+            OFlags::TMPFILE | OFlags::RDWR | OFlags::CLOEXEC,
+            Mode::RUSR | Mode::WUSR,
+        )?
+        .into())
+    }
+
+    /// Request a send buffer of `size` bytes for `socket`.
+    pub fn set_send_buffer_size(socket: &UnixDatagram, size: usize) -> std::io::Result<()> {
+        Ok(set_socket_send_buffer_size(socket, size)?)
+    }
+
+    /// The size of the send buffer of `socket`.
+    pub fn send_buffer_size(socket: &UnixDatagram) -> std::io::Result<usize> {
+        Ok(get_socket_send_buffer_size(socket)?)
+    }
+
+    /// The device and inode numbers of the file of `fd`.
+    pub fn device_and_inode(fd: BorrowedFd<'_>) -> std::io::Result<(u64, u64)> {
+        let stat = rustix::fs::fstat(fd)?;
+        Ok((stat.st_dev, stat.st_ino))
+    }
+
+    /// The effective user ID of the current process.
+    pub fn geteuid() -> u32 {
+        rustix::process::geteuid().as_raw()
+    }
+
+    /// The effective group ID of the current process.
+    pub fn getegid() -> u32 {
+        rustix::process::getegid().as_raw()
+    }
+}
+
+#[cfg(feature = "libc-backend")]
+#[allow(unsafe_code)]
+mod imp {
+    use std::ffi::CStr;
+    use std::fs::File;
+    use std::io::IoSlice;
+    use std::os::fd::{AsRawFd, BorrowedFd, FromRawFd, RawFd};
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::net::UnixDatagram;
+    use std::path::Path;
+    use std::time::Duration;
+
+    pub use libc::{EAGAIN, EINVAL, EMSGSIZE, ENOMEM, ENOSPC, ENOSYS, EPERM};
+
+    /// Turn the return value of a system call into a result.
+    ///
+    /// Return the last OS error if `result` is negative.
+    fn cvt<T: Default + PartialOrd>(result: T) -> std::io::Result<T> {
+        if result < T::default() {
+            Err(std::io::Error::last_os_error())
+        } else {
+            Ok(result)
+        }
+    }
+
+    /// The address of a Unix socket.
+    #[derive(Clone)]
+    pub struct Address {
+        address: libc::sockaddr_un,
+        length: libc::socklen_t,
+    }
+
+    impl Address {
+        /// The address of the socket at `path`.
+        pub fn new(path: &Path) -> std::io::Result<Self> {
+            // SAFETY: sockaddr_un is plain old data, and all zeros is valid.
+            let mut address: libc::sockaddr_un = unsafe { std::mem::zeroed() };
+            address.sun_family = libc::AF_UNIX as libc::sa_family_t;
+            let path = path.as_os_str().as_bytes();
+            // Leave room for the trailing NUL byte
+            if address.sun_path.len() <= path.len() || path.contains(&0) {
+                return Err(std::io::Error::from_raw_os_error(libc::EINVAL));
+            }
+            for (dst, src) in address.sun_path.iter_mut().zip(path) {
+                *dst = *src as libc::c_char;
+            }
+            let length = std::mem::size_of::<libc::sa_family_t>() + path.len() + 1;
+            Ok(Self {
+                address,
+                length: length as libc::socklen_t,
+            })
+        }
+    }
+
+    /// The credentials of a process.
+    #[derive(Clone, Copy)]
+    pub struct Credentials(libc::ucred);
+
+    impl Credentials {
+        /// The PID and the real user and group IDs of the current process.
+        pub fn current() -> Self {
+            // SAFETY: getpid, getuid, and getgid always succeed.
+            Self(unsafe {
+                libc::ucred {
+                    pid: libc::getpid(),
+                    uid: libc::getuid(),
+                    gid: libc::getgid(),
+                }
+            })
+        }
+    }
+
+    /// Add a control message of `level` and `kind` with `data` to `message`.
+    ///
+    /// Append the message after the first `used` bytes of the control buffer
+    /// of `message`, and return the number of used bytes afterwards.
+    ///
+    /// # Safety
+    ///
+    /// The control buffer of `message` must have room for the new message
+    /// after `used` bytes, and must be aligned for `cmsghdr`.
+    unsafe fn push_control_message<T: Copy>(
+        message: &mut libc::msghdr,
+        used: usize,
+        level: libc::c_int,
+        kind: libc::c_int,
+        data: T,
+    ) -> usize {
+        let size = std::mem::size_of::<T>() as u32;
+        let header = message
+            .msg_control
+            .cast::<u8>()
+            .add(used)
+            .cast::<libc::cmsghdr>();
+        (*header).cmsg_level = level;
+        (*header).cmsg_type = kind;
+        (*header).cmsg_len = libc::CMSG_LEN(size) as _;
+        std::ptr::write_unaligned(libc::CMSG_DATA(header).cast::<T>(), data);
+        used + libc::CMSG_SPACE(size) as usize
+    }
+
+    /// Send the concatenation of `slices` to `address`, together with `fd`
+    /// and `credentials`, if any.
+    ///
+    /// If `address` is `None` send to the peer of the connected `socket`.
+    pub fn send(
+        socket: &UnixDatagram,
+        address: Option<&Address>,
+        slices: &[IoSlice<'_>],
+        fd: Option<BorrowedFd<'_>>,
+        credentials: Option<Credentials>,
+    ) -> std::io::Result<usize> {
+        // A buffer for a single FD and credentials, aligned for cmsghdr
+        let mut control = [0_u64; 8];
+        // SAFETY: CMSG_SPACE only computes sizes.
+        let space = unsafe {
+            libc::CMSG_SPACE(std::mem::size_of::<RawFd>() as u32)
+                + libc::CMSG_SPACE(std::mem::size_of::<libc::ucred>() as u32)
+        };
+        // Just a sanity check should we ever get the static buffer size wrong.
+        assert!(
+            space as usize <= std::mem::size_of_val(&control),
+            "static buffer size not sufficient for control messages of size {}",
+            space
+        );
+        // SAFETY: msghdr is plain old data, and all zeros is valid.
+        let mut message: libc::msghdr = unsafe { std::mem::zeroed() };
+        if let Some(address) = address {
+            message.msg_name = &address.address as *const libc::sockaddr_un as *mut libc::c_void;
+            message.msg_namelen = address.length;
+        }
+        // IoSlice is ABI-compatible with iovec on Unix.
+        message.msg_iov = slices.as_ptr() as *mut libc::iovec;
+        message.msg_iovlen = slices.len() as _;
+        message.msg_control = control.as_mut_ptr().cast();
+        let mut used = 0;
+        // SAFETY: The control buffer has room for a single FD and credentials,
+        // as asserted above, and is aligned for cmsghdr.
+        if let Some(fd) = fd {
+            used = unsafe {
+                push_control_message(
+                    &mut message,
+                    used,
+                    libc::SOL_SOCKET,
+                    libc::SCM_RIGHTS,
+                    fd.as_raw_fd(),
+                )
+            };
+        }
+        if let Some(credentials) = credentials {
+            used = unsafe {
+                push_control_message(
+                    &mut message,
+                    used,
+                    libc::SOL_SOCKET,
+                    libc::SCM_CREDENTIALS,
+                    credentials.0,
+                )
+            };
+        }
+        if used == 0 {
+            message.msg_control = std::ptr::null_mut();
+        }
+        message.msg_controllen = used as _;
+        // SAFETY: All pointers in message point to live buffers of the given
+        // lengths.
+        let sent = cvt(unsafe { libc::sendmsg(socket.as_raw_fd(), &message, libc::MSG_NOSIGNAL) })?;
+        Ok(sent as usize)
+    }
+
+    /// Wait until `fd` is writable, for at most `timeout`.
+    pub fn wait_writable(fd: BorrowedFd<'_>, timeout: Duration) -> std::io::Result<bool> {
+        let mut fds = [libc::pollfd {
+            fd: fd.as_raw_fd(),
+            events: libc::POLLOUT,
+            revents: 0,
+        }];
+        let timeout = i32::try_from(timeout.as_millis()).unwrap_or(i32::MAX);
+        loop {
+            // SAFETY: fds is a valid array of one pollfd.
+            match cvt(unsafe { libc::poll(fds.as_mut_ptr(), 1, timeout) }) {
+                Ok(ready) => return Ok(0 < ready),
+                Err(error) if error.raw_os_error() == Some(libc::EINTR) => continue,
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    /// Create a memfd with `name` which allows sealing.
+    pub fn memfd_sealable(name: &CStr) -> std::io::Result<File> {
+        // SAFETY: name is a valid NUL-terminated string.
+        let fd = cvt(unsafe {
+            libc::memfd_create(name.as_ptr(), libc::MFD_ALLOW_SEALING | libc::MFD_CLOEXEC)
+        })?;
+        // SAFETY: memfd_create returned a new FD which we own.
+        Ok(unsafe { File::from_raw_fd(fd) })
+    }
+
+    /// Seal `file` against all modifications, and against further seals.
+    pub fn seal_fully(file: &File) -> std::io::Result<()> {
+        let seals =
+            libc::F_SEAL_SEAL | libc::F_SEAL_SHRINK | libc::F_SEAL_WRITE | libc::F_SEAL_GROW;
+        // SAFETY: file is a valid FD.
+        cvt(unsafe { libc::fcntl(file.as_raw_fd(), libc::F_ADD_SEALS, seals) })?;
+        Ok(())
+    }
+
+    /// Create an anonymous file in `/dev/shm`, readable and writable by the
+    /// current user only.
+    pub fn shm_tmpfile() -> std::io::Result<File> {
+        let flags = libc::O_TMPFILE | libc::O_RDWR | libc::O_CLOEXEC;
+        let mode: libc::c_uint = 0o600;
+        // SAFETY: The path is a valid NUL-terminated string.
+        let fd = cvt(unsafe { libc::open(b"/dev/shm\0".as_ptr().cast(), flags, mode) })?;
+        // SAFETY: open returned a new FD which we own.
+        Ok(unsafe { File::from_raw_fd(fd) })
+    }
+
+    /// Request a send buffer of `size` bytes for `socket`.
+    pub fn set_send_buffer_size(socket: &UnixDatagram, size: usize) -> std::io::Result<()> {
+        let size = libc::c_int::try_from(size).unwrap_or(libc::c_int::MAX);
+        // SAFETY: We pass a valid pointer to a c_int and its size.
+        cvt(unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_SNDBUF,
+                (&size as *const libc::c_int).cast(),
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        })?;
+        Ok(())
+    }
+
+    /// The size of the send buffer of `socket`.
+    pub fn send_buffer_size(socket: &UnixDatagram) -> std::io::Result<usize> {
+        let mut size: libc::c_int = 0;
+        let mut length = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+        // SAFETY: We pass a valid pointer to a c_int and its size.
+        cvt(unsafe {
+            libc::getsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_SNDBUF,
+                (&mut size as *mut libc::c_int).cast(),
+                &mut length,
+            )
+        })?;
+        Ok(size as usize)
+    }
+
+    /// The device and inode numbers of the file of `fd`.
+    pub fn device_and_inode(fd: BorrowedFd<'_>) -> std::io::Result<(u64, u64)> {
+        // SAFETY: stat is plain old data, and all zeros is valid.
+        let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+        // SAFETY: fd is a valid FD, and stat a valid pointer.
+        cvt(unsafe { libc::fstat(fd.as_raw_fd(), &mut stat) })?;
+        #[allow(clippy::useless_conversion)]
+        Ok((u64::from(stat.st_dev), u64::from(stat.st_ino)))
+    }
+
+    /// The effective user ID of the current process.
+    pub fn geteuid() -> u32 {
+        // SAFETY: geteuid always succeeds.
+        unsafe { libc::geteuid() }
+    }
+
+    /// The effective group ID of the current process.
+    pub fn getegid() -> u32 {
+        // SAFETY: getegid always succeeds.
+        unsafe { libc::getegid() }
+    }
+}
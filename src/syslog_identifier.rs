@@ -0,0 +1,162 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Validate syslog identifiers.
+
+use std::fmt::{Display, Formatter};
+
+/// The maximum length of a syslog identifier in bytes.
+///
+/// See [`crate::JournalLog::try_with_syslog_identifier`].
+pub const MAX_SYSLOG_IDENTIFIER_LEN: usize = 255;
+
+/// Why a syslog identifier is not valid.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum InvalidSyslogIdentifierReason {
+    /// The identifier is longer than [`MAX_SYSLOG_IDENTIFIER_LEN`] bytes.
+    TooLong,
+    /// The identifier contains a control character, e.g. a newline.
+    ControlCharacter(char),
+}
+
+impl Display for InvalidSyslogIdentifierReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InvalidSyslogIdentifierReason::TooLong => write!(
+                f,
+                "identifier is longer than {} bytes",
+                MAX_SYSLOG_IDENTIFIER_LEN
+            ),
+            InvalidSyslogIdentifierReason::ControlCharacter(c) => {
+                write!(f, "identifier contains control character {:?}", c)
+            }
+        }
+    }
+}
+
+/// A syslog identifier which is not valid.
+///
+/// See [`crate::JournalLog::try_with_syslog_identifier`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidSyslogIdentifier {
+    identifier: String,
+    reason: InvalidSyslogIdentifierReason,
+}
+
+impl InvalidSyslogIdentifier {
+    /// The offending identifier.
+    pub fn identifier(&self) -> &str {
+        &self.identifier
+    }
+
+    /// Why the identifier is invalid.
+    pub fn reason(&self) -> InvalidSyslogIdentifierReason {
+        self.reason
+    }
+}
+
+impl Display for InvalidSyslogIdentifier {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Invalid syslog identifier {:?}: {}",
+            self.identifier, self.reason
+        )
+    }
+}
+
+impl std::error::Error for InvalidSyslogIdentifier {}
+
+/// Check whether `identifier` is a valid syslog identifier.
+///
+/// A valid identifier has at most [`MAX_SYSLOG_IDENTIFIER_LEN`] bytes, and no
+/// control characters.
+pub fn validate_syslog_identifier(identifier: String) -> Result<String, InvalidSyslogIdentifier> {
+    let reason = if MAX_SYSLOG_IDENTIFIER_LEN < identifier.len() {
+        Some(InvalidSyslogIdentifierReason::TooLong)
+    } else {
+        identifier
+            .chars()
+            .find(|c| c.is_control())
+            .map(InvalidSyslogIdentifierReason::ControlCharacter)
+    };
+    match reason {
+        None => Ok(identifier),
+        Some(reason) => Err(InvalidSyslogIdentifier { identifier, reason }),
+    }
+}
+
+/// Make `identifier` a valid syslog identifier.
+///
+/// Replace every control character with a space, and truncate the identifier
+/// to at most [`MAX_SYSLOG_IDENTIFIER_LEN`] bytes, at a character boundary.
+///
+/// ```rust
+/// use systemd_journal_logger::sanitize_syslog_identifier;
+///
+/// assert_eq!(sanitize_syslog_identifier("my\nservice"), "my service");
+/// assert_eq!(sanitize_syslog_identifier(&"x".repeat(300)).len(), 255);
+/// ```
+pub fn sanitize_syslog_identifier(identifier: &str) -> String {
+    let mut sanitized = String::with_capacity(identifier.len().min(MAX_SYSLOG_IDENTIFIER_LEN));
+    for c in identifier.chars() {
+        let c = if c.is_control() { ' ' } else { c };
+        if MAX_SYSLOG_IDENTIFIER_LEN < sanitized.len() + c.len_utf8() {
+            break;
+        }
+        sanitized.push(c);
+    }
+    sanitized
+}
+
+#[cfg(test)]
+mod tests {
+    use similar_asserts::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn validate_syslog_identifier() {
+        assert_eq!(
+            super::validate_syslog_identifier("my-service".to_string()),
+            Ok("my-service".to_string())
+        );
+        assert_eq!(
+            super::validate_syslog_identifier("x".repeat(255)),
+            Ok("x".repeat(255))
+        );
+        assert_eq!(
+            super::validate_syslog_identifier("x".repeat(256))
+                .unwrap_err()
+                .reason(),
+            InvalidSyslogIdentifierReason::TooLong
+        );
+        let error = super::validate_syslog_identifier("my\nservice".to_string()).unwrap_err();
+        assert_eq!(
+            error.reason(),
+            InvalidSyslogIdentifierReason::ControlCharacter('\n')
+        );
+        assert_eq!(error.identifier(), "my\nservice");
+    }
+
+    #[test]
+    fn sanitize_syslog_identifier() {
+        assert_eq!(
+            super::sanitize_syslog_identifier("my-service"),
+            "my-service"
+        );
+        assert_eq!(
+            super::sanitize_syslog_identifier("my\nservice\0"),
+            "my service "
+        );
+        // Do not split multi-byte characters
+        let sanitized = super::sanitize_syslog_identifier(&"ä".repeat(200));
+        assert_eq!(sanitized, "ä".repeat(127));
+        assert!(super::validate_syslog_identifier(sanitized).is_ok());
+    }
+}
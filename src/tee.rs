@@ -0,0 +1,160 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Send records to two loggers.
+
+use log::{Log, Metadata, Record};
+
+/// A logger which forwards records to two loggers.
+///
+/// Use this logger to log to the journal and to another logger at the same
+/// time, e.g. to an in-memory buffer for a status page:
+///
+/// ```rust
+/// use systemd_journal_logger::{JournalLog, TeeLogger};
+/// # struct StatusBuffer;
+/// # impl log::Log for StatusBuffer {
+/// #     fn enabled(&self, _: &log::Metadata) -> bool { true }
+/// #     fn log(&self, _: &log::Record) {}
+/// #     fn flush(&self) {}
+/// # }
+///
+/// let logger = TeeLogger::new(JournalLog::new().unwrap(), StatusBuffer);
+/// log::set_boxed_logger(Box::new(logger)).unwrap();
+/// log::set_max_level(log::LevelFilter::Info);
+/// ```
+///
+/// Each logger only receives the records it is enabled for.
+#[derive(Debug)]
+pub struct TeeLogger<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A: Log, B: Log> TeeLogger<A, B> {
+    /// Forward records to `first` and to `second`, in this order.
+    pub fn new(first: A, second: B) -> Self {
+        Self { first, second }
+    }
+
+    /// The first logger.
+    pub fn first(&self) -> &A {
+        &self.first
+    }
+
+    /// The second logger.
+    pub fn second(&self) -> &B {
+        &self.second
+    }
+}
+
+impl<A: Log, B: Log> Log for TeeLogger<A, B> {
+    /// Whether any of both loggers is enabled for `metadata`.
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.first.enabled(metadata) || self.second.enabled(metadata)
+    }
+
+    /// Log `record` to each logger which is enabled for `record`.
+    fn log(&self, record: &Record) {
+        let metadata = record.metadata();
+        if self.first.enabled(metadata) {
+            self.first.log(record);
+        }
+        if self.second.enabled(metadata) {
+            self.second.log(record);
+        }
+    }
+
+    /// Flush both loggers.
+    fn flush(&self) {
+        self.first.flush();
+        self.second.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use log::{Level, LevelFilter};
+    use similar_asserts::assert_eq;
+
+    use super::*;
+
+    struct Recorder {
+        level: LevelFilter,
+        messages: Mutex<Vec<String>>,
+        flushed: Mutex<bool>,
+    }
+
+    impl Recorder {
+        fn new(level: LevelFilter) -> Self {
+            Self {
+                level,
+                messages: Mutex::new(Vec::new()),
+                flushed: Mutex::new(false),
+            }
+        }
+    }
+
+    impl Log for Recorder {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            metadata.level() <= self.level
+        }
+
+        fn log(&self, record: &Record) {
+            self.messages
+                .lock()
+                .unwrap()
+                .push(record.args().to_string());
+        }
+
+        fn flush(&self) {
+            *self.flushed.lock().unwrap() = true;
+        }
+    }
+
+    #[test]
+    fn forward_to_enabled_loggers() {
+        let tee = TeeLogger::new(
+            Recorder::new(LevelFilter::Warn),
+            Recorder::new(LevelFilter::Info),
+        );
+        for (level, message) in [
+            (Level::Error, "error"),
+            (Level::Info, "info"),
+            (Level::Debug, "debug"),
+        ] {
+            tee.log(
+                &Record::builder()
+                    .level(level)
+                    .args(format_args!("{}", message))
+                    .build(),
+            );
+        }
+        assert_eq!(*tee.first().messages.lock().unwrap(), vec!["error"]);
+        assert_eq!(
+            *tee.second().messages.lock().unwrap(),
+            vec!["error", "info"]
+        );
+
+        assert!(tee.enabled(&Metadata::builder().level(Level::Info).build()));
+        assert!(!tee.enabled(&Metadata::builder().level(Level::Debug).build()));
+    }
+
+    #[test]
+    fn flush_both_loggers() {
+        let tee = TeeLogger::new(
+            Recorder::new(LevelFilter::Off),
+            Recorder::new(LevelFilter::Off),
+        );
+        tee.flush();
+        assert!(*tee.first().flushed.lock().unwrap());
+        assert!(*tee.second().flushed.lock().unwrap());
+    }
+}
@@ -0,0 +1,439 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Read back journal entries, to test logging in integration tests.
+//!
+//! This module invokes `journalctl` to read entries from the journal, and
+//! parses its JSON output.
+//!
+//! ```no_run
+//! use log::{info, LevelFilter};
+//! use systemd_journal_logger::JournalLog;
+//! use systemd_journal_logger::testing::{read_one_entry, Journal};
+//!
+//! JournalLog::new().unwrap().install().unwrap();
+//! log::set_max_level(LevelFilter::Info);
+//!
+//! info!(target: "my_test", "Hello World");
+//!
+//! let pid = std::process::id().to_string();
+//! let entry = read_one_entry(Journal::User, &[("_PID", &pid), ("TARGET", "my_test")]).unwrap();
+//! assert_eq!(entry["MESSAGE"], "Hello World");
+//! ```
+//!
+//! To test logging without journald use an [`InMemoryClient`] instead, which
+//! captures all payloads in memory, or capture all records logged in a test
+//! with [`crate::JournalLog::capture`].
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fmt::Display;
+use std::marker::PhantomData;
+use std::process::Command;
+use std::sync::{Arc, Mutex, Once};
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::{JournalLog, JournalTransport};
+
+/// A journal to read from.
+#[derive(Debug, Copy, Clone)]
+pub enum Journal {
+    /// The journal of the current user, i.e. `journalctl --user`.
+    User,
+    /// The system journal.
+    System,
+}
+
+/// The value of a field in a journal entry.
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum FieldValue {
+    /// A text value.
+    Text(String),
+    /// Multiple values for the same field.
+    Array(Vec<String>),
+    /// A binary value, e.g. a value with non-printable characters.
+    Binary(Vec<u8>),
+}
+
+impl FieldValue {
+    /// Get this value as text.
+    ///
+    /// Decode binary values lossily, and return the first value of arrays.
+    pub fn as_text(&self) -> Cow<'_, str> {
+        match self {
+            FieldValue::Text(v) => Cow::Borrowed(v.as_str()),
+            FieldValue::Binary(binary) => String::from_utf8_lossy(binary),
+            FieldValue::Array(v) => Cow::Borrowed(v.first().map_or("", |s| s.as_str())),
+        }
+    }
+}
+
+impl Display for FieldValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.as_text().fmt(f)
+    }
+}
+
+// Convenience impls to compare fields against strings and bytes with assert_eq!
+impl PartialEq<&str> for FieldValue {
+    fn eq(&self, other: &&str) -> bool {
+        match self {
+            FieldValue::Text(s) => s == other,
+            FieldValue::Binary(b) => b == other.as_bytes(),
+            FieldValue::Array(_) => false,
+        }
+    }
+}
+
+// Convenience impls to compare fields against strings and bytes with assert_eq!
+impl PartialEq<String> for FieldValue {
+    fn eq(&self, other: &String) -> bool {
+        self == &other.as_str()
+    }
+}
+
+// Convenience impls to compare fields against strings and bytes with assert_eq!
+impl PartialEq<&String> for FieldValue {
+    fn eq(&self, other: &&String) -> bool {
+        self == &other.as_str()
+    }
+}
+
+impl PartialEq<[u8]> for FieldValue {
+    fn eq(&self, other: &[u8]) -> bool {
+        match self {
+            FieldValue::Text(s) => s.as_bytes() == other,
+            FieldValue::Binary(data) => data == other,
+            FieldValue::Array(_) => false,
+        }
+    }
+}
+
+impl PartialEq<Vec<&str>> for FieldValue {
+    fn eq(&self, other: &std::vec::Vec<&str>) -> bool {
+        match self {
+            FieldValue::Text(_) => false,
+            FieldValue::Binary(_) => false,
+            FieldValue::Array(a) => a == other,
+        }
+    }
+}
+
+/// A single journal entry, mapping field names to values.
+pub type Entry = HashMap<String, FieldValue>;
+
+/// Read from `journal`.
+///
+/// `args` contains additional journalctl arguments such as filters.
+///
+/// # Panics
+///
+/// Panic if `journalctl` fails to run, or if its output is not valid JSON.
+pub fn read<I, S>(journal: Journal, args: I) -> Vec<Entry>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let mut command = Command::new("journalctl");
+    if matches!(journal, Journal::User) {
+        command.arg("--user");
+    }
+
+    let stdout = String::from_utf8(
+        command
+            .arg("--output=json")
+            // We pass --all to circumvent journalctl's default limit of 4096 bytes for field values
+            .arg("--all")
+            .args(args)
+            .output()
+            .unwrap()
+            .stdout,
+    )
+    .unwrap();
+
+    stdout
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect()
+}
+
+/// Read all entries from `journal` which match all `filters`.
+///
+/// Each filter is a pair of a field name and the value the field must have.
+pub fn read_entries(journal: Journal, filters: &[(&str, &str)]) -> Vec<Entry> {
+    read(
+        journal,
+        filters
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value)),
+    )
+}
+
+/// Parse entries in the journal export format from `export`.
+///
+/// Parse both text fields, i.e. `KEY=value`, and binary fields with a length
+/// prefix, and return all entries in `export`, e.g. as written by
+/// [`crate::JournalLog::write_export`].  Empty lines separate entries.
+///
+/// Like `journalctl --output=json`, return values which are valid UTF-8 as
+/// [`FieldValue::Text`] and other values as [`FieldValue::Binary`], and collect
+/// the values of fields which occur multiple times in an entry in a
+/// [`FieldValue::Array`].
+///
+/// See <https://systemd.io/JOURNAL_EXPORT_FORMATS/> for the format.
+///
+/// # Panics
+///
+/// Panic if `export` is not well-formed.
+pub fn parse_export(export: &[u8]) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    let mut entry = Entry::new();
+    let mut rest = export;
+    while !rest.is_empty() {
+        let line_end = rest
+            .iter()
+            .position(|b| *b == b'\n')
+            .expect("field not terminated by newline");
+        let line = &rest[..line_end];
+        if line.is_empty() {
+            entries.push(std::mem::take(&mut entry));
+            rest = &rest[1..];
+            continue;
+        }
+        let (name, value) = match line.iter().position(|b| *b == b'=') {
+            Some(separator) => {
+                rest = &rest[line_end + 1..];
+                (&line[..separator], &line[separator + 1..])
+            }
+            None => {
+                let value_start = line_end + 1 + 8;
+                let length_bytes = rest
+                    .get(line_end + 1..value_start)
+                    .expect("binary field without length");
+                let length = u64::from_le_bytes(length_bytes.try_into().unwrap()) as usize;
+                let value = rest
+                    .get(value_start..value_start + length)
+                    .expect("binary field value shorter than its length");
+                assert_eq!(
+                    rest.get(value_start + length),
+                    Some(&b'\n'),
+                    "binary field not terminated by newline"
+                );
+                rest = &rest[value_start + length + 1..];
+                (line, value)
+            }
+        };
+        let name = String::from_utf8(name.to_vec()).expect("field name not valid UTF-8");
+        let value = match String::from_utf8(value.to_vec()) {
+            Ok(text) => FieldValue::Text(text),
+            Err(error) => FieldValue::Binary(error.into_bytes()),
+        };
+        match entry.remove(&name) {
+            None => {
+                entry.insert(name, value);
+            }
+            Some(existing) => {
+                let mut values = match existing {
+                    FieldValue::Array(values) => values,
+                    other => vec![other.as_text().into_owned()],
+                };
+                values.push(value.as_text().into_owned());
+                entry.insert(name, FieldValue::Array(values));
+            }
+        }
+    }
+    if !entry.is_empty() {
+        entries.push(entry);
+    }
+    entries
+}
+
+/// Wait for a single entry matching `filters` in `journal`.
+///
+/// journald processes entries asynchronously, so try for about three seconds
+/// until exactly one entry matches `filters` (see [`read_entries`]).
+///
+/// Return `None` if no single matching entry appeared in time.
+pub fn read_one_entry(journal: Journal, filters: &[(&str, &str)]) -> Option<Entry> {
+    for _ in 0..30 {
+        let mut entries = read_entries(journal, filters);
+        if entries.len() == 1 {
+            return entries.pop();
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    None
+}
+
+/// A transport which captures payloads in memory.
+///
+/// Pass this client to [`crate::JournalLog::new_with_transport`] to unit-test
+/// logging without journald, and inspect the captured payloads afterwards:
+///
+/// ```rust
+/// use std::sync::Arc;
+/// use log::Log;
+/// use systemd_journal_logger::JournalLog;
+/// use systemd_journal_logger::testing::InMemoryClient;
+///
+/// let client = Arc::new(InMemoryClient::new());
+/// let logger = JournalLog::new_with_transport(client.clone())
+///     .unwrap()
+///     .with_syslog_identifier("my-service".to_string());
+/// logger.log(
+///     &log::Record::builder()
+///         .level(log::Level::Warn)
+///         .args(format_args!("Disk almost full"))
+///         .build(),
+/// );
+///
+/// let entries = client.entries();
+/// assert_eq!(entries.len(), 1);
+/// assert_eq!(entries[0]["MESSAGE"], "Disk almost full");
+/// assert_eq!(entries[0]["PRIORITY"], "4");
+/// assert_eq!(entries[0]["SYSLOG_IDENTIFIER"], "my-service");
+/// ```
+#[derive(Debug, Default)]
+pub struct InMemoryClient {
+    payloads: Mutex<Vec<Vec<u8>>>,
+}
+
+impl InMemoryClient {
+    /// Create a client without any payloads.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// All payloads sent to this client so far, oldest first.
+    pub fn payloads(&self) -> Vec<Vec<u8>> {
+        self.payloads.lock().unwrap().clone()
+    }
+
+    /// Remove and return all payloads sent to this client so far.
+    pub fn take_payloads(&self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut *self.payloads.lock().unwrap())
+    }
+
+    /// Parse all payloads sent to this client so far into entries.
+    ///
+    /// journald's native protocol and the journal export format encode
+    /// fields alike, so parse each payload with [`parse_export`].
+    ///
+    /// # Panics
+    ///
+    /// Panic if a payload is not well-formed.
+    pub fn entries(&self) -> Vec<Entry> {
+        self.payloads
+            .lock()
+            .unwrap()
+            .iter()
+            .flat_map(|payload| parse_export(payload))
+            .collect()
+    }
+}
+
+impl JournalTransport for InMemoryClient {
+    /// Capture `payload`.
+    fn send_payload(&self, payload: &[u8]) -> std::io::Result<usize> {
+        self.payloads.lock().unwrap().push(payload.to_vec());
+        Ok(payload.len())
+    }
+}
+
+thread_local! {
+    /// The clients capturing records of the current thread, innermost last.
+    static CAPTURES: RefCell<Vec<Arc<InMemoryClient>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// The innermost client capturing records of the current thread, if any.
+pub(crate) fn current_capture() -> Option<Arc<InMemoryClient>> {
+    CAPTURES
+        .try_with(|captures| captures.borrow().last().cloned())
+        .ok()
+        .flatten()
+}
+
+/// A transport which discards all payloads.
+struct Discard;
+
+impl JournalTransport for Discard {
+    fn send_payload(&self, payload: &[u8]) -> std::io::Result<usize> {
+        Ok(payload.len())
+    }
+}
+
+/// Install a logger for captures, unless a logger is installed already.
+fn install_capture_logger() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        if let Ok(logger) = JournalLog::new_with_transport(Arc::new(Discard)) {
+            if logger.install().is_ok() {
+                log::set_max_level(log::LevelFilter::Trace);
+            }
+        }
+    });
+}
+
+/// Captures records logged on the current thread.
+///
+/// See [`crate::JournalLog::capture`].  Dropping the guard ends the capture,
+/// and restores the previous capture of the current thread, if any.
+#[derive(Debug)]
+#[must_use = "Dropping the guard ends the capture immediately"]
+pub struct CaptureGuard {
+    client: Arc<InMemoryClient>,
+    /// Captures are per thread, so the guard must stay on its thread.
+    _not_send: PhantomData<*const ()>,
+}
+
+impl CaptureGuard {
+    /// Start capturing records logged on the current thread.
+    pub(crate) fn start() -> Self {
+        install_capture_logger();
+        let client = Arc::new(InMemoryClient::new());
+        CAPTURES.with(|captures| captures.borrow_mut().push(client.clone()));
+        Self {
+            client,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// All entries captured so far, oldest first.
+    ///
+    /// # Panics
+    ///
+    /// See [`InMemoryClient::entries`].
+    pub fn entries(&self) -> Vec<Entry> {
+        self.client.entries()
+    }
+
+    /// End this capture, and return all captured entries, oldest first.
+    ///
+    /// # Panics
+    ///
+    /// See [`InMemoryClient::entries`].
+    pub fn finish(self) -> Vec<Entry> {
+        self.client.entries()
+    }
+}
+
+impl Drop for CaptureGuard {
+    /// End this capture.
+    fn drop(&mut self) {
+        let _ = CAPTURES.try_with(|captures| {
+            captures
+                .borrow_mut()
+                .retain(|client| !Arc::ptr_eq(client, &self.client))
+        });
+    }
+}
@@ -0,0 +1,136 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Timestamp fields for records with a known event time.
+
+use std::io::Write;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// The usual size of the fields [`put_timestamp_fields`] writes.
+///
+/// `SOURCE_REALTIME_TIMESTAMP` with up to 20 digits, and `SYSLOG_TIMESTAMP`
+/// with 15 characters, each with a separator and a trailing newline.
+pub const TIMESTAMP_FIELDS_SIZE: usize = (25 + 1 + 20 + 1) + (16 + 1 + 15 + 1);
+
+/// Write timestamp fields for `timestamp` to `buffer`.
+///
+/// Write `SOURCE_REALTIME_TIMESTAMP` with the microseconds since the epoch,
+/// which journald and journalctl use as the time of the entry, and
+/// `SYSLOG_TIMESTAMP` in RFC 3164 format, in UTC.
+///
+/// Fail with [`std::io::ErrorKind::InvalidInput`] if `timestamp` is before the
+/// epoch, because journald only accepts non-negative microseconds.
+pub fn put_timestamp_fields<W: Write + ?Sized>(
+    buffer: &mut W,
+    timestamp: SystemTime,
+) -> std::io::Result<()> {
+    let since_epoch = since_epoch(timestamp)?;
+    writeln!(
+        buffer,
+        "SOURCE_REALTIME_TIMESTAMP={}",
+        since_epoch.as_micros()
+    )?;
+    writeln!(
+        buffer,
+        "SYSLOG_TIMESTAMP={}",
+        syslog_timestamp(since_epoch.as_secs())
+    )?;
+    Ok(())
+}
+
+/// The time between the epoch and `timestamp`.
+///
+/// Fail with [`std::io::ErrorKind::InvalidInput`] if `timestamp` is before the
+/// epoch.
+pub fn since_epoch(timestamp: SystemTime) -> std::io::Result<Duration> {
+    timestamp.duration_since(UNIX_EPOCH).map_err(|_| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "timestamp before the Unix epoch",
+        )
+    })
+}
+
+/// Format `secs` since the epoch as RFC 3164 timestamp in UTC.
+///
+/// RFC 3164 timestamps look like `Oct  6 09:51:22`; they omit the year and pad
+/// the day with a space.
+fn syslog_timestamp(secs: u64) -> String {
+    let (month, day) = month_and_day(secs / 86400);
+    let secs_of_day = secs % 86400;
+    format!(
+        "{} {:>2} {:02}:{:02}:{:02}",
+        MONTHS[month as usize - 1],
+        day,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60
+    )
+}
+
+/// The month and the day of the month, both starting at 1, of `days` since the
+/// epoch.
+///
+/// See <https://howardhinnant.github.io/date_algorithms.html#civil_from_days>.
+fn month_and_day(days: u64) -> (u64, u64) {
+    // Shift the epoch to 0000-03-01, the start of a 400 year era
+    let z = days + 719_468;
+    let day_of_era = z % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    // Months starting at March
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use similar_asserts::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn syslog_timestamp() {
+        assert_eq!(super::syslog_timestamp(0), "Jan  1 00:00:00");
+        // 2026-10-16T09:51:22Z
+        assert_eq!(super::syslog_timestamp(1_792_144_282), "Oct 16 09:51:22");
+        // 2024-02-29T23:59:59Z
+        assert_eq!(super::syslog_timestamp(1_709_251_199), "Feb 29 23:59:59");
+        // 2000-03-01T12:00:00Z
+        assert_eq!(super::syslog_timestamp(951_912_000), "Mar  1 12:00:00");
+    }
+
+    #[test]
+    fn put_timestamp_fields() {
+        let mut buffer = Vec::new();
+        super::put_timestamp_fields(
+            &mut buffer,
+            UNIX_EPOCH + Duration::from_micros(1_792_144_282_123_456),
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(buffer).unwrap(),
+            "SOURCE_REALTIME_TIMESTAMP=1792144282123456\nSYSLOG_TIMESTAMP=Oct 16 09:51:22\n"
+        );
+    }
+
+    #[test]
+    fn put_timestamp_fields_before_epoch() {
+        let error =
+            super::put_timestamp_fields(&mut Vec::new(), UNIX_EPOCH - Duration::from_secs(1))
+                .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+    }
+}
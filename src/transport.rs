@@ -0,0 +1,125 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Send payloads to journald or elsewhere.
+
+use std::io::IoSlice;
+use std::sync::{Arc, PoisonError, RwLock};
+
+use crate::client::JournalClient;
+
+/// Send journal payloads somewhere.
+///
+/// A payload is a single journal entry in journald's native protocol (see
+/// <https://systemd.io/JOURNAL_NATIVE_PROTOCOL/>).  The logger sends payloads
+/// to journald's socket by default; implement this trait to send payloads
+/// elsewhere, e.g. to memory in unit tests (see `InMemoryClient` in the
+/// `testing` module), or to a remote journal, and pass the transport to
+/// [`crate::JournalLog::new_with_transport`] or
+/// [`crate::JournalLog::with_transport`]:
+///
+/// ```rust
+/// use std::net::UdpSocket;
+/// use std::sync::Arc;
+/// use systemd_journal_logger::{JournalLog, JournalTransport};
+///
+/// struct Udp(UdpSocket);
+///
+/// impl JournalTransport for Udp {
+///     fn send_payload(&self, payload: &[u8]) -> std::io::Result<usize> {
+///         self.0.send(payload)
+///     }
+/// }
+///
+/// let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+/// socket.connect("127.0.0.1:5514").unwrap();
+/// let logger = JournalLog::new_with_transport(Arc::new(Udp(socket))).unwrap();
+/// ```
+///
+/// The logger sends every payload exactly once, from the logging thread or
+/// from its background thread (see
+/// [`crate::JournalLog::with_background_sender`]), and ignores errors from
+/// [`crate::JournalLog`]'s `log` method like it does for journald's socket.
+/// A transport fails with [`std::io::ErrorKind::WouldBlock`] to count a
+/// payload as dropped (see [`crate::JournalLog::dropped_records`]).
+pub trait JournalTransport: Send + Sync {
+    /// Send `payload`.
+    ///
+    /// Return the number of bytes sent.
+    fn send_payload(&self, payload: &[u8]) -> std::io::Result<usize>;
+
+    /// Send the concatenation of all `slices` as a single payload.
+    ///
+    /// The default implementation copies all slices into a single buffer and
+    /// sends it with [`Self::send_payload`].
+    fn send_payload_vectored(&self, slices: &[IoSlice<'_>]) -> std::io::Result<usize> {
+        let mut payload = Vec::with_capacity(slices.iter().map(|slice| slice.len()).sum());
+        for slice in slices {
+            payload.extend_from_slice(slice);
+        }
+        self.send_payload(&payload)
+    }
+}
+
+impl JournalTransport for JournalClient {
+    fn send_payload(&self, payload: &[u8]) -> std::io::Result<usize> {
+        JournalClient::send_payload(self, payload)
+    }
+
+    fn send_payload_vectored(&self, slices: &[IoSlice<'_>]) -> std::io::Result<usize> {
+        JournalClient::send_payload_vectored(self, slices)
+    }
+}
+
+/// The custom transport of a logger, if any.
+///
+/// Shared between a logger and the threads and hooks which send on behalf of
+/// the logger, so that replacing the transport of the logger applies to all of
+/// them.
+#[derive(Default)]
+pub struct TransportSlot(RwLock<Option<Arc<dyn JournalTransport>>>);
+
+impl TransportSlot {
+    /// The current transport, if any.
+    ///
+    /// Return a clone, so that callers don't hold the lock while sending.
+    pub fn get(&self) -> Option<Arc<dyn JournalTransport>> {
+        self.0
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Replace the current transport with `transport`.
+    pub fn set(&self, transport: Option<Arc<dyn JournalTransport>>) {
+        *self.0.write().unwrap_or_else(PoisonError::into_inner) = transport;
+    }
+}
+
+/// Send through the current transport in a slot, or through a client if the
+/// slot is empty.
+pub struct SlotOrClient {
+    pub slot: Arc<TransportSlot>,
+    pub client: JournalClient,
+}
+
+impl JournalTransport for SlotOrClient {
+    fn send_payload(&self, payload: &[u8]) -> std::io::Result<usize> {
+        match self.slot.get() {
+            Some(transport) => transport.send_payload(payload),
+            None => self.client.send_payload(payload),
+        }
+    }
+
+    fn send_payload_vectored(&self, slices: &[IoSlice<'_>]) -> std::io::Result<usize> {
+        match self.slot.get() {
+            Some(transport) => transport.send_payload_vectored(slices),
+            None => self.client.send_payload_vectored(slices),
+        }
+    }
+}
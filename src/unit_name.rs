@@ -0,0 +1,167 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Validate systemd unit names.
+
+use std::fmt::{Display, Formatter};
+
+/// The maximum length of a unit name in bytes, see `systemd.unit(5)`.
+const MAX_UNIT_NAME_LEN: usize = 255;
+
+/// All unit types, see `systemd.unit(5)`.
+const UNIT_TYPES: [&str; 11] = [
+    "service",
+    "socket",
+    "device",
+    "mount",
+    "automount",
+    "swap",
+    "target",
+    "path",
+    "timer",
+    "slice",
+    "scope",
+];
+
+/// Why a unit name is not valid.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum InvalidUnitNameReason {
+    /// The name has no prefix before the unit type.
+    Empty,
+    /// The name is longer than 255 bytes.
+    TooLong,
+    /// The name does not end with a unit type, e.g. `.service`.
+    MissingUnitType,
+    /// The name contains a character other than ASCII letters, digits, `:`,
+    /// `-`, `_`, `.`, `\`, and `@`.
+    InvalidCharacter(char),
+}
+
+impl Display for InvalidUnitNameReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InvalidUnitNameReason::Empty => write!(f, "name is empty"),
+            InvalidUnitNameReason::TooLong => {
+                write!(f, "name is longer than {} bytes", MAX_UNIT_NAME_LEN)
+            }
+            InvalidUnitNameReason::MissingUnitType => {
+                write!(f, "name does not end with a unit type")
+            }
+            InvalidUnitNameReason::InvalidCharacter(c) => {
+                write!(f, "name contains invalid character {:?}", c)
+            }
+        }
+    }
+}
+
+/// A unit name which is not valid.
+///
+/// See [`crate::JournalLog::with_unit`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidUnitName {
+    name: String,
+    reason: InvalidUnitNameReason,
+}
+
+impl InvalidUnitName {
+    /// The offending unit name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Why the unit name is invalid.
+    pub fn reason(&self) -> InvalidUnitNameReason {
+        self.reason
+    }
+}
+
+impl Display for InvalidUnitName {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid unit name {:?}: {}", self.name, self.reason)
+    }
+}
+
+impl std::error::Error for InvalidUnitName {}
+
+/// Check whether `name` is a valid unit name, like `my-service.service`.
+pub fn validate_unit_name(name: String) -> Result<String, InvalidUnitName> {
+    let reason = if MAX_UNIT_NAME_LEN < name.len() {
+        Some(InvalidUnitNameReason::TooLong)
+    } else if let Some(c) = name
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || ":-_.\\@".contains(*c)))
+    {
+        Some(InvalidUnitNameReason::InvalidCharacter(c))
+    } else {
+        match name.rsplit_once('.') {
+            Some((prefix, unit_type)) if UNIT_TYPES.contains(&unit_type) => {
+                if prefix.is_empty() || prefix == "@" {
+                    Some(InvalidUnitNameReason::Empty)
+                } else {
+                    None
+                }
+            }
+            _ => Some(InvalidUnitNameReason::MissingUnitType),
+        }
+    };
+    match reason {
+        None => Ok(name),
+        Some(reason) => Err(InvalidUnitName { name, reason }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use similar_asserts::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn validate_unit_name() {
+        for name in [
+            "my-service.service",
+            "getty@tty1.service",
+            "system-getty.slice",
+            "dev-disk-by\\x2duuid.device",
+        ] {
+            assert_eq!(
+                super::validate_unit_name(name.to_string()),
+                Ok(name.to_string())
+            );
+        }
+        let cases = [
+            ("".to_string(), InvalidUnitNameReason::MissingUnitType),
+            (".service".to_string(), InvalidUnitNameReason::Empty),
+            (
+                "my-service".to_string(),
+                InvalidUnitNameReason::MissingUnitType,
+            ),
+            (
+                "my.bogus".to_string(),
+                InvalidUnitNameReason::MissingUnitType,
+            ),
+            (
+                "my service.service".to_string(),
+                InvalidUnitNameReason::InvalidCharacter(' '),
+            ),
+            (
+                "my\nservice.service".to_string(),
+                InvalidUnitNameReason::InvalidCharacter('\n'),
+            ),
+            (
+                format!("{}.service", "a".repeat(250)),
+                InvalidUnitNameReason::TooLong,
+            ),
+        ];
+        for (name, reason) in cases {
+            let error = super::validate_unit_name(name.clone()).unwrap_err();
+            assert_eq!(error.name(), name);
+            assert_eq!(error.reason(), reason);
+        }
+    }
+}
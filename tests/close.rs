@@ -0,0 +1,31 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+#![deny(warnings, clippy::all)]
+
+use similar_asserts::assert_eq;
+use systemd_journal_logger::JournalLog;
+
+fn open_fds() -> usize {
+    std::fs::read_dir("/proc/self/fd").unwrap().count()
+}
+
+// The only test in this binary, so that no other test opens file descriptors
+// concurrently
+#[test]
+fn close_releases_socket() {
+    let before = open_fds();
+    for _ in 0..10 {
+        let logger = JournalLog::new_lenient()
+            .unwrap()
+            .with_background_sender(16)
+            .unwrap();
+        assert!(logger.close());
+    }
+    assert_eq!(open_fds(), before);
+}
@@ -0,0 +1,626 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+#![deny(warnings, clippy::all)]
+
+use std::os::unix::fs::MetadataExt;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::kv::Value;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use similar_asserts::assert_eq;
+use systemd_journal_logger::testing::InMemoryClient;
+use systemd_journal_logger::{
+    argv0_identifier, pop_context, priority_for_level, push_context, Catalog, IdentifierSource,
+    JournalLog, JournalTransport, MessageId, DROP_SUMMARY_MESSAGE_ID,
+};
+
+#[test]
+fn log_to_memory() {
+    let client = Arc::new(InMemoryClient::new());
+    let logger = JournalLog::new_with_transport(client.clone())
+        .unwrap()
+        .with_extra_fields(vec![("SERVICE", "in_memory")]);
+    let kvs = [("spam", "no eggs")];
+    logger.log(
+        &Record::builder()
+            .level(Level::Error)
+            .target("in_memory")
+            .args(format_args!("Hello World"))
+            .key_values(&kvs)
+            .build(),
+    );
+
+    let entries = client.entries();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0]["MESSAGE"], "Hello World");
+    assert_eq!(entries[0]["PRIORITY"], "3");
+    assert_eq!(entries[0]["TARGET"], "in_memory");
+    assert_eq!(entries[0]["SPAM"], "no eggs");
+    assert_eq!(entries[0]["SERVICE"], "in_memory");
+
+    assert_eq!(client.take_payloads().len(), 1);
+    assert!(client.payloads().is_empty());
+}
+
+#[test]
+fn log_to_memory_from_background_sender() {
+    let client = Arc::new(InMemoryClient::new());
+    let logger = JournalLog::new_with_transport(client.clone())
+        .unwrap()
+        .with_background_sender(16)
+        .unwrap();
+    for i in 0..3 {
+        logger.log(&Record::builder().args(format_args!("Record {}", i)).build());
+    }
+    logger.flush();
+
+    let messages = client
+        .entries()
+        .iter()
+        .map(|entry| entry["MESSAGE"].to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(messages, vec!["Record 0", "Record 1", "Record 2"]);
+}
+
+#[test]
+fn background_sender_without_queue_capacity() {
+    let client = Arc::new(InMemoryClient::new());
+    let logger = JournalLog::new_with_transport(client.clone())
+        .unwrap()
+        .with_background_sender(0)
+        .unwrap();
+    logger.log(&Record::builder().args(format_args!("Queued")).build());
+    logger.flush();
+
+    assert_eq!(client.entries().len(), 1);
+    assert_eq!(logger.dropped_records(), 0);
+}
+
+#[test]
+fn replace_transport() {
+    let first = Arc::new(InMemoryClient::new());
+    let second = Arc::new(InMemoryClient::new());
+    let logger = JournalLog::new_with_transport(first.clone())
+        .unwrap()
+        .with_transport(second.clone());
+    logger.log(&Record::builder().args(format_args!("Hello World")).build());
+
+    assert!(first.payloads().is_empty());
+    assert_eq!(second.entries()[0]["MESSAGE"], "Hello World");
+}
+
+#[test]
+fn replace_transport_after_background_sender_and_panic_hook() {
+    let first = Arc::new(InMemoryClient::new());
+    let second = Arc::new(InMemoryClient::new());
+    let logger = JournalLog::new_with_transport(first.clone())
+        .unwrap()
+        .with_background_sender(16)
+        .unwrap();
+    logger.install_panic_hook();
+    let logger = logger.with_transport(second.clone());
+    logger.log(&Record::builder().args(format_args!("Hello World")).build());
+    logger.flush();
+    let panicked = std::thread::Builder::new()
+        .name("replace_transport".to_string())
+        .spawn(|| panic!("replace_transport"))
+        .unwrap()
+        .join();
+    assert!(panicked.is_err());
+
+    assert!(first.payloads().is_empty());
+    let entries = second.entries();
+    assert_eq!(entries[0]["MESSAGE"], "Hello World");
+    // Other tests may panic concurrently
+    assert!(entries.iter().any(|entry| entry
+        .get("PANIC_MESSAGE")
+        .map_or(false, |m| *m == "replace_transport")));
+}
+
+#[test]
+fn credentials_fields() {
+    let client = Arc::new(InMemoryClient::new());
+    let logger = JournalLog::new_with_transport(client.clone())
+        .unwrap()
+        .with_credentials_fields(true);
+    logger.log(&Record::builder().args(format_args!("Hello World")).build());
+
+    // procfs gives /proc/self the effective credentials of the process
+    let proc_self = std::fs::metadata("/proc/self").unwrap();
+    let entry = &client.entries()[0];
+    assert_eq!(entry["EUID"], proc_self.uid().to_string());
+    assert_eq!(entry["EGID"], proc_self.gid().to_string());
+}
+
+#[test]
+fn syslog_identifier_from_argv0() {
+    let client = Arc::new(InMemoryClient::new());
+    let logger = JournalLog::new_with_transport(client.clone())
+        .unwrap()
+        .with_syslog_identifier_from(IdentifierSource::Argv0);
+    logger.log(&Record::builder().args(format_args!("Hello World")).build());
+
+    let argv0 = std::env::args().next().unwrap();
+    let name = argv0.rsplit('/').next().unwrap();
+    assert_eq!(client.entries()[0]["SYSLOG_IDENTIFIER"], name);
+    assert_eq!(argv0_identifier().as_deref(), Some(name));
+}
+
+#[test]
+fn escape_malicious_field_names() {
+    let client = Arc::new(InMemoryClient::new());
+    let logger = JournalLog::new_with_transport(client.clone())
+        .unwrap()
+        .add_extra_field("EXTRA\nPRIORITY=0", "extra")
+        .with_target_as_field("TARGET=\n")
+        .with_sequence_field("SEQ\n=")
+        .with_context_stack(true);
+    push_context("CONTEXT\nMESSAGE", "context");
+    let kvs = [("key\nMESSAGE=", "value")];
+    logger.log(
+        &Record::builder()
+            .args(format_args!("Hello World"))
+            .key_values(&kvs)
+            .build(),
+    );
+    pop_context();
+    logger
+        .send_fields(&[("FIELD\nPRIORITY=0", b"field")])
+        .unwrap();
+
+    let payloads = client.payloads();
+    assert_eq!(payloads.len(), 2);
+    for payload in &payloads {
+        // Every payload must parse into exactly one entry with valid names
+        let entries = systemd_journal_logger::testing::parse_export(payload);
+        assert_eq!(entries.len(), 1);
+        for name in entries[0].keys() {
+            assert!(
+                !name.is_empty()
+                    && name
+                        .chars()
+                        .all(|c| matches!(c, 'A'..='Z' | '0'..='9' | '_')),
+                "{:?}",
+                name
+            );
+        }
+    }
+    let entries = client.entries();
+    assert_eq!(entries[0]["MESSAGE"], "Hello World");
+    assert_eq!(entries[0]["PRIORITY"], "5");
+    for value in ["extra", "context", "value"] {
+        assert!(entries[0].values().any(|v| *v == value), "{}", value);
+    }
+    assert_eq!(entries[1]["PRIORITY"], "6");
+    assert!(entries[1].values().any(|v| *v == "field"));
+}
+
+#[test]
+fn log_catalog() {
+    let started = MessageId::from_u128(0x39f53479d3a045ac8e11786248231fbf);
+    let client = Arc::new(InMemoryClient::new());
+    let logger = JournalLog::new_with_transport(client.clone())
+        .unwrap()
+        .with_catalog(Catalog::new([("started", started)]));
+
+    logger
+        .log_catalog("started", Level::Info, format_args!("Service started"))
+        .unwrap();
+    let error = logger
+        .log_catalog("stopped", Level::Info, format_args!("Service stopped"))
+        .unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+    assert_eq!(error.to_string(), "Unknown catalog event \"stopped\"");
+
+    let entries = client.entries();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0]["MESSAGE"], "Service started");
+    assert_eq!(entries[0]["MESSAGE_ID"], started.to_string());
+}
+
+#[test]
+fn send_binary_message() {
+    let client = Arc::new(InMemoryClient::new());
+    let logger = JournalLog::new_with_transport(client.clone())
+        .unwrap()
+        .with_syslog_identifier("in_memory".to_string());
+    let message = [0x02, 0xff, 0x00, b'\n', b'='];
+    logger
+        .send_binary_message(Level::Warn, &message, &[("protocol", b"modbus")])
+        .unwrap();
+
+    let entries = client.entries();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0]["MESSAGE"], message[..]);
+    assert_eq!(entries[0]["PRIORITY"], "4");
+    assert_eq!(entries[0]["SYSLOG_IDENTIFIER"], "in_memory");
+    assert_eq!(entries[0]["PROTOCOL"], "modbus");
+}
+
+#[test]
+fn source_realtime_timestamp_key() {
+    let client = Arc::new(InMemoryClient::new());
+    let logger = JournalLog::new_with_transport(client.clone()).unwrap();
+    let kvs: [(&str, &dyn log::kv::ToValue); 2] = [
+        ("source_realtime_timestamp", &1_792_144_282_123_456_u64),
+        ("other", &"value"),
+    ];
+    logger.log(
+        &Record::builder()
+            .args(format_args!("Replayed"))
+            .key_values(&kvs)
+            .build(),
+    );
+    let kvs = [("source_realtime_timestamp", "yesterday")];
+    logger.log(
+        &Record::builder()
+            .args(format_args!("Not replayed"))
+            .key_values(&kvs)
+            .build(),
+    );
+
+    let entries = client.entries();
+    assert_eq!(entries[0]["SOURCE_REALTIME_TIMESTAMP"], "1792144282123456");
+    assert_eq!(entries[0]["SYSLOG_TIMESTAMP"], "Oct 16 09:51:22");
+    assert_eq!(entries[0]["OTHER"], "value");
+    // Write values which are no timestamp as ordinary field
+    assert_eq!(entries[1]["SOURCE_REALTIME_TIMESTAMP"], "yesterday");
+    assert!(!entries[1].contains_key("SYSLOG_TIMESTAMP"));
+}
+
+#[test]
+fn log_io_error() {
+    let client = Arc::new(InMemoryClient::new());
+    let logger = JournalLog::new_with_transport(client.clone()).unwrap();
+    let error = std::fs::read("/does-not-exist").unwrap_err();
+    logger
+        .log_io_error(Level::Warn, "Failed to read config", &error)
+        .unwrap();
+    let error = std::io::Error::new(std::io::ErrorKind::Other, "custom error");
+    logger
+        .log_io_error(Level::Error, "Failed to parse config", &error)
+        .unwrap();
+
+    let entries = client.entries();
+    assert_eq!(
+        entries[0]["MESSAGE"],
+        format!(
+            "Failed to read config: {}",
+            std::io::Error::from_raw_os_error(2)
+        )
+    );
+    assert_eq!(entries[0]["PRIORITY"], "4");
+    assert_eq!(entries[0]["ERRNO"], "2");
+    assert_eq!(
+        entries[1]["MESSAGE"],
+        "Failed to parse config: custom error"
+    );
+    assert!(!entries[1].contains_key("ERRNO"));
+}
+
+#[test]
+fn skip_empty_values() {
+    let client = Arc::new(InMemoryClient::new());
+    let logger = JournalLog::new_with_transport(client.clone())
+        .unwrap()
+        .with_skip_empty_values(true)
+        .add_extra_field("EMPTY", "")
+        .add_extra_field("REGION", "eu");
+    logger.log(&Record::builder().args(format_args!("Hello")).build());
+
+    let entry = &client.entries()[0];
+    assert!(!entry.contains_key("EMPTY"));
+    assert_eq!(entry["REGION"], "eu");
+}
+
+#[test]
+fn capture() {
+    let capture = JournalLog::capture();
+    log::info!(target: "capture", "Outer");
+    let inner = JournalLog::capture();
+    log::warn!(target: "capture", "Inner");
+    std::thread::spawn(|| log::error!(target: "capture", "Other thread"))
+        .join()
+        .unwrap();
+    let inner = inner.finish();
+    assert_eq!(inner.len(), 1);
+    assert_eq!(inner[0]["MESSAGE"], "Inner");
+    assert_eq!(inner[0]["PRIORITY"], "4");
+    log::debug!(target: "capture", "Outer again");
+    let entries = capture.finish();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0]["MESSAGE"], "Outer");
+    assert_eq!(entries[1]["MESSAGE"], "Outer again");
+    assert_eq!(entries[1]["TARGET"], "capture");
+}
+
+#[test]
+fn capture_with_buffer() {
+    let logger = JournalLog::empty()
+        .unwrap()
+        .with_extra_fields([("SERVICE", "web")]);
+    let capture = JournalLog::capture();
+    let mut buffer = [0; 4096];
+    logger
+        .journal_send_with_buffer(
+            &mut buffer,
+            &Record::builder()
+                .level(Level::Info)
+                .target("capture")
+                .args(format_args!("With buffer"))
+                .build(),
+        )
+        .unwrap();
+    let entries = capture.finish();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0]["MESSAGE"], "With buffer");
+    assert_eq!(entries[0]["SERVICE"], "web");
+}
+
+#[test]
+fn extra_fields_sorted() {
+    let fields = std::collections::HashMap::from([
+        ("zzz", "last"),
+        ("SERVICE", "web"),
+        ("FOO", "b"),
+        ("foo", "a"),
+        ("ABC", "first"),
+    ]);
+    let logger = JournalLog::new_with_transport(Arc::new(InMemoryClient::new()))
+        .unwrap()
+        .with_extra_fields_sorted(fields);
+    let payload = logger.format_record(&Record::builder().args(format_args!("")).build());
+    let payload = String::from_utf8(payload).unwrap();
+    assert!(
+        payload.ends_with("ABC=first\nFOO=a\nFOO=b\nSERVICE=web\nZZZ=last\n"),
+        "{}",
+        payload
+    );
+}
+
+#[test]
+fn max_extra_fields_bytes() {
+    let logger = JournalLog::new_with_transport(Arc::new(InMemoryClient::new()))
+        .unwrap()
+        .with_max_extra_fields_bytes(16)
+        .with_extra_fields(vec![("FOO", "bar"), ("HUGE", "x".repeat(64).as_str())])
+        .add_extra_field("A", "1")
+        .add_raw_fields(b"B=2\nC=3\n")
+        .unwrap();
+    let payload = logger.format_record(&Record::builder().args(format_args!("")).build());
+    let payload = String::from_utf8(payload).unwrap();
+    assert!(payload.ends_with("\nFOO=bar\nA=1\n"), "{}", payload);
+}
+
+#[test]
+fn set_extra_field() {
+    let client = Arc::new(InMemoryClient::new());
+    let logger = JournalLog::new_with_transport(client.clone())
+        .unwrap()
+        .with_max_extra_fields_bytes(40)
+        .with_extra_fields(vec![("SESSION_ID", "1"), ("SERVICE", "web")]);
+    logger.log(&Record::builder().args(format_args!("First")).build());
+    logger.set_extra_field("SESSION_ID", "2").unwrap();
+    logger.set_extra_field("TOKEN", "abc").unwrap();
+    logger.log(&Record::builder().args(format_args!("Second")).build());
+    assert!(logger.set_extra_field("token", "abc").is_err());
+    logger.set_extra_field("TOKEN", "x".repeat(32)).unwrap();
+
+    let entries = client.entries();
+    assert_eq!(entries[0]["SESSION_ID"], "1");
+    assert_eq!(entries[1]["SESSION_ID"], "2");
+    assert_eq!(entries[1]["SERVICE"], "web");
+    assert_eq!(entries[1]["TOKEN"], "abc");
+    assert_eq!(
+        logger.extra_fields(),
+        vec![
+            ("SESSION_ID".to_string(), b"2".to_vec()),
+            ("SERVICE".to_string(), b"web".to_vec()),
+        ]
+    );
+}
+
+/// A transport which blocks every send until released.
+struct BlockingTransport {
+    client: InMemoryClient,
+    entered: Mutex<Sender<()>>,
+    release: Mutex<Receiver<()>>,
+}
+
+impl JournalTransport for BlockingTransport {
+    fn send_payload(&self, payload: &[u8]) -> std::io::Result<usize> {
+        self.entered.lock().unwrap().send(()).unwrap();
+        self.release.lock().unwrap().recv().unwrap();
+        self.client.send_payload(payload)
+    }
+}
+
+#[test]
+fn set_extra_field_while_sending() {
+    let (entered, wait_entered) = channel();
+    let (release, wait_release) = channel();
+    let transport = Arc::new(BlockingTransport {
+        client: InMemoryClient::new(),
+        entered: Mutex::new(entered),
+        release: Mutex::new(wait_release),
+    });
+    let logger = JournalLog::new_with_transport(transport.clone())
+        .unwrap()
+        .with_extra_fields(vec![("SESSION_ID", "1")]);
+    std::thread::scope(|scope| {
+        let logger = &logger;
+        scope.spawn(|| logger.log(&Record::builder().args(format_args!("First")).build()));
+        wait_entered.recv().unwrap();
+        // Sending must not block writers of extra fields
+        let (updated, wait_updated) = channel();
+        scope.spawn(move || {
+            logger.set_extra_field("SESSION_ID", "2").unwrap();
+            updated.send(()).unwrap();
+        });
+        let result = wait_updated.recv_timeout(Duration::from_secs(5));
+        release.send(()).unwrap();
+        result.unwrap();
+    });
+    release.send(()).unwrap();
+    logger.log(&Record::builder().args(format_args!("Second")).build());
+
+    let entries = transport.client.entries();
+    // The record sent while updating keeps the extra fields it started with
+    assert_eq!(entries[0]["SESSION_ID"], "1");
+    assert_eq!(entries[1]["SESSION_ID"], "2");
+}
+
+#[test]
+fn priority_of_levels() {
+    let client = Arc::new(InMemoryClient::new());
+    let logger = JournalLog::new_with_transport(client.clone()).unwrap();
+    for level in Level::iter() {
+        logger.log(
+            &Record::builder()
+                .level(level)
+                .args(format_args!(""))
+                .build(),
+        );
+    }
+    let entries = client.entries();
+    assert_eq!(entries.len(), 5);
+    for (level, entry) in Level::iter().zip(entries) {
+        assert_eq!(entry["PRIORITY"], priority_for_level(level).to_string());
+    }
+    assert_eq!(priority_for_level(Level::Info), 5);
+}
+
+#[test]
+fn record_filter() {
+    let client = Arc::new(InMemoryClient::new());
+    let logger = JournalLog::new_with_transport(client.clone())
+        .unwrap()
+        .with_record_filter(Box::new(|metadata: &Metadata, record: Option<&Record>| {
+            metadata.target() != "noisy"
+                && record.map_or(true, |record| !record.args().to_string().contains("health"))
+        }));
+    assert!(!logger.enabled(&Metadata::builder().target("noisy").build()));
+    assert!(logger.enabled(&Metadata::builder().target("app").build()));
+    for (target, message) in [
+        ("noisy", "Dropped by target"),
+        ("app", "GET /health"),
+        ("app", "Kept"),
+    ] {
+        logger.log(
+            &Record::builder()
+                .target(target)
+                .args(format_args!("{}", message))
+                .build(),
+        );
+    }
+    let entries = client.entries();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0]["MESSAGE"], "Kept");
+
+    let client = Arc::new(InMemoryClient::new());
+    let logger = JournalLog::new_with_transport(client.clone())
+        .unwrap()
+        .with_record_filter(Box::new(LevelFilter::Info));
+    assert!(!logger.enabled(&Metadata::builder().level(Level::Debug).build()));
+    assert!(logger.enabled(&Metadata::builder().level(Level::Info).build()));
+}
+
+#[test]
+fn sequence_numbers_without_overflow() {
+    let client = Arc::new(InMemoryClient::new());
+    let logger = JournalLog::new_with_transport(client.clone())
+        .unwrap()
+        .with_sequence_field("SEQ");
+    let record = Record::builder().args(format_args!("Hello World")).build();
+    assert!(logger.format_record_into(&mut [0; 8], &record).is_err());
+    let error = logger
+        .journal_send_with_buffer(&mut [0; 8], &record)
+        .unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+    for _ in 0..2 {
+        logger
+            .journal_send_with_buffer(&mut [0; 1024], &record)
+            .unwrap();
+    }
+
+    // Overflowing buffers don't take sequence numbers
+    let sequence = client
+        .entries()
+        .iter()
+        .map(|entry| entry["SEQ"].to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(sequence, vec!["1", "2"]);
+}
+
+#[test]
+fn drop_summary_without_kept_records() {
+    let client = Arc::new(InMemoryClient::new());
+    let logger = JournalLog::new_with_transport(client.clone())
+        .unwrap()
+        .with_syslog_identifier("drop_summary".to_string())
+        .with_sampling(Level::Debug, 0.0)
+        .with_drop_summary_interval(Duration::from_millis(10));
+    for _ in 0..3 {
+        logger.log(&Record::builder().level(Level::Debug).build());
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while client.payloads().is_empty() && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let entries = client.entries();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(
+        entries[0]["MESSAGE_ID"],
+        DROP_SUMMARY_MESSAGE_ID.to_string()
+    );
+    assert_eq!(entries[0]["PRIORITY"], "4");
+    assert_eq!(entries[0]["SYSLOG_IDENTIFIER"], "drop_summary");
+    assert_eq!(entries[0]["DROPPED_SAMPLED"], "3");
+    assert_eq!(entries[0]["DROPPED_WOULD_BLOCK"], "0");
+    assert_eq!(entries[0]["DROPPED_PAUSED"], "0");
+}
+
+#[test]
+fn flatten_structured() {
+    #[derive(serde::Serialize)]
+    struct User {
+        id: u64,
+        name: &'static str,
+        roles: [&'static str; 2],
+    }
+
+    let client = Arc::new(InMemoryClient::new());
+    let logger = JournalLog::new_with_transport(client.clone())
+        .unwrap()
+        .with_flatten_structured(true);
+    let user = User {
+        id: 5,
+        name: "x",
+        roles: ["admin", "dev"],
+    };
+    let kvs = [("user", Value::from_serde(&user))];
+    logger.log(
+        &Record::builder()
+            .args(format_args!("User logged in"))
+            .key_values(&kvs)
+            .build(),
+    );
+
+    let entries = client.entries();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0]["MESSAGE"], "User logged in");
+    assert_eq!(entries[0]["USER_ID"], "5");
+    assert_eq!(entries[0]["USER_NAME"], "x");
+    assert_eq!(entries[0]["USER_ROLES_0"], "admin");
+    assert_eq!(entries[0]["USER_ROLES_1"], "dev");
+    assert!(!entries[0].contains_key("USER"));
+}
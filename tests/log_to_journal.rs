@@ -11,11 +11,18 @@
 
 #![deny(warnings, clippy::all)]
 
+use std::os::fd::OwnedFd;
+use std::os::unix::net::UnixDatagram;
+use std::time::{Duration, UNIX_EPOCH};
+
 use log::kv::Value;
 use log::{Level, Log, Record};
 use similar_asserts::assert_eq;
 
-use systemd_journal_logger::JournalLog;
+use systemd_journal_logger::testing::parse_export;
+use systemd_journal_logger::{
+    JournalLog, LargePayloadStrategy, MessageId, DROP_SUMMARY_MESSAGE_ID, SYSTEM_JOURNAL_SOCKET,
+};
 
 mod journal;
 
@@ -129,6 +136,71 @@ fn very_large_message() {
     assert_eq!(entry["MESSAGE"].as_text(), very_large_string);
 }
 
+#[test]
+fn memfd_large_payload() {
+    // Force the memfd path with a low threshold, even for a message which
+    // would fit into a datagram.
+    let logger = JournalLog::new()
+        .unwrap()
+        .with_large_payload_strategy(LargePayloadStrategy::Memfd)
+        .with_large_payload_threshold(1024);
+    let message = "memfd\nwith ümlauts\n".repeat(100);
+    logger.log(
+        &Record::builder()
+            .level(Level::Info)
+            .target("memfd_large_payload")
+            .args(format_args!("{}", message))
+            .build(),
+    );
+    assert_eq!(logger.large_payload_records(), 1);
+
+    let entry = journal::read_one_entry("memfd_large_payload");
+    assert_eq!(entry["_TRANSPORT"], "journal");
+    assert_eq!(entry["MESSAGE"].as_text(), message);
+}
+
+#[test]
+fn socket_fd() {
+    let socket = UnixDatagram::unbound().unwrap();
+    socket.connect(SYSTEM_JOURNAL_SOCKET).unwrap();
+    JournalLog::new_with_socket_fd(OwnedFd::from(socket))
+        .unwrap()
+        .log(
+            &Record::builder()
+                .level(Level::Warn)
+                .target("socket_fd")
+                .args(format_args!("Through an inherited socket"))
+                .build(),
+        );
+
+    let entry = journal::read_one_entry("socket_fd");
+    assert_eq!(entry["PRIORITY"], "4");
+    assert_eq!(entry["MESSAGE"], "Through an inherited socket");
+}
+
+#[test]
+fn native_transport() {
+    // Send one small payload as datagram, and one large payload which exceeds
+    // the datagram size limit through a memfd, and check that journald
+    // receives both through its native protocol.
+    for (target, message) in [
+        ("native_transport_datagram", "small".to_string()),
+        ("native_transport_memfd", "b".repeat(512_000)),
+    ] {
+        JournalLog::new().unwrap().log(
+            &Record::builder()
+                .level(Level::Info)
+                .target(target)
+                .args(format_args!("{}", message))
+                .build(),
+        );
+
+        let entry = journal::read_one_entry(target);
+        assert_eq!(entry["_TRANSPORT"], "journal", "{}", target);
+        assert_eq!(entry["MESSAGE"].as_text(), message);
+    }
+}
+
 #[test]
 fn extra_fields() {
     JournalLog::new()
@@ -226,3 +298,599 @@ fn duplicate_fields() {
     // since we append extra fields of the logger at the very end.
     assert_eq!(entry["FOO"], vec!["record foo", "logger foo"]);
 }
+
+#[test]
+fn background_sender() {
+    let logger = JournalLog::new()
+        .unwrap()
+        .with_background_sender(16)
+        .unwrap();
+    logger.log(
+        &Record::builder()
+            .level(Level::Info)
+            .target("background_sender")
+            .args(format_args!("sent from a background thread"))
+            .build(),
+    );
+    logger.flush();
+
+    let entry = journal::read_one_entry("background_sender");
+    assert_eq!(entry["PRIORITY"], "5");
+    assert_eq!(entry["MESSAGE"], "sent from a background thread");
+    assert_eq!(logger.dropped_records(), 0);
+}
+
+#[test]
+fn format_record() {
+    let kvs: &[(&str, Value)] = &[("spam", Value::from("eggs"))];
+    let payload = JournalLog::empty()
+        .unwrap()
+        .with_syslog_identifier("foo".to_string())
+        .with_extra_fields(vec![("VERSION", "42")])
+        .format_record(
+            &Record::builder()
+                .level(Level::Info)
+                .target("format_record")
+                .line(Some(12))
+                .args(format_args!("Hello {}", "world"))
+                .key_values(&kvs)
+                .build(),
+        );
+
+    let mut expected = b"PRIORITY=5\nMESSAGE\n\x0b\0\0\0\0\0\0\0Hello world\n".to_vec();
+    expected.extend_from_slice(
+        format!(
+            "SYSLOG_PID={}\nSYSLOG_IDENTIFIER=foo\nCODE_LINE=12\nTARGET=format_record\n",
+            std::process::id()
+        )
+        .as_bytes(),
+    );
+    expected.extend_from_slice(b"SPAM\n\x04\0\0\0\0\0\0\0eggs\nVERSION=42\n");
+    assert_eq!(payload, expected);
+}
+
+#[test]
+fn format_record_skip_extra_fields() {
+    let logger = JournalLog::empty()
+        .unwrap()
+        .with_extra_fields(vec![("DEPLOYMENT", "a verbose blob")]);
+    let kvs: &[(&str, Value)] = &[
+        ("spam", Value::from("eggs")),
+        ("__skip_extra_fields", Value::from(true)),
+    ];
+    let payload = logger.format_record(
+        &Record::builder()
+            .level(Level::Debug)
+            .args(format_args!("chatty"))
+            .key_values(&kvs)
+            .build(),
+    );
+    assert!(payload.ends_with(b"SPAM\n\x04\0\0\0\0\0\0\0eggs\n"));
+
+    let kvs: &[(&str, Value)] = &[("__skip_extra_fields", Value::from(false))];
+    let payload = logger.format_record(
+        &Record::builder()
+            .level(Level::Debug)
+            .args(format_args!("chatty"))
+            .key_values(&kvs)
+            .build(),
+    );
+    assert!(payload.ends_with(b"TARGET=\nDEPLOYMENT=a verbose blob\n"));
+}
+
+#[test]
+fn write_export() {
+    let logger = JournalLog::empty()
+        .unwrap()
+        .with_extra_fields(vec![("VERSION", "42")]);
+    let mut export = Vec::new();
+    let mut expected = Vec::new();
+    for message in [format_args!("first"), format_args!("second")] {
+        let record = Record::builder()
+            .level(Level::Info)
+            .target("write_export")
+            .args(message)
+            .build();
+        logger.write_export(&mut export, &record).unwrap();
+        expected.extend_from_slice(&logger.format_record(&record));
+        expected.push(b'\n');
+    }
+    assert_eq!(export, expected);
+    assert!(export.ends_with(b"VERSION=42\n\n"));
+}
+
+#[test]
+fn write_and_parse_export() {
+    let logger = JournalLog::empty().unwrap().with_extra_fields(vec![
+        ("VERSION", "42"),
+        ("TAG", "a"),
+        ("TAG", "b"),
+    ]);
+    let mut export = Vec::new();
+    for message in [format_args!("single line"), format_args!("multiple\nlines")] {
+        logger
+            .write_export(
+                &mut export,
+                &Record::builder()
+                    .level(Level::Warn)
+                    .target("write_and_parse_export")
+                    .args(message)
+                    .build(),
+            )
+            .unwrap();
+    }
+
+    let entries = parse_export(&export);
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0]["MESSAGE"], "single line");
+    assert_eq!(entries[1]["MESSAGE"], "multiple\nlines");
+    for entry in &entries {
+        assert_eq!(entry["PRIORITY"], "4");
+        assert_eq!(entry["TARGET"], "write_and_parse_export");
+        assert_eq!(entry["VERSION"], "42");
+        assert_eq!(entry["TAG"], vec!["a", "b"]);
+    }
+}
+
+#[test]
+fn new_lenient() {
+    JournalLog::new_lenient().unwrap().log(
+        &Record::builder()
+            .level(Level::Info)
+            .target("new_lenient")
+            .args(format_args!("from a lenient logger"))
+            .build(),
+    );
+
+    let entry = journal::read_one_entry("new_lenient");
+    assert_eq!(entry["MESSAGE"], "from a lenient logger");
+}
+
+#[test]
+fn journal_log_macro() {
+    systemd_journal_logger::journal_log!().unwrap().log(
+        &Record::builder()
+            .level(Level::Info)
+            .target("journal_log_macro")
+            .args(format_args!("with the package name as identifier"))
+            .build(),
+    );
+
+    let entry = journal::read_one_entry("journal_log_macro");
+    assert_eq!(entry["SYSLOG_IDENTIFIER"], env!("CARGO_PKG_NAME"));
+}
+
+#[test]
+fn new_with_startup_probe() {
+    JournalLog::new_with_startup_probe(true).unwrap().log(
+        &Record::builder()
+            .level(Level::Info)
+            .target("new_with_startup_probe")
+            .args(format_args!("after probing journald"))
+            .build(),
+    );
+
+    let entry = journal::read_one_entry("new_with_startup_probe");
+    assert_eq!(entry["MESSAGE"], "after probing journald");
+}
+
+#[test]
+fn log_event() {
+    const EVENT: MessageId = MessageId::from_u128(0x39f53479d3a045ac8e11786248231fbf);
+    JournalLog::new()
+        .unwrap()
+        .log_event(EVENT, Level::Warn, format_args!("an event"))
+        .unwrap();
+
+    let entry = systemd_journal_logger::testing::read_one_entry(
+        journal::Journal::User,
+        &[
+            ("_PID", &std::process::id().to_string()),
+            ("MESSAGE_ID", "39f53479d3a045ac8e11786248231fbf"),
+        ],
+    )
+    .unwrap();
+    assert!(!entry.contains_key("TARGET"));
+    assert_eq!(entry["PRIORITY"], "4");
+    assert_eq!(entry["MESSAGE"], "an event");
+    assert_eq!(entry["MESSAGE_ID"], "39f53479d3a045ac8e11786248231fbf");
+}
+
+#[test]
+fn with_sequence_field() {
+    let logger = JournalLog::new().unwrap().with_sequence_field("seqno");
+    for target in ["with_sequence_field_1", "with_sequence_field_2"] {
+        logger.log(
+            &Record::builder()
+                .level(Level::Info)
+                .target(target)
+                .args(format_args!("numbered entry"))
+                .build(),
+        );
+    }
+
+    assert_eq!(
+        journal::read_one_entry("with_sequence_field_1")["SEQNO"],
+        "1"
+    );
+    assert_eq!(
+        journal::read_one_entry("with_sequence_field_2")["SEQNO"],
+        "2"
+    );
+}
+
+#[test]
+fn background_sender_shutdown() {
+    let logger = JournalLog::new()
+        .unwrap()
+        .with_background_sender(16)
+        .unwrap();
+    logger.log(
+        &Record::builder()
+            .level(Level::Info)
+            .target("background_sender_shutdown")
+            .args(format_args!("sent before shutdown"))
+            .build(),
+    );
+    assert!(logger.shutdown());
+
+    let entry = journal::read_one_entry("background_sender_shutdown");
+    assert_eq!(entry["MESSAGE"], "sent before shutdown");
+}
+
+#[test]
+fn extra_fields_from_env() {
+    std::env::set_var("SYSTEMD_JOURNAL_LOGGER_TEST_FOO", "foo from env");
+    JournalLog::new()
+        .unwrap()
+        .with_extra_fields_from_env(&[
+            ("FOO", "SYSTEMD_JOURNAL_LOGGER_TEST_FOO"),
+            ("BAR", "SYSTEMD_JOURNAL_LOGGER_TEST_UNSET"),
+        ])
+        .log(
+            &Record::builder()
+                .level(Level::Info)
+                .target("extra_fields_from_env")
+                .args(format_args!("with fields from env"))
+                .build(),
+        );
+
+    let entry = journal::read_one_entry("extra_fields_from_env");
+    assert_eq!(entry["FOO"], "foo from env");
+    assert!(!entry.contains_key("BAR"));
+}
+
+#[test]
+fn target_field_name() {
+    let logger = JournalLog::empty()
+        .unwrap()
+        .with_target_field_name(Some("LOGGER".to_string()))
+        .unwrap();
+    let payload = logger.format_record(
+        &Record::builder()
+            .target("target_field_name")
+            .args(format_args!("Hello"))
+            .build(),
+    );
+    assert!(String::from_utf8_lossy(&payload).ends_with("\nLOGGER=target_field_name\n"));
+
+    let payload = logger.with_target_field_name(None).unwrap().format_record(
+        &Record::builder()
+            .target("target_field_name")
+            .args(format_args!("Hello"))
+            .build(),
+    );
+    assert!(!String::from_utf8_lossy(&payload).contains("target_field_name"));
+
+    assert!(JournalLog::empty()
+        .unwrap()
+        .with_target_field_name(Some("logger".to_string()))
+        .is_err());
+}
+
+#[test]
+fn target_as_field() {
+    JournalLog::new()
+        .unwrap()
+        .with_target_as_field("component")
+        .log(
+            &Record::builder()
+                .level(Level::Info)
+                .target("target_as_field")
+                .args(format_args!("from a component"))
+                .build(),
+        );
+
+    let entry = journal::read_one_entry("target_as_field");
+    assert_eq!(entry["COMPONENT"], "target_as_field");
+}
+
+#[test]
+fn send_fields() {
+    JournalLog::new()
+        .unwrap()
+        .send_fields(&[
+            ("TARGET", b"send_fields"),
+            ("audit_user", b"jdoe"),
+            ("MULTILINE", b"first\nsecond"),
+        ])
+        .unwrap();
+
+    let entry = journal::read_one_entry("send_fields");
+    assert_eq!(entry["PRIORITY"], "6");
+    assert_eq!(entry["MESSAGE"], "");
+    assert_eq!(entry["AUDIT_USER"], "jdoe");
+    assert_eq!(entry["MULTILINE"], "first\nsecond");
+}
+
+#[test]
+fn pause_and_resume() {
+    let logger = JournalLog::new().unwrap();
+    let record = |message| {
+        logger.log(
+            &Record::builder()
+                .level(Level::Info)
+                .target("pause_and_resume")
+                .args(format_args!("{}", message))
+                .build(),
+        )
+    };
+
+    logger.pause();
+    assert!(logger.is_paused());
+    record("while paused");
+    assert_eq!(logger.paused_records(), 1);
+    logger.resume();
+    assert!(!logger.is_paused());
+    record("after resume");
+
+    let entry = journal::read_one_entry("pause_and_resume");
+    assert_eq!(entry["MESSAGE"], "after resume");
+}
+
+#[test]
+fn boot_id_and_machine_id_fields() {
+    JournalLog::new()
+        .unwrap()
+        .with_boot_id_field("BOOT_ID")
+        .with_machine_id_field("MACHINE_ID")
+        .log(
+            &Record::builder()
+                .level(Level::Info)
+                .target("boot_id_and_machine_id_fields")
+                .args(format_args!("with boot and machine ID"))
+                .build(),
+        );
+
+    let entry = journal::read_one_entry("boot_id_and_machine_id_fields");
+    assert_eq!(entry["BOOT_ID"], entry["_BOOT_ID"]);
+    assert_eq!(entry["MACHINE_ID"], entry["_MACHINE_ID"]);
+}
+
+#[test]
+fn send_with_timestamp() {
+    let timestamp = UNIX_EPOCH + Duration::from_micros(1_792_144_282_123_456);
+    JournalLog::new()
+        .unwrap()
+        .send_with_timestamp(
+            timestamp,
+            &Record::builder()
+                .level(Level::Info)
+                .target("send_with_timestamp")
+                .args(format_args!("replayed"))
+                .build(),
+        )
+        .unwrap();
+
+    let entry = journal::read_one_entry("send_with_timestamp");
+    assert_eq!(entry["SOURCE_REALTIME_TIMESTAMP"], "1792144282123456");
+    assert_eq!(entry["SYSLOG_TIMESTAMP"], "Oct 16 09:51:22");
+}
+
+#[test]
+fn source_realtime_timestamp_key() {
+    let logger = JournalLog::new().unwrap();
+    // Replay two events against wall clock order
+    let later = 1_792_144_282_123_456_u64;
+    let earlier = later - 60_000_000;
+    for (timestamp, message) in [(later, "second"), (earlier, "first")] {
+        let kvs = [("source_realtime_timestamp", timestamp)];
+        logger
+            .journal_send(
+                &Record::builder()
+                    .level(Level::Info)
+                    .target("source_realtime_timestamp_key")
+                    .args(format_args!("{}", message))
+                    .key_values(&kvs)
+                    .build(),
+            )
+            .unwrap();
+    }
+
+    let mut entries = Vec::new();
+    for _ in 0..30 {
+        entries = journal::read_current_process("source_realtime_timestamp_key");
+        if entries.len() == 2 {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    assert_eq!(entries.len(), 2);
+    // journald keeps the order of receipt, but the source timestamp orders
+    // entries by event time
+    assert_eq!(entries[0]["MESSAGE"], "second");
+    entries.sort_by_key(|entry| {
+        entry["SOURCE_REALTIME_TIMESTAMP"]
+            .as_text()
+            .parse::<u64>()
+            .unwrap()
+    });
+    let messages = entries
+        .iter()
+        .map(|entry| entry["MESSAGE"].to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(messages, vec!["first", "second"]);
+    assert_eq!(entries[0]["SYSLOG_TIMESTAMP"], "Oct 16 09:50:22");
+}
+
+#[test]
+fn container_name() {
+    JournalLog::new()
+        .unwrap()
+        .with_container_name("my-container".to_string())
+        .log(
+            &Record::builder()
+                .level(Level::Info)
+                .target("container_name")
+                .args(format_args!("from a container"))
+                .build(),
+        );
+
+    let entry = journal::read_one_entry("container_name");
+    assert_eq!(entry["CONTAINER_NAME"], "my-container");
+}
+
+#[test]
+fn new_sharing_socket() {
+    let app = JournalLog::new().unwrap();
+    let audit = app
+        .new_sharing_socket()
+        .with_extra_fields(vec![("AUDIT", "1")]);
+    audit.log(
+        &Record::builder()
+            .level(Level::Info)
+            .target("new_sharing_socket")
+            .args(format_args!("audit event"))
+            .build(),
+    );
+
+    let entry = journal::read_one_entry("new_sharing_socket");
+    assert_eq!(entry["MESSAGE"], "audit event");
+    assert_eq!(entry["AUDIT"], "1");
+}
+
+#[test]
+fn journal_send_with_buffer() {
+    let logger = JournalLog::new()
+        .unwrap()
+        .with_extra_fields(vec![("EXTRA_FIELD", "foo")]);
+    let record = Record::builder()
+        .level(Level::Warn)
+        .target("journal_send_with_buffer")
+        .args(format_args!("from a buffer"))
+        .build();
+
+    let error = logger
+        .journal_send_with_buffer(&mut [0; 16], &record)
+        .unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+
+    logger
+        .journal_send_with_buffer(&mut [0; 1024], &record)
+        .unwrap();
+    let entry = journal::read_one_entry("journal_send_with_buffer");
+    assert_eq!(entry["MESSAGE"], "from a buffer");
+    assert_eq!(entry["PRIORITY"], "4");
+    assert_eq!(entry["EXTRA_FIELD"], "foo");
+}
+
+#[test]
+fn drop_summary() {
+    let logger = JournalLog::new()
+        .unwrap()
+        .with_sampling(Level::Debug, 0.0)
+        .with_drop_summary_interval(Duration::from_millis(50));
+    for _ in 0..3 {
+        logger
+            .log_record(
+                &Record::builder()
+                    .level(Level::Debug)
+                    .target("drop_summary")
+                    .args(format_args!("sampled out"))
+                    .build(),
+            )
+            .unwrap();
+    }
+
+    // The logger summarizes dropped records even if it dropped every record
+    let entry = journal::read_one_entry("systemd_journal_logger");
+    assert_eq!(entry["MESSAGE_ID"], DROP_SUMMARY_MESSAGE_ID.to_string());
+    assert_eq!(entry["PRIORITY"], "4");
+    assert_eq!(entry["DROPPED_SAMPLED"], "3");
+    assert_eq!(entry["DROPPED_WOULD_BLOCK"], "0");
+    assert_eq!(entry["DROPPED_PAUSED"], "0");
+}
+
+#[test]
+fn new_with_socket_path() {
+    let error = JournalLog::new_with_socket_path("/run/systemd/journal.does-not-exist/socket")
+        .err()
+        .unwrap();
+    assert_eq!(error.kind(), std::io::ErrorKind::NotFound);
+    assert_eq!(
+        JournalLog::new_for_namespace("does-not-exist")
+            .err()
+            .unwrap()
+            .kind(),
+        std::io::ErrorKind::NotFound
+    );
+    assert_eq!(
+        JournalLog::new_for_namespace("../journal")
+            .err()
+            .unwrap()
+            .kind(),
+        std::io::ErrorKind::InvalidInput
+    );
+
+    JournalLog::new_with_socket_path(SYSTEM_JOURNAL_SOCKET)
+        .unwrap()
+        .log_record(
+            &Record::builder()
+                .level(Level::Info)
+                .target("new_with_socket_path")
+                .args(format_args!("to the system socket"))
+                .build(),
+        )
+        .unwrap();
+    let entry = journal::read_one_entry("new_with_socket_path");
+    assert_eq!(entry["MESSAGE"], "to the system socket");
+}
+
+#[test]
+fn with_clock() {
+    let record = Record::builder()
+        .level(Level::Info)
+        .args(format_args!("Hello"))
+        .build();
+    let payload = JournalLog::empty()
+        .unwrap()
+        .with_clock(|| UNIX_EPOCH + Duration::from_micros(1_792_144_282_123_456))
+        .format_record(&record);
+    assert!(payload.ends_with(
+        b"TARGET=\nSOURCE_REALTIME_TIMESTAMP=1792144282123456\nSYSLOG_TIMESTAMP=Oct 16 09:51:22\n"
+    ));
+
+    // Omit timestamps before the epoch
+    let payload = JournalLog::empty()
+        .unwrap()
+        .with_clock(|| UNIX_EPOCH - Duration::from_secs(1))
+        .format_record(&record);
+    assert!(payload.ends_with(b"TARGET=\n"));
+}
+
+#[test]
+fn logger_tag() {
+    JournalLog::new()
+        .unwrap()
+        .with_logger_tag("log".to_string())
+        .log(
+            &Record::builder()
+                .level(Level::Info)
+                .target("logger_tag")
+                .args(format_args!("from the log facade"))
+                .build(),
+        );
+
+    let entry = journal::read_one_entry("logger_tag");
+    assert_eq!(entry["LOGGER"], "log");
+}
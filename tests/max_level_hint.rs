@@ -0,0 +1,40 @@
+// Copyright Sebastian Wiesner <sebastian@swsnr.de>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Test the hint about a global max level of `Off`.
+//!
+//! This test installs a global logger, so it needs a process of its own.
+
+#![deny(warnings, clippy::all)]
+
+use std::sync::Arc;
+
+use similar_asserts::assert_eq;
+use systemd_journal_logger::testing::InMemoryClient;
+use systemd_journal_logger::JournalLog;
+
+#[test]
+fn warn_once_if_max_level_off() {
+    let client = Arc::new(InMemoryClient::new());
+    JournalLog::new_with_transport(client.clone())
+        .unwrap()
+        .with_max_level_hint(true)
+        .install()
+        .unwrap();
+    log::info!("Discarded");
+    log::logger().flush();
+    log::logger().flush();
+
+    let entries = client.entries();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0]["PRIORITY"], "4");
+    assert_eq!(
+        entries[0]["MESSAGE"],
+        "Journal logger installed, but the max level is Off; call log::set_max_level to log records"
+    );
+}